@@ -0,0 +1,42 @@
+/* C interface of the permanent estimator, built with `--features ffi`.
+ *
+ * The caller owns every buffer: the matrix is only read during the call and
+ * the result is written to `out_log`. Nothing is allocated for the caller. */
+
+#ifndef PERMANENT_H
+#define PERMANENT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* the estimate was written to `out_log` */
+#define PERMANENT_OK 0
+/* `matrix` or `out_log` is null */
+#define PERMANENT_NULL_POINTER 1
+/* `n` or `chains` is zero, or an entry is negative or not finite */
+#define PERMANENT_INVALID_ARGUMENT 2
+/* the matrix has no perfect matching, so its permanent is zero */
+#define PERMANENT_NO_PERFECT_MATCHING 3
+/* the estimator failed internally */
+#define PERMANENT_INTERNAL_ERROR 4
+
+/* Estimate the permanent of the row-major `n x n` nonnegative `matrix` with
+ * `chains` chains seeded by `seed`, writing its natural logarithm to
+ * `out_log`. Returns one of the status codes above. */
+int32_t permanent_estimate(const double *matrix, size_t n, size_t chains,
+                           uint64_t seed, double *out_log);
+
+/* Like `permanent_estimate`, counting the perfect matchings of the 0/1
+ * `matrix`, of which every nonzero byte is an edge. */
+int32_t permanent_estimate_01(const uint8_t *matrix, size_t n, size_t chains,
+                              uint64_t seed, double *out_log);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PERMANENT_H */
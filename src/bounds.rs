@@ -0,0 +1,95 @@
+//! Analytic bounds on the permanent, cheap enough to sanity check an input
+//! before running the chains.
+
+use crate::{cooling_state::sinkhorn_scaling, graph::Graph, markov_chain::ln_factorial};
+
+/// Rounds of Sinkhorn scaling behind [`van_der_waerden_lower`].
+const SCALING_ITERATIONS: usize = 1000;
+
+/// Largest deviation of a row sum of the scaled matrix from one for which
+/// [`van_der_waerden_lower`] takes the scaling as doubly stochastic.
+const SCALING_TOLERANCE: f64 = 1e-9;
+
+/// Bregman-Minc upper bound `prod_u (r_u!)^(1 / r_u)` on the number of perfect
+/// matchings, `r_u` being the degree of row `u`. Every term of a weighted
+/// permanent picks one entry per row, so weighted graphs scale the factor of
+/// each row by its largest weight.
+pub fn bregman_minc_upper(graph: &Graph) -> f64 {
+    let mut log_bound = 0.0;
+    for (u, edges) in graph.edges.iter().enumerate() {
+        if edges.is_empty() {
+            return 0.0;
+        }
+        let max_weight = edges
+            .iter()
+            .map(|v| graph.edge_weight(u, *v))
+            .fold(0.0, f64::max);
+        log_bound += ln_factorial(edges.len()) / edges.len() as f64 + max_weight.ln();
+    }
+    log_bound.exp()
+}
+
+/// Van der Waerden lower bound `n! / n^n` on the permanent of a doubly
+/// stochastic matrix, carried over to the graph through the factors of its
+/// Sinkhorn scaling. Zero, the trivial bound, if the scaling does not converge,
+/// which is the case for graphs without total support.
+pub fn van_der_waerden_lower(graph: &Graph) -> f64 {
+    let n = graph.size;
+    let (rows, columns) = sinkhorn_scaling(graph, SCALING_ITERATIONS);
+    for (u, edges) in graph.edges.iter().enumerate() {
+        let sum = edges
+            .iter()
+            .map(|v| rows[u] * graph.edge_weight(u, *v) * columns[*v])
+            .sum::<f64>();
+        if (sum - 1.0).abs() > SCALING_TOLERANCE {
+            return 0.0;
+        }
+    }
+    // perm(A) = perm(D_r A D_c) / (prod r * prod c)
+    let log_scale = rows
+        .iter()
+        .chain(columns.iter())
+        .map(|x| x.ln())
+        .sum::<f64>();
+    (ln_factorial(n) - n as f64 * (n as f64).ln() - log_scale).exp()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bregman_minc_upper, van_der_waerden_lower};
+    use crate::{exact::graph_permanent, graph::Graph};
+    use std::path::PathBuf;
+
+    #[test]
+    fn complete_graph_bounds() {
+        for n in [1, 4, 8] {
+            let graph = Graph::complete(n);
+            let exact = (1..=n).product::<usize>() as f64;
+            // both are tight on complete graphs, which scale to J / n, the
+            // minimizer of van der Waerden's bound
+            assert!((bregman_minc_upper(&graph) - exact).abs() < 1e-9 * exact);
+            assert!((van_der_waerden_lower(&graph) - exact).abs() < 1e-9 * exact);
+        }
+    }
+
+    #[test]
+    fn bounds_enclose_exact() {
+        let path: PathBuf = env!("PWD").into();
+        for name in ["4-cycles.json", "box.json", "weighted.json", "choice.json"] {
+            let graph = Graph::load(path.join("data").join(name)).unwrap();
+            let exact = graph_permanent(&graph);
+            let (lower, upper) = (van_der_waerden_lower(&graph), bregman_minc_upper(&graph));
+            assert!(
+                lower <= exact * (1.0 + 1e-9) && exact <= upper * (1.0 + 1e-9),
+                "{}: {} not in [{}, {}]",
+                name,
+                exact,
+                lower,
+                upper
+            );
+        }
+        // box.json has no total support, so the scaling never converges
+        let graph = Graph::load(path.join("data").join("box.json")).unwrap();
+        assert_eq!(van_der_waerden_lower(&graph), 0.0);
+    }
+}
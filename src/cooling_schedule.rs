@@ -1,17 +1,105 @@
 use std::num::NonZeroUsize;
 
+/// A sequence of increasing betas to cool the chains along.
+///
+/// Every `f64` iterator is a schedule, so adapters such as `take_while` or
+/// `chain` on a schedule, or a materialized `Vec`'s `into_iter`, can be passed
+/// to [`crate::markov_chain::MCState::cooling_evolve`] directly.
+pub trait Schedule: Iterator<Item = f64> {}
+
+impl<T: Iterator<Item = f64>> Schedule for T {}
+
+/// `steps` betas `start * factor^k`.
+#[derive(Debug, Clone, Copy)]
+pub struct GeometricSchedule {
+    pub start: f64,
+    pub factor: f64,
+    pub steps: usize,
+}
+
+impl Iterator for GeometricSchedule {
+    type Item = f64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.steps == 0 {
+            return None;
+        }
+        let value = self.start;
+        self.start *= self.factor;
+        self.steps -= 1;
+        Some(value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.steps, Some(self.steps))
+    }
+}
+
+/// `steps` evenly spaced betas from `start` to `end`, both included (a single
+/// step yields `end`).
+#[derive(Debug, Clone, Copy)]
+pub struct LinearSchedule {
+    pub start: f64,
+    pub end: f64,
+    pub steps: usize,
+}
+
+impl Iterator for LinearSchedule {
+    type Item = f64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.steps {
+            0 => None,
+            1 => {
+                self.steps = 0;
+                Some(self.end)
+            }
+            _ => {
+                let value = self.start;
+                self.start += (self.end - self.start) / (self.steps - 1) as f64;
+                self.steps -= 1;
+                Some(value)
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.steps, Some(self.steps))
+    }
+}
+
+/// Shape of the two-phase cooling schedule of a graph with `n` vertices per
+/// side: additive steps of `1 / (n * additive_ratio)` up to `log2(n)`, then
+/// multiplicative steps of `1 + 1 / (n * log2(n) * multiplicative_ratio)`.
+#[derive(Debug, Clone, Copy)]
 pub struct CoolingConfig {
-    pub(crate) n: NonZeroUsize,
-    pub(crate) additive_ratio: NonZeroUsize,
-    pub(crate) multiplicative_ratio: NonZeroUsize,
+    pub n: NonZeroUsize,
+    pub additive_ratio: NonZeroUsize,
+    pub multiplicative_ratio: NonZeroUsize,
 }
 
 impl CoolingConfig {
+    pub fn new(
+        n: NonZeroUsize,
+        additive_ratio: NonZeroUsize,
+        multiplicative_ratio: NonZeroUsize,
+    ) -> Self {
+        CoolingConfig {
+            n,
+            additive_ratio,
+            multiplicative_ratio,
+        }
+    }
     fn log(&self) -> usize {
         self.n.ilog2() as usize + if self.n.is_power_of_two() { 0 } else { 1 }
     }
+    /// Number of multiplicative steps.
+    fn multiplicative_steps(&self) -> usize {
+        self.log() * self.log() * self.n.get() * self.multiplicative_ratio.get()
+    }
 }
 
+#[derive(Debug, Clone, Copy)]
 enum CoolingState {
     Additive {
         current: usize,
@@ -26,6 +114,8 @@ enum CoolingState {
     Infinite,
 }
 
+/// The sequence of betas of a [`CoolingConfig`], starting at zero.
+#[derive(Debug, Clone)]
 pub struct CoolingSchedule {
     config: CoolingConfig,
     state: CoolingState,
@@ -41,6 +131,22 @@ impl From<CoolingConfig> for CoolingSchedule {
     }
 }
 
+impl CoolingSchedule {
+    pub fn new(config: CoolingConfig) -> Self {
+        Self::from(config)
+    }
+    /// The remaining betas of the schedule, without consuming it.
+    pub fn betas(&self) -> Vec<f64> {
+        self.clone().collect()
+    }
+    /// Skip the schedule forward to the first beta that is at least `beta`.
+    /// All earlier entries are consumed together with the returned one, so the
+    /// next call to `next` yields the entry right after it.
+    pub fn fast_forward(&mut self, beta: f64) -> Option<f64> {
+        self.find(|x| *x >= beta)
+    }
+}
+
 impl Iterator for CoolingSchedule {
     type Item = f64;
 
@@ -51,8 +157,7 @@ impl Iterator for CoolingSchedule {
                     / (self.config.n.get() * self.config.additive_ratio.get()) as f64;
                 let state = if current == target {
                     let log = self.config.log();
-                    let target =
-                        log * log * self.config.n.get() * self.config.multiplicative_ratio.get();
+                    let target = self.config.multiplicative_steps();
                     let gamma = 1.0
                         + 1.0
                             / (self.config.n.get() * log * self.config.multiplicative_ratio.get())
@@ -94,12 +199,56 @@ impl Iterator for CoolingSchedule {
         self.state = state;
         value
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = match self.state {
+            CoolingState::Additive { current, target } => {
+                target - current + 1 + self.config.multiplicative_steps()
+            }
+            CoolingState::Multiplicative { times, target, .. } => target - times + 1,
+            CoolingState::Infinite => 0,
+        };
+        (remaining, Some(remaining))
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn geometric_and_linear_schedules() {
+        let geometric = GeometricSchedule {
+            start: 0.5,
+            factor: 2.0,
+            steps: 4,
+        };
+        assert_eq!(geometric.collect::<Vec<_>>(), [0.5, 1.0, 2.0, 4.0]);
+        let linear = LinearSchedule {
+            start: 0.0,
+            end: 1.0,
+            steps: 5,
+        };
+        assert_eq!(linear.collect::<Vec<_>>(), [0.0, 0.25, 0.5, 0.75, 1.0]);
+    }
+
+    #[test]
+    fn materialized_betas() {
+        let n = NonZeroUsize::new(4).unwrap();
+        let schedule = CoolingSchedule::new(CoolingConfig::new(n, n, NonZeroUsize::MIN));
+        let betas = schedule.betas();
+        // additive steps of 1/16 up to log2(4) = 2, then 16 multiplicative ones
+        assert_eq!(betas.len(), 33 + 16);
+        assert_eq!(betas[..3], [0.0, 1.0 / 16.0, 2.0 / 16.0]);
+        assert_eq!(betas[32], 2.0);
+        let mut rest = schedule.clone();
+        for remaining in (0..=betas.len()).rev() {
+            assert_eq!(rest.size_hint(), (remaining, Some(remaining)));
+            rest.next();
+        }
+        assert_eq!(schedule.collect::<Vec<_>>(), betas);
+    }
+
     #[test]
     fn simple_cooling_schedule() {
         let config = CoolingConfig {
@@ -131,4 +280,20 @@ mod test {
             println!("{}", value)
         }
     }
+
+    #[test]
+    fn fast_forward_schedule() {
+        let config = || CoolingConfig {
+            n: NonZeroUsize::new(16).unwrap(),
+            additive_ratio: NonZeroUsize::new(2).unwrap(),
+            multiplicative_ratio: NonZeroUsize::new(2).unwrap(),
+        };
+        let full: Vec<f64> = CoolingSchedule::from(config()).collect();
+        let mid = full.len() / 2;
+        let target = (full[mid - 1] + full[mid]) / 2.0;
+        let mut schedule = CoolingSchedule::from(config());
+        assert_eq!(schedule.fast_forward(target), Some(full[mid]));
+        let remaining: Vec<f64> = schedule.collect();
+        assert_eq!(remaining, full[mid + 1..]);
+    }
 }
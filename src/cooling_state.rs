@@ -1,10 +1,58 @@
-use rayon::{
-    iter::ParallelIterator,
-    slice::{ChunksMut, ParallelSliceMut},
+use crate::par::{ChunksMut, ParallelSlice, ParallelSliceMut};
+#[cfg(feature = "parallel")]
+use crate::par::{IndexedParallelIterator, ParallelIterator};
+
+use crate::graph::{CostMatrix, Graph, Match};
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::Path,
 };
 
-use crate::graph::{Graph, Match};
+/// Dimension of a square binary matrix of `f64` values occupying `bytes` bytes.
+fn binary_dimension(bytes: usize) -> anyhow::Result<usize> {
+    let cells = bytes / std::mem::size_of::<f64>();
+    let size = (cells as f64).sqrt() as usize;
+    if cells * std::mem::size_of::<f64>() != bytes || size * size != cells {
+        anyhow::bail!("{} bytes do not form a square matrix of f64", bytes);
+    }
+    Ok(size)
+}
 
+/// Neumaier's compensated sum, which keeps the low-order bits that a plain
+/// running sum drops when adding small terms to a large total.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CompensatedSum {
+    sum: f64,
+    compensation: f64,
+}
+
+impl CompensatedSum {
+    pub fn add(&mut self, x: f64) {
+        let sum = self.sum + x;
+        if self.sum.abs() >= x.abs() {
+            self.compensation += (self.sum - sum) + x;
+        } else {
+            self.compensation += (x - sum) + self.sum;
+        }
+        self.sum = sum;
+    }
+    pub fn value(&self) -> f64 {
+        self.sum + self.compensation
+    }
+}
+
+impl std::iter::Sum<f64> for CompensatedSum {
+    fn sum<I: Iterator<Item = f64>>(iter: I) -> Self {
+        let mut sum = CompensatedSum::default();
+        for x in iter {
+            sum.add(x);
+        }
+        sum
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Matrix {
     size: usize,
     data: Box<[f64]>,
@@ -20,15 +68,25 @@ impl Matrix {
     pub fn dimension(&self) -> usize {
         self.size
     }
-    pub fn par_mut_rows(&mut self) -> ChunksMut<f64> {
+    pub fn par_mut_rows(&mut self) -> ChunksMut<'_, f64> {
         self.data.par_chunks_mut(self.size)
     }
+    pub fn rows(&self) -> std::slice::Chunks<'_, f64> {
+        self.data.chunks(self.size)
+    }
     pub fn get(&self, u: usize, v: usize) -> f64 {
         self.data[u * self.size + v]
     }
+    /// Like [`Matrix::get`], but `None` out of bounds.
+    pub fn try_get(&self, u: usize, v: usize) -> Option<f64> {
+        (u < self.size && v < self.size).then(|| self.get(u, v))
+    }
     pub fn set(&mut self, u: usize, v: usize, value: f64) {
         self.data[u * self.size + v] = value;
     }
+    pub fn to_rows(&self) -> Vec<Vec<f64>> {
+        self.rows().map(<[f64]>::to_vec).collect()
+    }
     pub fn add(&mut self, u: usize, v: usize, value: f64) {
         self.data[u * self.size + v] += value;
     }
@@ -39,6 +97,146 @@ impl Matrix {
             }
         });
     }
+    pub fn trace(&self) -> f64 {
+        (0..self.size).map(|i| self.get(i, i)).sum()
+    }
+    /// Dense matrix product `self * other`, computed row-parallel.
+    pub fn multiply(&self, other: &Matrix) -> Matrix {
+        assert_eq!(self.size, other.size, "dimension mismatch");
+        let mut result = Matrix::new(self.size, 0.0);
+        result
+            .par_mut_rows()
+            .zip(self.data.par_chunks(self.size))
+            .for_each(|(row, lhs)| {
+                for (a, rhs) in lhs.iter().zip(other.rows()) {
+                    for (x, b) in row.iter_mut().zip(rhs) {
+                        *x += a * b;
+                    }
+                }
+            });
+        result
+    }
+    /// Power sums `trace(W^k)` for `k = 1..=max_power`.
+    pub fn power_traces(&self, max_power: usize) -> Vec<f64> {
+        let mut traces = Vec::with_capacity(max_power);
+        let mut power = self.clone();
+        for k in 1..=max_power {
+            traces.push(power.trace());
+            if k < max_power {
+                power = power.multiply(self);
+            }
+        }
+        traces
+    }
+    /// Stable FNV-1a fingerprint of the dimension and the bit patterns of all
+    /// entries in row-major order.
+    pub fn checksum(&self) -> u64 {
+        const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0100_0000_01b3;
+        std::iter::once(self.size as u64)
+            .chain(self.data.iter().map(|x| x.to_bits()))
+            .flat_map(u64::to_le_bytes)
+            .fold(OFFSET, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(PRIME)
+            })
+    }
+    /// Copy of the matrix as an `ndarray` array.
+    #[cfg(feature = "ndarray")]
+    pub fn to_ndarray(&self) -> ndarray::Array2<f64> {
+        ndarray::Array2::from_shape_vec((self.size, self.size), self.data.to_vec())
+            .expect("data holds size * size entries")
+    }
+    /// Write the matrix as comma-separated values, one row per line, with
+    /// every entry at full precision.
+    pub fn write_csv(&self, mut w: impl Write) -> std::io::Result<()> {
+        for row in self.rows() {
+            for (j, x) in row.iter().enumerate() {
+                if j > 0 {
+                    w.write_all(b",")?;
+                }
+                write!(w, "{}", x)?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+    /// Write the matrix as raw little-endian `f64` values in row-major order.
+    pub fn write_binary<S: AsRef<Path>>(&self, path: S) -> anyhow::Result<()> {
+        let mut writer = std::io::BufWriter::new(File::create(path)?);
+        for x in self.data.iter() {
+            writer.write_all(&x.to_le_bytes())?;
+        }
+        writer.flush().map_err(Into::into)
+    }
+    /// Read a matrix written by [`Matrix::write_binary`].
+    pub fn read_binary<S: AsRef<Path>>(path: S) -> anyhow::Result<Self> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        let size = binary_dimension(bytes.len())?;
+        let data = bytes
+            .chunks_exact(std::mem::size_of::<f64>())
+            .map(|x| f64::from_le_bytes(x.try_into().unwrap()))
+            .collect();
+        Ok(Matrix { size, data })
+    }
+    /// Memory-map a matrix written by [`Matrix::write_binary`] without copying it.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be truncated or modified by anyone else while the
+    /// mapping is alive; its size is validated only once, here.
+    #[cfg(feature = "mmap")]
+    pub unsafe fn mmap_readonly<S: AsRef<Path>>(path: S) -> anyhow::Result<MmapMatrix> {
+        let file = File::open(path)?;
+        let map = memmap2::Mmap::map(&file)?;
+        let size = binary_dimension(map.len())?;
+        Ok(MmapMatrix { size, map })
+    }
+}
+
+/// Rows on separate lines with right-aligned columns, two decimals unless the
+/// formatter asks for another precision, as in `{:.4}`.
+impl std::fmt::Display for Matrix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let precision = f.precision().unwrap_or(2);
+        let cells: Vec<String> = self
+            .data
+            .iter()
+            .map(|x| format!("{:.*}", precision, x))
+            .collect();
+        let width = cells.iter().map(String::len).max().unwrap_or(0);
+        for (i, row) in cells.chunks(self.size.max(1)).enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            for (j, cell) in row.iter().enumerate() {
+                if j > 0 {
+                    write!(f, " ")?;
+                }
+                write!(f, "{:>width$}", cell, width = width)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Read-only view of a binary matrix file that is paged in on demand.
+#[cfg(feature = "mmap")]
+pub struct MmapMatrix {
+    size: usize,
+    map: memmap2::Mmap,
+}
+
+#[cfg(feature = "mmap")]
+impl MmapMatrix {
+    pub fn dimension(&self) -> usize {
+        self.size
+    }
+    pub fn get(&self, u: usize, v: usize) -> f64 {
+        let offset = (u * self.size + v) * std::mem::size_of::<f64>();
+        let bytes = &self.map[offset..offset + std::mem::size_of::<f64>()];
+        f64::from_le_bytes(bytes.try_into().unwrap())
+    }
 }
 
 pub struct BitMatrix {
@@ -50,7 +248,7 @@ impl BitMatrix {
     pub fn new(size: usize) -> Self {
         BitMatrix {
             size,
-            data: vec![0; size * size / 64 + 1].into_boxed_slice(),
+            data: vec![0; (size * size).div_ceil(64)].into_boxed_slice(),
         }
     }
     pub fn get(&self, u: usize, v: usize) -> bool {
@@ -65,30 +263,377 @@ impl BitMatrix {
     }
 }
 
+/// Graphs with fewer than this fraction of the `n * n` possible edges keep
+/// their weights in a [`SparseWeights`].
+pub const SPARSE_WEIGHT_DENSITY: f64 = 0.01;
+
+/// Weights stored only on the edges of a graph, in compressed rows, with a
+/// single weight shared by every non-edge.
+#[derive(Clone, Debug)]
+pub struct SparseWeights {
+    size: usize,
+    offsets: Box<[usize]>,
+    columns: Box<[usize]>,
+    values: Box<[f64]>,
+    non_edge: f64,
+}
+
+impl SparseWeights {
+    pub fn new(graph: &Graph, initial: f64) -> Self {
+        let mut offsets = Vec::with_capacity(graph.size + 1);
+        let mut columns = Vec::new();
+        offsets.push(0);
+        for edges in graph.edges.iter() {
+            let mut row = edges.to_vec();
+            row.sort_unstable();
+            row.dedup();
+            columns.extend(row);
+            offsets.push(columns.len());
+        }
+        SparseWeights {
+            size: graph.size,
+            offsets: offsets.into_boxed_slice(),
+            values: vec![initial; columns.len()].into_boxed_slice(),
+            columns: columns.into_boxed_slice(),
+            non_edge: initial,
+        }
+    }
+    pub fn dimension(&self) -> usize {
+        self.size
+    }
+    /// Weights of the stored edges, in row order.
+    pub fn values_mut(&mut self) -> &mut [f64] {
+        &mut self.values
+    }
+    /// Number of stored edges.
+    pub fn edges(&self) -> usize {
+        self.columns.len()
+    }
+    /// Position of edge `(u, v)` among the stored edges.
+    pub fn index(&self, u: usize, v: usize) -> Option<usize> {
+        let row = &self.columns[self.offsets[u]..self.offsets[u + 1]];
+        row.binary_search(&v).ok().map(|i| self.offsets[u] + i)
+    }
+    pub fn get(&self, u: usize, v: usize) -> f64 {
+        match self.index(u, v) {
+            Some(i) => self.values[i],
+            None => self.non_edge,
+        }
+    }
+    /// Set the weight of `(u, v)`, or of all non-edges if it is not an edge.
+    pub fn set(&mut self, u: usize, v: usize, value: f64) {
+        match self.index(u, v) {
+            Some(i) => self.values[i] = value,
+            None => self.non_edge = value,
+        }
+    }
+    pub fn non_edge(&self) -> f64 {
+        self.non_edge
+    }
+    pub fn set_non_edge(&mut self, value: f64) {
+        self.non_edge = value;
+    }
+}
+
+/// Weight matrix of the chains, dense or backed by a [`SparseWeights`].
+#[derive(Clone, Debug)]
+pub enum Weights {
+    Dense(Matrix),
+    Sparse(SparseWeights),
+}
+
+impl Weights {
+    pub fn dimension(&self) -> usize {
+        match self {
+            Weights::Dense(matrix) => matrix.dimension(),
+            Weights::Sparse(sparse) => sparse.dimension(),
+        }
+    }
+    pub fn get(&self, u: usize, v: usize) -> f64 {
+        match self {
+            Weights::Dense(matrix) => matrix.get(u, v),
+            Weights::Sparse(sparse) => sparse.get(u, v),
+        }
+    }
+    pub fn set(&mut self, u: usize, v: usize, value: f64) {
+        match self {
+            Weights::Dense(matrix) => matrix.set(u, v, value),
+            Weights::Sparse(sparse) => sparse.set(u, v, value),
+        }
+    }
+    /// Number of distinct weights stored; non-edges of a sparse backing share
+    /// the last one.
+    pub fn cells(&self) -> usize {
+        match self {
+            Weights::Dense(matrix) => matrix.size * matrix.size,
+            Weights::Sparse(sparse) => sparse.edges() + 1,
+        }
+    }
+    /// Index of the weight of `(u, v)` in `0..self.cells()`.
+    pub fn cell(&self, u: usize, v: usize) -> usize {
+        match self {
+            Weights::Dense(matrix) => u * matrix.size + v,
+            Weights::Sparse(sparse) => sparse.index(u, v).unwrap_or(sparse.edges()),
+        }
+    }
+    pub fn as_dense(&self) -> Option<&Matrix> {
+        match self {
+            Weights::Dense(matrix) => Some(matrix),
+            Weights::Sparse(_) => None,
+        }
+    }
+    /// The weights as a dense matrix, moved out when already dense.
+    pub fn into_dense(self) -> Matrix {
+        match self {
+            Weights::Dense(matrix) => matrix,
+            sparse => sparse.to_dense(),
+        }
+    }
+    pub fn to_rows(&self) -> Vec<Vec<f64>> {
+        match self {
+            Weights::Dense(matrix) => matrix.to_rows(),
+            Weights::Sparse(_) => self.to_dense().to_rows(),
+        }
+    }
+    pub fn to_dense(&self) -> Matrix {
+        match self {
+            Weights::Dense(matrix) => matrix.clone(),
+            Weights::Sparse(sparse) => {
+                let mut matrix = Matrix::new(sparse.size, sparse.non_edge);
+                for u in 0..sparse.size {
+                    for i in sparse.offsets[u]..sparse.offsets[u + 1] {
+                        matrix.set(u, sparse.columns[i], sparse.values[i]);
+                    }
+                }
+                matrix
+            }
+        }
+    }
+}
+
 pub struct State {
     adjacency: BitMatrix,
-    pub weight: Matrix,
+    /// per-edge energy overriding the default `1 - A[u, v]`
+    energy: Option<Matrix>,
+    pub weight: Weights,
     pub beta: f64,
+    /// fixed threshold replacing the uniform draw of the Metropolis test
+    pub acceptance_threshold: Option<f64>,
+}
+
+/// Energy of the non-edges (and zero-weight edges) of a weighted graph with
+/// `n` vertices per side, whose edges have energies `-ln(w / max(w))` of at
+/// most `max_energy`.
+///
+/// Weighted graphs are only cooled down to `beta = 1`. There a perfect
+/// matching of edges weighs at least `exp(-n * max_energy)`, so the scaled
+/// permanent is no smaller, while the at most `n!` matchings through a
+/// non-edge weigh `exp(-energy)` or less each. The energy makes them together
+/// fall below the `2^-53` resolution of an `f64` relative to the permanent;
+/// any more would only steepen the first cooling steps, which have to push the
+/// chains off the non-edges.
+pub fn non_edge_energy(n: usize, max_energy: f64) -> f64 {
+    n as f64 * max_energy + crate::markov_chain::ln_factorial(n) + 53.0 * std::f64::consts::LN_2
 }
 
 impl<'a> From<&'a Graph> for State {
+    /// For a weighted graph the energy of an edge is `-ln(w / max(w))`, so that
+    /// at `beta = 1` the chain targets the weighted permanent scaled down by
+    /// `max(w)^n`.
     fn from(graph: &'a Graph) -> Self {
         let mut adjacency = BitMatrix::new(graph.size);
-        let weight = Matrix::new(graph.size, graph.size as f64);
+        let edges = graph.edges.iter().map(|x| x.len()).sum::<usize>();
+        let weight = if (edges as f64) < SPARSE_WEIGHT_DENSITY * (graph.size * graph.size) as f64 {
+            Weights::Sparse(SparseWeights::new(graph, graph.size as f64))
+        } else {
+            Weights::Dense(Matrix::new(graph.size, graph.size as f64))
+        };
         for (u, edges) in graph.edges.iter().enumerate() {
             for v in edges.iter().copied() {
                 adjacency.set(u, v, true);
             }
         }
+        let energy = graph.weights.as_ref().map(|_| {
+            let scale = graph.max_weight();
+            let edge_energies = graph.edges.iter().enumerate().flat_map(|(u, edges)| {
+                edges
+                    .iter()
+                    .map(move |v| (u, *v, -(graph.edge_weight(u, *v) / scale).ln()))
+            });
+            let max_energy = edge_energies
+                .clone()
+                .map(|(_, _, e)| e)
+                .filter(|e| e.is_finite())
+                .fold(0.0, f64::max);
+            let mut energy = Matrix::new(graph.size, non_edge_energy(graph.size, max_energy));
+            // zero-weight edges keep the energy of a non-edge
+            for (u, v, e) in edge_energies.filter(|(_, _, e)| e.is_finite()) {
+                energy.set(u, v, e);
+            }
+            energy
+        });
         State {
             adjacency,
+            energy,
             weight,
             beta: 0.0,
+            acceptance_threshold: None,
         }
     }
 }
 
 impl State {
+    /// State for the Gibbs matrix `exp(-C / T)` of a cost matrix over the
+    /// complete graph. The energies `(c - min(C)) / T` are shifted to be
+    /// nonnegative, so at `beta = 1` the chain targets the weighted permanent
+    /// scaled by `exp(n min(C) / T)`.
+    pub fn from_cost(cost: &CostMatrix, temperature: f64) -> Self {
+        let graph = Graph::complete(cost.size);
+        let min = cost.min_cost();
+        let mut energy = Matrix::new(cost.size, 0.0);
+        for (u, row) in cost.cost.iter().enumerate() {
+            for (v, c) in row.iter().enumerate() {
+                energy.set(u, v, (c - min) / temperature);
+            }
+        }
+        State {
+            energy: Some(energy),
+            ..State::from(&graph)
+        }
+    }
+    /// Experimental: initialize the weights from the rank-`rank` truncated SVD
+    /// of the 0/1 adjacency, computed by power iteration with deflation.
+    ///
+    /// The reconstruction, clamped to be positive, is taken as the frequency of
+    /// each edge in the matchings, so the weights are its inverse scaled like
+    /// the estimated weights (inverse weights sum to `n`). Sparse weights give
+    /// all non-edges the weight of their mean frequency.
+    pub fn spectral_init(&mut self, rank: usize) {
+        const ITERATIONS: usize = 64;
+        let n = self.weight.dimension();
+        let a = |u: usize, v: usize| if self.adjacency.get(u, v) { 1.0 } else { 0.0 };
+        let apply = |x: &[f64]| -> Vec<f64> {
+            (0..n)
+                .map(|u| (0..n).map(|v| a(u, v) * x[v]).sum())
+                .collect()
+        };
+        let apply_transposed = |x: &[f64]| -> Vec<f64> {
+            (0..n)
+                .map(|v| (0..n).map(|u| a(u, v) * x[u]).sum())
+                .collect()
+        };
+        let normalize = |x: &mut Vec<f64>| {
+            let norm = x.iter().map(|y| y * y).sum::<f64>().sqrt();
+            if norm > 0.0 {
+                x.iter_mut().for_each(|y| *y /= norm);
+            }
+            norm
+        };
+        let mut reconstruction = vec![0.0; n * n];
+        let mut found: Vec<Vec<f64>> = Vec::new();
+        for _ in 0..rank.min(n) {
+            // deterministic start, slightly tilted to avoid symmetric traps
+            let mut v: Vec<f64> = (0..n).map(|i| 1.0 + i as f64 / n as f64).collect();
+            for _ in 0..ITERATIONS {
+                for w in found.iter() {
+                    let dot = v.iter().zip(w).map(|(x, y)| x * y).sum::<f64>();
+                    v.iter_mut().zip(w).for_each(|(x, y)| *x -= dot * y);
+                }
+                if normalize(&mut v) == 0.0 {
+                    break;
+                }
+                v = apply_transposed(&apply(&v));
+            }
+            for w in found.iter() {
+                let dot = v.iter().zip(w).map(|(x, y)| x * y).sum::<f64>();
+                v.iter_mut().zip(w).for_each(|(x, y)| *x -= dot * y);
+            }
+            if normalize(&mut v) == 0.0 {
+                break;
+            }
+            let mut u = apply(&v);
+            let sigma = normalize(&mut u);
+            for (i, x) in u.iter().enumerate() {
+                for (j, y) in v.iter().enumerate() {
+                    reconstruction[i * n + j] += sigma * x * y;
+                }
+            }
+            found.push(v);
+        }
+        let max = reconstruction.iter().copied().fold(0.0, f64::max);
+        if max <= 0.0 {
+            return;
+        }
+        let floor = max * 1e-3;
+        reconstruction.iter_mut().for_each(|x| *x = x.max(floor));
+        let scale = n as f64 / reconstruction.iter().sum::<f64>();
+        match &mut self.weight {
+            Weights::Dense(matrix) => {
+                for (i, x) in reconstruction.into_iter().enumerate() {
+                    matrix.set(i / n, i % n, 1.0 / (x * scale));
+                }
+            }
+            Weights::Sparse(sparse) => {
+                let mut non_edges = CompensatedSum::default();
+                let mut count = 0;
+                for (i, x) in reconstruction.into_iter().enumerate() {
+                    match sparse.index(i / n, i % n) {
+                        Some(j) => sparse.values_mut()[j] = 1.0 / (x * scale),
+                        None => {
+                            non_edges.add(x);
+                            count += 1;
+                        }
+                    }
+                }
+                if count > 0 {
+                    sparse.set_non_edge(count as f64 / (non_edges.value() * scale));
+                }
+            }
+        }
+    }
+    /// Initialize the weights from the Sinkhorn scaling of the (weighted)
+    /// adjacency of `graph`, after `iterations` rounds of [`sinkhorn_scaling`].
+    ///
+    /// Like in [`State::spectral_init`] the scaled matrix, floored to be
+    /// positive, is taken as the frequency of each edge in the matchings. A
+    /// doubly stochastic scaling is what those frequencies approach as the
+    /// chain cools, so skewed matrices start with weights close to the final
+    /// ones instead of uniform; on regular graphs the scaling is uniform and
+    /// this changes nothing.
+    pub fn sinkhorn_init(&mut self, graph: &Graph, iterations: usize) {
+        let n = graph.size;
+        let (rows, columns) = sinkhorn_scaling(graph, iterations);
+        let frequency = |u: usize, v: usize| rows[u] * graph.edge_weight(u, v) * columns[v];
+        let max = (0..n)
+            .flat_map(|u| graph.edges[u].iter().map(move |v| (u, *v)))
+            .map(|(u, v)| frequency(u, v))
+            .fold(0.0, f64::max);
+        if !(max > 0.0 && max.is_finite()) {
+            return;
+        }
+        let floor = max * 1e-3;
+        let mut sum = CompensatedSum::default();
+        let mut edges = 0;
+        for (u, row) in graph.edges.iter().enumerate() {
+            for v in row.iter() {
+                sum.add(frequency(u, *v).max(floor));
+                edges += 1;
+            }
+        }
+        sum.add(floor * (n * n - edges) as f64);
+        let scale = n as f64 / sum.value();
+        let non_edge = 1.0 / (floor * scale);
+        match &mut self.weight {
+            Weights::Dense(matrix) => matrix.transform(|_| non_edge),
+            Weights::Sparse(sparse) => sparse.set_non_edge(non_edge),
+        }
+        for (u, row) in graph.edges.iter().enumerate() {
+            for v in row.iter().copied() {
+                self.weight
+                    .set(u, v, 1.0 / (frequency(u, v).max(floor) * scale));
+            }
+        }
+    }
     pub fn activity_of_edge(&self, u: usize, v: usize) -> usize {
         // e ^ (-beta * (1 - A[u, v]))
         if self.adjacency.get(u, v) {
@@ -97,6 +642,19 @@ impl State {
             0
         }
     }
+    pub fn energy_of_edge(&self, u: usize, v: usize) -> f64 {
+        match &self.energy {
+            Some(energy) => energy.get(u, v),
+            None => (1 - self.activity_of_edge(u, v)) as f64,
+        }
+    }
+    pub fn energy_of_match(&self, matching: &Match) -> f64 {
+        matching
+            .edges
+            .iter()
+            .map(|x| self.energy_of_edge(x.0, x.1))
+            .sum()
+    }
     pub fn active_count_of_match(&self, matching: &Match) -> usize {
         matching
             .edges
@@ -111,13 +669,314 @@ impl State {
         matching
             .edges
             .iter()
-            .map(|x| self.weight.get(x.0, x.1))
-            .sum()
+            .map(|x| self.weight_of_edge(x.0, x.1))
+            .sum::<CompensatedSum>()
+            .value()
+    }
+}
+
+/// Row and column factors `r`, `c` that make `r[u] * w(u, v) * c[v]` doubly
+/// stochastic, after `iterations` rounds of alternately normalizing the rows
+/// and the columns of the weighted adjacency `w` of `graph`. Rows or columns
+/// without edges keep a factor of one.
+pub fn sinkhorn_scaling(graph: &Graph, iterations: usize) -> (Vec<f64>, Vec<f64>) {
+    let n = graph.size;
+    let mut rows = vec![1.0; n];
+    let mut columns = vec![1.0; n];
+    let inverse = |x: f64| if x > 0.0 { 1.0 / x } else { 1.0 };
+    for _ in 0..iterations {
+        for (u, edges) in graph.edges.iter().enumerate() {
+            let sum = edges
+                .iter()
+                .map(|v| graph.edge_weight(u, *v) * columns[*v])
+                .sum::<f64>();
+            rows[u] = inverse(sum);
+        }
+        let mut sums = vec![0.0; n];
+        for (u, edges) in graph.edges.iter().enumerate() {
+            for v in edges.iter() {
+                sums[*v] += rows[u] * graph.edge_weight(u, *v);
+            }
+        }
+        columns = sums.into_iter().map(inverse).collect();
     }
+    (rows, columns)
 }
 
 #[cfg(test)]
 mod test {
+    use std::path::PathBuf;
+
+    #[test]
+    fn compensated_sum() {
+        let terms = || std::iter::once(1.0).chain(std::iter::repeat_n(1e-16, 1_000_000));
+        // every tiny term is below half an ulp of the running total
+        assert_eq!(terms().sum::<f64>(), 1.0);
+        let sum = terms().sum::<super::CompensatedSum>().value();
+        assert!((sum - (1.0 + 1e-10)).abs() < 1e-15, "sum = {}", sum);
+        let mut cancelling = super::CompensatedSum::default();
+        for x in [1.0, 1e100, 1.0, -1e100] {
+            cancelling.add(x);
+        }
+        assert_eq!(cancelling.value(), 2.0);
+    }
+
+    #[test]
+    fn sparse_weights() {
+        let n = 256;
+        // a cycle cover with two edges per row, well below the density threshold
+        let graph = crate::graph::Graph {
+            size: n,
+            edges: (0..n).map(|u| vec![(u + 1) % n, u].into()).collect(),
+            weights: None,
+        };
+        let mut state = super::State::from(&graph);
+        let super::Weights::Sparse(sparse) = &state.weight else {
+            panic!("expected sparse weights");
+        };
+        assert_eq!(sparse.edges(), 2 * n);
+        assert_eq!(state.weight.cells(), 2 * n + 1);
+        assert_eq!(state.weight.cell(3, 3), state.weight.cell(3, 4) - 1);
+        assert_eq!(state.weight.cell(0, 7), 2 * n);
+        state.weight.set(3, 4, 2.0);
+        state.weight.set(0, 7, 0.5);
+        assert_eq!(state.weight_of_edge(3, 4), 2.0);
+        assert_eq!(state.weight_of_edge(3, 3), n as f64);
+        // every non-edge shares the weight
+        assert_eq!(state.weight_of_edge(9, 2), 0.5);
+        let dense = state.weight.to_dense();
+        for u in 0..n {
+            for v in 0..n {
+                assert_eq!(dense.get(u, v), state.weight_of_edge(u, v));
+            }
+        }
+        let complete = super::State::from(&crate::graph::Graph::complete(4));
+        assert!(complete.weight.as_dense().is_some());
+    }
+
+    #[test]
+    fn light_edges_keep_their_energy() {
+        // (0, 2) is the only non-edge, (1, 1) weighs e^-100 of the heaviest
+        let graph = crate::graph::Graph {
+            size: 3,
+            edges: vec![
+                vec![0, 1].into(),
+                vec![0, 1, 2].into(),
+                vec![0, 1, 2].into(),
+            ]
+            .into(),
+            weights: Some(
+                vec![
+                    vec![1.0, 1.0].into(),
+                    vec![1.0, (-100f64).exp(), 1.0].into(),
+                    vec![1.0, 1.0, 0.0].into(),
+                ]
+                .into(),
+            ),
+        };
+        let state = super::State::from(&graph);
+        assert!((state.energy_of_edge(1, 1) - 100.0).abs() < 1e-9);
+        let non_edge = super::non_edge_energy(3, 100.0);
+        assert!(non_edge > 300.0);
+        assert_eq!(state.energy_of_edge(0, 2), non_edge);
+        // a zero-weight edge counts as a non-edge
+        assert_eq!(state.energy_of_edge(2, 2), non_edge);
+    }
+
+    #[test]
+    fn bit_matrix_corners() {
+        for size in [1, 7, 8, 9, 15, 16, 17, 63, 64, 65] {
+            let mut matrix = super::BitMatrix::new(size);
+            assert_eq!(matrix.data.len(), (size * size).div_ceil(64));
+            matrix.set(size - 1, size - 1, true);
+            assert!(matrix.get(size - 1, size - 1));
+            assert!(!matrix.get(0, 0) || size == 1);
+            matrix.set(size - 1, size - 1, false);
+            assert!(!matrix.get(size - 1, size - 1));
+        }
+        assert!(super::BitMatrix::new(0).data.is_empty());
+    }
+
+    #[test]
+    fn sinkhorn_init() {
+        let path: PathBuf = env!("PWD").into();
+        for name in ["box.json", "weighted.json"] {
+            let graph = crate::graph::Graph::load(path.join("data").join(name)).unwrap();
+            let n = graph.size;
+            // without total support (box.json) the sums only converge like 1/k
+            let (rows, columns) = super::sinkhorn_scaling(&graph, 1000);
+            let scaled = |u: usize, v: usize| rows[u] * graph.edge_weight(u, v) * columns[v];
+            for i in 0..n {
+                let row = (0..n).map(|v| scaled(i, v)).sum::<f64>();
+                let column = (0..n).map(|u| scaled(u, i)).sum::<f64>();
+                assert!(
+                    (row - 1.0).abs() < 1e-2,
+                    "{}: row {} sums to {}",
+                    name,
+                    i,
+                    row
+                );
+                assert!(
+                    (column - 1.0).abs() < 1e-2,
+                    "{}: column {} sums to {}",
+                    name,
+                    i,
+                    column
+                );
+            }
+            let mut state = super::State::from(&graph);
+            state.sinkhorn_init(&graph, 1000);
+            let inverse_sum = (0..n * n)
+                .map(|x| 1.0 / state.weight_of_edge(x / n, x % n))
+                .sum::<f64>();
+            assert!((inverse_sum - n as f64).abs() < 1e-9 * n as f64);
+        }
+        // a regular graph scales uniformly
+        let graph = crate::graph::Graph::complete(4);
+        let mut state = super::State::from(&graph);
+        state.sinkhorn_init(&graph, 8);
+        assert!((0..16).all(|x| (state.weight_of_edge(x / 4, x % 4) - 4.0).abs() < 1e-9));
+    }
+
+    #[test]
+    fn spectral_init() {
+        let path: PathBuf = env!("PWD").into();
+        for name in ["complete.json", "box.json"] {
+            let graph = crate::graph::Graph::load(path.join("data").join(name)).unwrap();
+            let n = graph.size as f64;
+            let mut state = super::State::from(&graph);
+            state.spectral_init(2);
+            let weights: Vec<f64> = state.weight.to_dense().rows().flatten().copied().collect();
+            assert!(weights.iter().all(|x| x.is_finite() && *x > 0.0));
+            let inverse_sum = weights.iter().map(|x| 1.0 / x).sum::<f64>();
+            assert!((inverse_sum - n).abs() < 1e-9 * n);
+            if name == "complete.json" {
+                // the rank-one reconstruction of all ones is uniform
+                assert!(weights.iter().all(|x| (x - n).abs() < 1e-9 * n));
+            }
+        }
+        // a cycle cover with a denser first row keeps its weights sparse
+        let n = 256;
+        let graph = crate::graph::Graph {
+            size: n,
+            edges: (0..n)
+                .map(|u| match u {
+                    0 => (0..8).collect::<Vec<_>>().into(),
+                    _ => vec![u, (u + 1) % n].into(),
+                })
+                .collect(),
+            weights: None,
+        };
+        let mut state = super::State::from(&graph);
+        state.spectral_init(2);
+        let super::Weights::Sparse(sparse) = &state.weight else {
+            panic!("expected sparse weights");
+        };
+        let non_edge = sparse.non_edge();
+        assert!(non_edge.is_finite() && non_edge > 0.0);
+        let inverse_sum = (0..n * n)
+            .map(|x| 1.0 / state.weight_of_edge(x / n, x % n))
+            .sum::<f64>();
+        assert!((inverse_sum - n as f64).abs() < 1e-9 * n as f64);
+    }
+
+    #[test]
+    fn power_traces() {
+        let mut matrix = super::Matrix::new(3, 0.0);
+        for (i, row) in [[1.0, 2.0, 0.0], [3.0, 0.5, 1.0], [0.0, 4.0, 2.0]]
+            .iter()
+            .enumerate()
+        {
+            for (j, x) in row.iter().enumerate() {
+                matrix.set(i, j, *x);
+            }
+        }
+        // trace(W^2) = sum_ij w_ij * w_ji
+        let mut expected = 0.0;
+        for i in 0..3 {
+            for j in 0..3 {
+                expected += matrix.get(i, j) * matrix.get(j, i);
+            }
+        }
+        let traces = matrix.power_traces(3);
+        assert_eq!(traces.len(), 3);
+        assert_eq!(traces[0], 3.5);
+        assert_eq!(traces[1], expected);
+        let cube = matrix.multiply(&matrix).multiply(&matrix);
+        assert_eq!(traces[2], cube.trace());
+    }
+
+    #[test]
+    fn matrix_rows() {
+        let mut matrix = super::Matrix::new(2, 0.0);
+        matrix.set(0, 1, 2.0);
+        matrix.set(1, 0, 3.0);
+        assert_eq!(matrix.to_rows(), [[0.0, 2.0], [3.0, 0.0]]);
+        assert_eq!(matrix.try_get(1, 0), Some(3.0));
+        assert_eq!(matrix.try_get(2, 0), None);
+        assert_eq!(matrix.try_get(0, 2), None);
+    }
+
+    #[test]
+    fn matrix_display_and_csv() {
+        let mut matrix = super::Matrix::new(2, 0.5);
+        matrix.set(0, 1, 12.25);
+        matrix.set(1, 0, -1.0);
+        assert_eq!(matrix.to_string(), " 0.50 12.25\n-1.00  0.50");
+        assert_eq!(format!("{:.1}", matrix), " 0.5 12.2\n-1.0  0.5");
+        let mut csv = Vec::new();
+        matrix.write_csv(&mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "0.5,12.25\n-1,0.5\n");
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn matrix_to_ndarray() {
+        let mut matrix = super::Matrix::new(2, 0.0);
+        matrix.set(0, 1, 2.0);
+        matrix.set(1, 0, 3.0);
+        assert_eq!(matrix.to_ndarray(), ndarray::array![[0.0, 2.0], [3.0, 0.0]]);
+    }
+
+    #[test]
+    fn matrix_checksum() {
+        let mut matrix = super::Matrix::new(4, 1.0);
+        matrix.set(1, 2, 3.0);
+        let copy = matrix.clone();
+        assert_eq!(matrix.checksum(), copy.checksum());
+        let mut perturbed = matrix.clone();
+        perturbed.add(3, 3, f64::EPSILON);
+        assert_ne!(matrix.checksum(), perturbed.checksum());
+        assert_ne!(
+            super::Matrix::new(2, 0.0).checksum(),
+            super::Matrix::new(3, 0.0).checksum()
+        );
+    }
+
+    #[test]
+    fn binary_matrix_roundtrip() {
+        let path = std::env::temp_dir().join(format!("matrix-{}.bin", std::process::id()));
+        let mut matrix = super::Matrix::new(3, 0.0);
+        for i in 0..3 {
+            for j in 0..3 {
+                matrix.set(i, j, (i * 3 + j) as f64 + 0.5);
+            }
+        }
+        matrix.write_binary(&path).unwrap();
+        let loaded = super::Matrix::read_binary(&path).unwrap();
+        assert_eq!(loaded.dimension(), 3);
+        #[cfg(feature = "mmap")]
+        let mapped = unsafe { super::Matrix::mmap_readonly(&path).unwrap() };
+        for i in 0..3 {
+            for j in 0..3 {
+                assert_eq!(loaded.get(i, j), matrix.get(i, j));
+                #[cfg(feature = "mmap")]
+                assert_eq!(mapped.get(i, j), matrix.get(i, j));
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
     #[test]
     fn bitmatrix_test() {
         let mut diagnal = super::BitMatrix::new(10);
@@ -0,0 +1,150 @@
+use crate::filter::TrajectoryPoint;
+
+/// Sample autocorrelation of `series` at the given `lag`.
+/// A constant series has no measurable correlation and yields `0.0`.
+pub fn autocorrelation(series: &[f64], lag: usize) -> f64 {
+    let n = series.len();
+    if lag >= n {
+        return 0.0;
+    }
+    let mean = series.iter().sum::<f64>() / n as f64;
+    let variance = series.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>();
+    if variance <= 0.0 {
+        return 0.0;
+    }
+    let covariance = series
+        .iter()
+        .zip(series[lag..].iter())
+        .map(|(x, y)| (x - mean) * (y - mean))
+        .sum::<f64>();
+    covariance / variance
+}
+
+/// Autocorrelations of `series` for the lags `0..=max_lag`.
+pub fn autocorrelation_function(series: &[f64], max_lag: usize) -> Vec<f64> {
+    (0..=max_lag)
+        .map(|lag| autocorrelation(series, lag))
+        .collect()
+}
+
+/// Integrated autocorrelation time `1 + 2 * sum(rho_k)`, where the sum runs
+/// over the initial positive autocorrelations.
+pub fn integrated_autocorrelation_time(series: &[f64]) -> f64 {
+    let mut tau = 1.0;
+    for lag in 1..series.len() {
+        let rho = autocorrelation(series, lag);
+        if rho <= 0.0 {
+            break;
+        }
+        tau += 2.0 * rho;
+    }
+    tau
+}
+
+/// Effective sample size of a correlated series, `n / tau`.
+pub fn effective_sample_size(series: &[f64]) -> f64 {
+    let n = series.len();
+    if n < 2 {
+        return n as f64;
+    }
+    n as f64 / integrated_autocorrelation_time(series)
+}
+
+/// Gelman–Rubin potential scale reduction factor of parallel chains, each
+/// given as a series of equal length. Values close to one indicate that the
+/// chains sample the same distribution; it is `NaN` with fewer than two chains
+/// or two samples per chain.
+pub fn potential_scale_reduction(chains: &[Vec<f64>]) -> f64 {
+    let m = chains.len();
+    let n = chains.iter().map(Vec::len).min().unwrap_or(0);
+    if m < 2 || n < 2 {
+        return f64::NAN;
+    }
+    let means: Vec<f64> = chains
+        .iter()
+        .map(|x| x[..n].iter().sum::<f64>() / n as f64)
+        .collect();
+    let grand_mean = means.iter().sum::<f64>() / m as f64;
+    let between = n as f64 / (m - 1) as f64
+        * means
+            .iter()
+            .map(|x| (x - grand_mean) * (x - grand_mean))
+            .sum::<f64>();
+    let within = chains
+        .iter()
+        .zip(means.iter())
+        .map(|(x, mean)| {
+            x[..n].iter().map(|y| (y - mean) * (y - mean)).sum::<f64>() / (n - 1) as f64
+        })
+        .sum::<f64>()
+        / m as f64;
+    if within <= 0.0 {
+        // constant chains agree exactly or not at all
+        return if between <= 0.0 { 1.0 } else { f64::INFINITY };
+    }
+    let pooled = (n - 1) as f64 / n as f64 * within + between / n as f64;
+    (pooled / within).sqrt()
+}
+
+/// Autocorrelation curves of a single chain's weight and active count.
+#[derive(Debug)]
+pub struct MixingProfile {
+    pub weight_acf: Vec<f64>,
+    pub active_count_acf: Vec<f64>,
+    pub weight_tau: f64,
+    pub active_count_tau: f64,
+}
+
+impl MixingProfile {
+    pub fn from_trajectory(trajectory: &[TrajectoryPoint], max_lag: usize) -> Self {
+        let weight: Vec<f64> = trajectory.iter().map(|x| x.weight).collect();
+        let active_count: Vec<f64> = trajectory.iter().map(|x| x.active_count as f64).collect();
+        MixingProfile {
+            weight_acf: autocorrelation_function(&weight, max_lag),
+            active_count_acf: autocorrelation_function(&active_count, max_lag),
+            weight_tau: integrated_autocorrelation_time(&weight),
+            active_count_tau: integrated_autocorrelation_time(&active_count),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn independent_series() {
+        let series: Vec<f64> = (0..4096).map(|_| rand::random::<f64>()).collect();
+        let ess = effective_sample_size(&series);
+        assert!(ess > 0.8 * series.len() as f64, "ess = {}", ess);
+    }
+
+    #[test]
+    fn correlated_series() {
+        let mut series = Vec::with_capacity(4096);
+        let mut x = 0.0;
+        for _ in 0..4096 {
+            x = 0.95 * x + rand::random::<f64>() - 0.5;
+            series.push(x);
+        }
+        assert!(autocorrelation(&series, 1) > 0.8);
+        let ess = effective_sample_size(&series);
+        assert!(ess < 0.2 * series.len() as f64, "ess = {}", ess);
+    }
+
+    #[test]
+    fn gelman_rubin() {
+        let chain =
+            |shift: f64| -> Vec<f64> { (0..1024).map(|_| rand::random::<f64>() + shift).collect() };
+        let mixed: Vec<_> = (0..4).map(|_| chain(0.0)).collect();
+        let r_hat = potential_scale_reduction(&mixed);
+        assert!((r_hat - 1.0).abs() < 0.01, "r_hat = {}", r_hat);
+        let stuck: Vec<_> = (0..4).map(|x| chain(x as f64)).collect();
+        assert!(potential_scale_reduction(&stuck) > 2.0);
+        assert_eq!(
+            potential_scale_reduction(&[vec![1.0; 8], vec![1.0; 8]]),
+            1.0
+        );
+        assert!(potential_scale_reduction(&mixed[..1]).is_nan());
+    }
+}
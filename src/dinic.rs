@@ -1,17 +1,23 @@
+use num_traits::PrimInt;
 use std::collections::VecDeque;
+use tracing::warn;
 
-struct Edge {
+struct Edge<C> {
     points: (usize, usize),
-    cap: isize,
-    flow: isize,
+    cap: C,
+    /// capacity left on the edge, `cap` minus the flow through it
+    residual: C,
 }
-pub struct DinicGraph {
+
+/// Max flow network with integer capacities of type `C`, unit capacities of
+/// matchings fitting any width.
+pub struct DinicGraph<C = u32> {
     /// current flow
-    flow: isize,
+    flow: C,
     /// map from node to edges
     adjacency: Vec<Vec<usize>>,
     /// edge storage
-    edges: Vec<Edge>,
+    edges: Vec<Edge<C>>,
     /// level graph
     level: Vec<usize>,
     /// pointer to the next edge
@@ -22,10 +28,10 @@ pub struct DinicGraph {
     sink: usize,
 }
 
-impl DinicGraph {
+impl<C: PrimInt> DinicGraph<C> {
     pub fn new(n: usize, source: usize, sink: usize) -> Self {
         DinicGraph {
-            flow: 0,
+            flow: C::zero(),
             adjacency: vec![vec![]; n],
             edges: vec![],
             level: vec![usize::MAX; n],
@@ -40,29 +46,59 @@ impl DinicGraph {
         self.pointer.fill(0);
     }
 
-    pub fn add_edge(&mut self, from: usize, to: usize, cap: isize) {
+    pub fn add_edge(&mut self, from: usize, to: usize, cap: C) {
         let m = self.edges.len();
         self.edges.push(Edge {
             points: (from, to),
             cap,
-            flow: 0,
+            residual: cap,
         });
         self.edges.push(Edge {
             points: (to, from),
-            cap: 0,
-            flow: 0,
+            cap: C::zero(),
+            residual: C::zero(),
         });
         self.adjacency[from].push(m);
         self.adjacency[to].push(m + 1);
     }
 
+    /// Remove all flow, so that the network can be solved again after
+    /// changing its capacities.
+    pub fn reset_flow(&mut self) {
+        self.flow = C::zero();
+        for edge in self.edges.iter_mut() {
+            edge.residual = edge.cap;
+        }
+    }
+
+    /// Set the capacity of every edge from `from` to `to`, returning whether
+    /// there was one. Resets the flow, which may not fit the new capacity.
+    pub fn set_cap(&mut self, from: usize, to: usize, cap: C) -> bool {
+        let mut found = false;
+        for id in self.adjacency[from].iter().copied() {
+            // odd ids are the reverse edges
+            if id % 2 == 0 && self.edges[id].points.1 == to {
+                self.edges[id].cap = cap;
+                found = true;
+            }
+        }
+        self.reset_flow();
+        found
+    }
+
+    /// Remove the edges from `from` to `to` by zeroing their capacity,
+    /// returning whether there were any. Resets the flow.
+    pub fn remove_edge(&mut self, from: usize, to: usize) -> bool {
+        self.set_cap(from, to, C::zero())
+    }
+
     fn bfs(&mut self) -> bool {
         let mut queue = VecDeque::new();
         queue.push_back(self.source);
         self.level[self.source] = 0;
         while let Some(v) = queue.pop_front() {
             for id in self.adjacency[v].iter().copied() {
-                if self.edges[id].cap - self.edges[id].flow < 1 {
+                if self.edges[id].residual.is_zero() {
                     continue;
                 }
                 if self.level[self.edges[id].points.1] != usize::MAX {
@@ -75,55 +111,104 @@ impl DinicGraph {
         self.level[self.sink] != usize::MAX
     }
 
-    fn dfs(&mut self, v: usize, budget: isize) -> isize {
-        stacker::maybe_grow(32 * 1024, 1024 * 1024, || {
-            if budget == 0 {
-                return 0;
-            }
+    /// Push flow along one augmenting path of the level graph, found by a
+    /// depth-first search with an explicit stack of edges so that long paths
+    /// cannot overflow the call stack. Dead ends advance `pointer` past the
+    /// edge leading into them.
+    fn dfs(&mut self) -> C {
+        let mut path: Vec<usize> = Vec::new();
+        let mut v = self.source;
+        loop {
             if v == self.sink {
-                return budget;
+                let update = path
+                    .iter()
+                    .map(|id| self.edges[*id].residual)
+                    .min()
+                    .unwrap_or(C::max_value());
+                // the residuals of an edge and its reverse add up to the
+                // capacity of the edge, so neither leaves `0..=cap`
+                for id in path.iter().copied() {
+                    self.edges[id].residual = self.edges[id].residual - update;
+                    self.edges[id ^ 1].residual = self.edges[id ^ 1].residual + update;
+                }
+                return update;
             }
+            let mut next = None;
             while self.pointer[v] < self.adjacency[v].len() {
-                let cid = self.pointer[v];
-                self.pointer[v] += 1;
-                let id = self.adjacency[v][cid];
+                let id = self.adjacency[v][self.pointer[v]];
                 let u = self.edges[id].points.1;
-                let space = self.edges[id].cap - self.edges[id].flow;
-                if self.level[v] + 1 != self.level[u] || space < 1 {
-                    continue;
+                if self.level[v] + 1 == self.level[u] && !self.edges[id].residual.is_zero() {
+                    next = Some((id, u));
+                    break;
                 }
-                let update = self.dfs(u, budget.min(space));
-                if update < 1 {
-                    continue;
+                self.pointer[v] += 1;
+            }
+            match next {
+                Some((id, u)) => {
+                    path.push(id);
+                    v = u;
                 }
-                self.edges[id].flow += update;
-                self.edges[id ^ 1].flow -= update;
-                return update;
+                None => match path.pop() {
+                    Some(id) => {
+                        v = self.edges[id].points.0;
+                        self.pointer[v] += 1;
+                    }
+                    None => return C::zero(),
+                },
             }
-            0
-        })
+        }
     }
 
-    pub fn calculate_flow(&mut self) -> isize {
+    /// Maximum flow from the source to the sink, saturating at
+    /// `C::max_value()` with a warning if the total overflows `C`; the flow
+    /// through every single edge stays exact.
+    pub fn calculate_flow(&mut self) -> C {
         while self.bfs() {
             loop {
-                let update = self.dfs(self.source, isize::MAX);
-                if update < 1 {
+                let update = self.dfs();
+                if update.is_zero() {
                     break;
                 }
-                self.flow += update;
+                self.flow = match self.flow.checked_add(&update) {
+                    Some(flow) => flow,
+                    None => {
+                        warn!("total flow overflows its capacity type, saturating");
+                        C::max_value()
+                    }
+                };
             }
             self.reset();
         }
         self.flow
     }
 
-    pub fn current_flow(&self) -> impl Iterator<Item = (usize, usize, isize)> + '_ {
+    /// Source and sink side of a minimum cut: the nodes reachable from the
+    /// source in the residual graph of the current flow, and the rest. Only a
+    /// minimum cut once [`DinicGraph::calculate_flow`] has run.
+    pub fn min_cut(&self) -> (Vec<usize>, Vec<usize>) {
+        let mut reachable = vec![false; self.adjacency.len()];
+        let mut queue = VecDeque::new();
+        reachable[self.source] = true;
+        queue.push_back(self.source);
+        while let Some(v) = queue.pop_front() {
+            for id in self.adjacency[v].iter().copied() {
+                let u = self.edges[id].points.1;
+                if !self.edges[id].residual.is_zero() && !reachable[u] {
+                    reachable[u] = true;
+                    queue.push_back(u);
+                }
+            }
+        }
+        (0..reachable.len()).partition(|v| reachable[*v])
+    }
+
+    pub fn current_flow(&self) -> impl Iterator<Item = (usize, usize, C)> + '_ {
         self.edges.iter().step_by(2).filter_map(|edge| {
-            if edge.flow < 1 {
+            let flow = edge.cap - edge.residual;
+            if flow.is_zero() {
                 return None;
             }
-            Some((edge.points.0, edge.points.1, edge.flow))
+            Some((edge.points.0, edge.points.1, flow))
         })
     }
 }
@@ -133,7 +218,7 @@ mod test {
     use super::*;
     #[test]
     fn maxflow_example() {
-        let mut g = DinicGraph::new(6, 0, 5);
+        let mut g = DinicGraph::<u32>::new(6, 0, 5);
         g.add_edge(0, 1, 16);
         g.add_edge(0, 2, 13);
         g.add_edge(1, 2, 10);
@@ -146,5 +231,60 @@ mod test {
         g.add_edge(4, 5, 4);
         assert_eq!(g.calculate_flow(), 23);
         println!("{:?}", g.current_flow().collect::<Box<[_]>>());
+        // the saturated edges 1 -> 3, 4 -> 3 and 4 -> 5 carry 12 + 7 + 4
+        let (source_side, sink_side) = g.min_cut();
+        assert_eq!(source_side, [0, 1, 2, 4]);
+        assert_eq!(sink_side, [3, 5]);
+    }
+
+    #[test]
+    fn reuse_network() {
+        let mut g = DinicGraph::<u32>::new(6, 0, 5);
+        for (from, to, cap) in [
+            (0, 1, 3),
+            (0, 2, 2),
+            (1, 3, 3),
+            (2, 4, 2),
+            (3, 5, 2),
+            (4, 5, 3),
+        ] {
+            g.add_edge(from, to, cap);
+        }
+        assert_eq!(g.calculate_flow(), 4);
+        g.reset_flow();
+        assert_eq!(g.current_flow().count(), 0);
+        assert_eq!(g.calculate_flow(), 4);
+        assert!(g.set_cap(3, 5, 3));
+        assert_eq!(g.calculate_flow(), 5);
+        assert!(g.remove_edge(2, 4));
+        assert!(!g.remove_edge(2, 5));
+        assert_eq!(g.calculate_flow(), 3);
+        assert!(g.current_flow().all(|(from, _, _)| from != 2));
+    }
+
+    #[test]
+    fn saturating_total_flow() {
+        let cap = isize::MAX - 1;
+        let mut g = DinicGraph::<isize>::new(4, 0, 3);
+        for v in [1, 2] {
+            g.add_edge(0, v, cap);
+            g.add_edge(v, 3, cap);
+        }
+        assert_eq!(g.calculate_flow(), isize::MAX);
+        assert!(g.current_flow().all(|(_, _, flow)| flow == cap));
+        assert_eq!(g.current_flow().count(), 4);
+    }
+
+    #[test]
+    fn long_augmenting_path() {
+        let n = 100_000;
+        let mut g = DinicGraph::<u8>::new(n + 1, 0, n - 1);
+        // a dead end halfway along the path the search has to back out of
+        g.add_edge(n / 2, n, 1);
+        for v in 0..n - 1 {
+            g.add_edge(v, v + 1, 1);
+        }
+        assert_eq!(g.calculate_flow(), 1);
+        assert_eq!(g.current_flow().count(), n - 1);
     }
 }
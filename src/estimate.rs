@@ -0,0 +1,851 @@
+use crate::{
+    cooling_schedule::{CoolingConfig, CoolingSchedule, Schedule},
+    cooling_state::Weights,
+    exact,
+    filter::{self, MetropolisFilter},
+    graph::{CostMatrix, Graph},
+    markov_chain::{Config, MCState, PermanentEstimate},
+};
+use serde::Serialize;
+use std::{fmt::Write, num::NonZeroUsize, time::Duration};
+use tracing::{info, warn};
+
+/// Outcome of a single estimation run together with its diagnostics.
+#[derive(Debug, Clone, Copy)]
+pub struct Estimate {
+    /// estimated permanent
+    pub value: f64,
+    /// natural logarithm of the estimate
+    pub log_value: f64,
+    /// standard error of `log_value`, i.e. the relative error of the estimate
+    pub rel_std_error: f64,
+    /// a priori bounds `(lower, upper)` of the permanent
+    pub bounds: (f64, f64),
+    /// aggregate effective sample size of the last cooling step
+    pub effective_sample_size: f64,
+    /// number of samples drawn in the last cooling step
+    pub nominal_sample_size: usize,
+    /// Metropolis acceptance rate of the chains in the last cooling step
+    pub acceptance_rate: f64,
+    /// Gelman–Rubin R-hat of the chain weights in the last cooling step, NaN
+    /// with fewer than two chains
+    pub r_hat: f64,
+    /// time spent warming up the chains
+    pub warmup_time: Duration,
+    /// time spent in the cooling schedule
+    pub cooling_time: Duration,
+    /// the time budget stopped the cooling before the final beta
+    pub truncated: bool,
+}
+
+/// Two-sided 95% quantile of the standard normal distribution.
+const NORMAL_QUANTILE_95: f64 = 1.959_964;
+
+/// Thresholds an estimate has to meet to count as converged.
+#[derive(Debug, Clone, Copy)]
+pub struct ConvergenceCriteria {
+    /// minimal ratio between the effective and the nominal sample size
+    pub min_ess_ratio: f64,
+}
+
+impl Estimate {
+    /// Trivial bounds `1 <= perm(A) <= n!` of a 0/1 matrix with a perfect matching.
+    pub fn binary_bounds(size: usize) -> (f64, f64) {
+        (1.0, (1..=size).map(|x| x as f64).product::<f64>())
+    }
+    /// A priori bounds of the permanent of `graph`: [`Estimate::binary_bounds`]
+    /// for a 0/1 matrix, `[0, n! * max(w)^n]` for a weighted one.
+    pub fn graph_bounds(graph: &Graph) -> (f64, f64) {
+        let bounds = Self::binary_bounds(graph.size);
+        match graph.weights {
+            Some(_) => (
+                0.0,
+                bounds.1 * (graph.size as f64 * graph.max_weight().ln()).exp(),
+            ),
+            None => bounds,
+        }
+    }
+    /// Approximate 95% confidence interval of the permanent, taking
+    /// `log_value` to be normal with standard deviation `rel_std_error`.
+    pub fn confidence_interval(&self) -> (f64, f64) {
+        let half_width = NORMAL_QUANTILE_95 * self.rel_std_error;
+        (
+            (self.log_value - half_width).exp(),
+            (self.log_value + half_width).exp(),
+        )
+    }
+    /// Check the estimate against its a priori bounds and against `criteria`.
+    pub fn check(&self, criteria: &ConvergenceCriteria) -> anyhow::Result<()> {
+        let (lower, upper) = self.bounds;
+        if !self.value.is_finite() || self.value < lower || self.value > upper {
+            anyhow::bail!(
+                "estimate {} is outside of the bounds [{}, {}]",
+                self.value,
+                lower,
+                upper
+            );
+        }
+        let ratio = self.effective_sample_size / self.nominal_sample_size.max(1) as f64;
+        // a NaN ratio, from a constant or infinite weight trace, fails too
+        if ratio.is_nan() || ratio < criteria.min_ess_ratio {
+            anyhow::bail!(
+                "effective sample size ratio {:.4} is below {:.4}",
+                ratio,
+                criteria.min_ess_ratio
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Certified lower bound on the permanent of the matrix with entries
+/// `weight(u, v)` on the edges of `graph`: the total weight of greedily found
+/// edge-disjoint perfect matchings. These are distinct terms of the permanent
+/// and all terms are nonnegative, so the sum never exceeds it.
+pub fn disjoint_matchings_lower_bound(graph: &Graph, weight: impl Fn(usize, usize) -> f64) -> f64 {
+    graph
+        .disjoint_matchings()
+        .iter()
+        .map(|x| x.edges.iter().map(|(u, v)| weight(*u, *v)).product::<f64>())
+        .sum()
+}
+
+/// Summary of a finished run, for sharing results.
+#[derive(Debug, Clone)]
+pub struct EstimatorReport {
+    /// estimate of the last attempt
+    pub estimate: Estimate,
+    /// beta the chains were cooled down to
+    pub beta: f64,
+    /// final weight matrix, in the labels the chains ran on
+    pub weight: Weights,
+    /// betas the chains were cooled through, in order
+    pub beta_steps: Vec<f64>,
+    /// wall-clock time of the whole run, including retries
+    pub elapsed: Duration,
+    /// why the run did not converge, `None` if it did
+    pub failure: Option<String>,
+    /// beta the cooling was resumed at without the steps before it, in which
+    /// case the estimate is the partial ratio `Z(beta) / Z(resumed_at)` of the
+    /// partition functions rather than the permanent
+    pub resumed_at: Option<f64>,
+}
+
+/// The JSON form of an [`EstimatorReport`].
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    estimate: f64,
+    log_estimate: f64,
+    /// `None` when infinite, which JSON cannot hold
+    rel_std_error: Option<f64>,
+    beta_steps: &'a [f64],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resumed_at: Option<f64>,
+}
+
+impl EstimatorReport {
+    pub fn converged(&self) -> bool {
+        self.failure.is_none()
+    }
+    /// `{"estimate", "log_estimate", "rel_std_error", "beta_steps"}` as a JSON
+    /// object, with a `null` relative error when it is infinite, and the
+    /// `"resumed_at"` beta of a partial ratio.
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        let report = JsonReport {
+            estimate: self.estimate.value,
+            log_estimate: self.estimate.log_value,
+            rel_std_error: Some(self.estimate.rel_std_error).filter(|x| x.is_finite()),
+            beta_steps: &self.beta_steps,
+            resumed_at: self.resumed_at,
+        };
+        simd_json::to_string(&report).map_err(Into::into)
+    }
+    /// Reciprocals of the final weights, row by row: the estimated share of
+    /// the matchings that use each edge, scaled so that the entries sum to `n`.
+    pub fn edge_frequencies(&self) -> Vec<Vec<f64>> {
+        self.weight
+            .to_rows()
+            .into_iter()
+            .map(|row| row.into_iter().map(|x| 1.0 / x).collect())
+            .collect()
+    }
+    /// Markdown table of the estimate, its diagnostics and the verdict.
+    pub fn to_markdown(&self) -> String {
+        let estimate = &self.estimate;
+        let mut table = String::from("| Quantity | Value |\n| --- | --- |\n");
+        let mut row = |name: &str, value: String| {
+            writeln!(table, "| {} | {} |", name, value).unwrap();
+        };
+        match self.resumed_at {
+            None => {
+                row("Estimate", format!("{:.5}", estimate.value));
+                row("ln(estimate)", format!("{:.5}", estimate.log_value));
+            }
+            Some(beta) => {
+                row(
+                    &format!("Partial ratio since beta = {:.5}", beta),
+                    format!("{:.5}", estimate.value),
+                );
+                row("ln(partial ratio)", format!("{:.5}", estimate.log_value));
+            }
+        }
+        let (low, high) = estimate.confidence_interval();
+        row(
+            "95% confidence interval",
+            format!("[{:.5}, {:.5}]", low, high),
+        );
+        row(
+            "Bounds",
+            format!("[{:.5}, {:.5}]", estimate.bounds.0, estimate.bounds.1),
+        );
+        row(
+            "Effective sample size",
+            format!(
+                "{:.1} of {}",
+                estimate.effective_sample_size, estimate.nominal_sample_size
+            ),
+        );
+        row(
+            "Acceptance rate",
+            format!("{:.3}", estimate.acceptance_rate),
+        );
+        row("R-hat", format!("{:.4}", estimate.r_hat));
+        row("Elapsed", format!("{:.3}s", self.elapsed.as_secs_f64()));
+        row(
+            "Warmup",
+            format!("{:.3}s", estimate.warmup_time.as_secs_f64()),
+        );
+        row(
+            "Cooling",
+            format!("{:.3}s", estimate.cooling_time.as_secs_f64()),
+        );
+        row(
+            "Converged",
+            match &self.failure {
+                None => "yes".to_string(),
+                Some(reason) => format!("no ({})", reason.replace('|', "\\|")),
+            },
+        );
+        table
+    }
+}
+
+/// Run `run` until `check` accepts its result, doubling the warmup (and the
+/// number of chains if `grow_chains` is set) after each failed attempt.
+/// Returns the result of the last attempt together with the verdict on it.
+pub fn run_with_retries<T>(
+    mut config: Config,
+    max_retries: usize,
+    grow_chains: bool,
+    mut run: impl FnMut(Config) -> anyhow::Result<T>,
+    check: impl Fn(&T) -> anyhow::Result<()>,
+) -> anyhow::Result<(T, anyhow::Result<()>)> {
+    let mut attempt = 1;
+    loop {
+        info!(
+            "attempt {} with warmup {} and {} chains",
+            attempt, config.warmup_times, config.num_of_chains
+        );
+        let result = run(config)?;
+        let e = match check(&result) {
+            Ok(()) => return Ok((result, Ok(()))),
+            Err(e) => e,
+        };
+        if attempt > max_retries {
+            let e = e.context(format!("not converged after {} attempts", attempt));
+            return Ok((result, Err(e)));
+        }
+        warn!("attempt {} did not converge: {}", attempt, e);
+        let double = |x: usize, what| {
+            x.checked_mul(2)
+                .ok_or_else(|| anyhow::anyhow!("cannot double the {} of {}", what, x))
+        };
+        config.warmup_times = double(config.warmup_times, "warmup")?;
+        if grow_chains {
+            config.num_of_chains = double(config.num_of_chains, "number of chains")?;
+        }
+        attempt += 1;
+    }
+}
+
+/// Run `run` until the relative standard error reported by `rel_error` is at
+/// most `target`, or `max_chains` chains did not suffice. The error shrinks
+/// like `1 / sqrt(num_of_chains)`, so each rerun takes the number of chains
+/// that would just meet the target, but at least twice as many as before.
+/// Returns the result of the last run and the number of chains it used.
+pub fn run_to_rel_error<T>(
+    mut config: Config,
+    target: f64,
+    max_chains: usize,
+    mut run: impl FnMut(Config) -> anyhow::Result<T>,
+    rel_error: impl Fn(&T) -> f64,
+) -> anyhow::Result<(T, usize)> {
+    loop {
+        let result = run(config)?;
+        let error = rel_error(&result);
+        if error <= target {
+            info!(
+                "relative error {:.5} within {} with {} chains",
+                error, target, config.num_of_chains
+            );
+            return Ok((result, config.num_of_chains));
+        }
+        if config.num_of_chains >= max_chains {
+            warn!(
+                "relative error {:.5} above {} with the maximum of {} chains",
+                error, target, config.num_of_chains
+            );
+            return Ok((result, config.num_of_chains));
+        }
+        let needed = (config.num_of_chains as f64 * (error / target).powi(2)).ceil() as usize;
+        let chains = needed.max(2 * config.num_of_chains).min(max_chains);
+        info!(
+            "relative error {:.5} above {} with {} chains, rerunning with {}",
+            error, target, config.num_of_chains, chains
+        );
+        config.num_of_chains = chains;
+    }
+}
+
+/// Warm up and cool down a fresh sampler on `graph`, returning the estimate.
+pub fn estimate_once<F: MetropolisFilter + Send + Sync + 'static>(
+    graph: Graph,
+    config: Config,
+    additive_ratio: NonZeroUsize,
+    multiplicative_ratio: NonZeroUsize,
+) -> f64 {
+    estimate_run::<F>(graph, config, additive_ratio, multiplicative_ratio).value
+}
+
+/// Like [`estimate_once`], with the logarithm and the error of the estimate.
+pub fn estimate_run<F: MetropolisFilter + Send + Sync + 'static>(
+    graph: Graph,
+    config: Config,
+    additive_ratio: NonZeroUsize,
+    multiplicative_ratio: NonZeroUsize,
+) -> PermanentEstimate {
+    let n = NonZeroUsize::new(graph.size).expect("graph must not be empty");
+    let scale = WeightScale::of(&graph);
+    let mut state = MCState::<F>::new(graph, config);
+    state.warmup();
+    let schedule = CoolingSchedule::from(CoolingConfig {
+        n,
+        additive_ratio,
+        multiplicative_ratio,
+    });
+    scale.apply(state.cooling_evolve(scale.schedule(schedule), false))
+}
+
+/// The sampler sees the weights of a weighted graph divided by the largest
+/// one (or the Gibbs factors of a cost matrix divided by the largest), which
+/// it reaches at `beta = 1` rather than at the end of the cooling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WeightScale {
+    /// stop the cooling at `beta = 1`
+    pub stop_at_one: bool,
+    /// `n ln(max(w))` (or `-n min(C) / T`), the logarithm of the factor the
+    /// scaling divided the permanent by
+    pub log_scale: f64,
+}
+
+impl WeightScale {
+    /// Scaling of `graph`, which stops at `beta = 1` if it is weighted.
+    pub fn of(graph: &Graph) -> Self {
+        WeightScale {
+            stop_at_one: graph.weights.is_some(),
+            log_scale: graph.size as f64 * graph.max_weight().ln(),
+        }
+    }
+    /// Scaling of the Gibbs matrix `exp(-C / T)` of `cost`, whose energies
+    /// [`State::from_cost`] shifts by the smallest cost.
+    ///
+    /// [`State::from_cost`]: crate::cooling_state::State::from_cost
+    pub fn of_cost(cost: &CostMatrix, temperature: f64) -> Self {
+        WeightScale {
+            stop_at_one: true,
+            log_scale: -(cost.size as f64) * cost.min_cost() / temperature,
+        }
+    }
+    /// Cut `schedule` at the first beta of at least 1 and end it at exactly 1
+    /// if the cooling stops there, or leave it alone.
+    pub fn schedule(&self, schedule: impl Schedule) -> impl Schedule {
+        let stop_at_one = self.stop_at_one;
+        schedule
+            .take_while(move |beta| !stop_at_one || *beta < 1.0)
+            .chain(stop_at_one.then_some(1.0))
+    }
+    /// Scale an estimate of the scaled permanent back up.
+    pub fn apply(&self, estimate: PermanentEstimate) -> PermanentEstimate {
+        let log_value = estimate.log_value + self.log_scale;
+        PermanentEstimate {
+            value: log_value.exp(),
+            log_value,
+            ..estimate
+        }
+    }
+}
+
+/// Geometric mean of the estimates of independent runs, given their
+/// logarithms, with the jackknife standard error of the mean logarithm. The
+/// error is infinite with fewer than two runs.
+pub fn jackknife(log_values: &[f64]) -> PermanentEstimate {
+    let r = log_values.len() as f64;
+    let total = log_values.iter().sum::<f64>();
+    let log_value = total / r;
+    let rel_std_error = if log_values.len() < 2 {
+        f64::INFINITY
+    } else {
+        // leave-one-out means, whose average is the full mean again
+        let squares = log_values
+            .iter()
+            .map(|x| ((total - x) / (r - 1.0) - log_value).powi(2))
+            .sum::<f64>();
+        ((r - 1.0) / r * squares).sqrt()
+    };
+    PermanentEstimate {
+        value: log_value.exp(),
+        log_value,
+        rel_std_error,
+        truncated: false,
+    }
+}
+
+/// Largest bias, relative to the mean, that still counts as none. Summing the
+/// log ratios of a run and exponentiating the sum loses about one ulp of the
+/// logarithm per step, far below `1e-12` for the small graphs that have an
+/// exact permanent to compare with, while sampling noise is orders of
+/// magnitude larger.
+const ROUNDING_TOLERANCE: f64 = 1e-12;
+
+/// Repeated-run statistics of one filter against the exact permanent.
+#[derive(Debug, Clone)]
+pub struct FilterComparison {
+    pub filter: &'static str,
+    pub mean: f64,
+    pub standard_error: f64,
+    /// mean minus the exact permanent
+    pub bias: f64,
+}
+
+impl FilterComparison {
+    /// Size of the bias measured in standard errors. A bias within the
+    /// rounding error of the log-space estimator, [`ROUNDING_TOLERANCE`],
+    /// counts as none.
+    pub fn bias_in_standard_errors(&self) -> f64 {
+        if self.bias.abs() <= ROUNDING_TOLERANCE * self.mean.abs() {
+            0.0
+        } else {
+            self.bias.abs() / self.standard_error
+        }
+    }
+    /// Whether the bias exceeds the standard error of the mean.
+    pub fn is_flagged(&self) -> bool {
+        self.bias_in_standard_errors() > 1.0
+    }
+}
+
+fn compare_filter<F: MetropolisFilter + Send + Sync + 'static>(
+    filter: &'static str,
+    graph: &Graph,
+    config: Config,
+    cooling: (NonZeroUsize, NonZeroUsize),
+    runs: usize,
+    exact: f64,
+) -> FilterComparison {
+    // a seeded comparison seeds its runs `seed`, `seed + 1`, ...
+    let estimates: Vec<f64> = (0..runs as u64)
+        .map(|r| {
+            let config = Config {
+                seed: config.seed.map(|x| x.wrapping_add(r)),
+                ..config
+            };
+            estimate_once::<F>(graph.clone(), config, cooling.0, cooling.1)
+        })
+        .collect();
+    let mean = estimates.iter().sum::<f64>() / runs as f64;
+    let variance = estimates
+        .iter()
+        .map(|x| (x - mean) * (x - mean))
+        .sum::<f64>()
+        / (runs.max(2) - 1) as f64;
+    FilterComparison {
+        filter,
+        mean,
+        standard_error: (variance / runs as f64).sqrt(),
+        bias: mean - exact,
+    }
+}
+
+/// Run every filter `runs` times on a small `graph` and compare the mean
+/// estimates against the exact permanent from Ryser's formula.
+pub fn compare_filters_exact(
+    graph: &Graph,
+    config: Config,
+    additive_ratio: NonZeroUsize,
+    multiplicative_ratio: NonZeroUsize,
+    runs: usize,
+) -> (f64, Vec<FilterComparison>) {
+    let exact = exact::ryser_permanent(graph);
+    let cooling = (additive_ratio, multiplicative_ratio);
+    let comparisons = vec![
+        compare_filter::<filter::Additive>("additive", graph, config, cooling, runs, exact),
+        compare_filter::<filter::Multiplicative>(
+            "multiplicative",
+            graph,
+            config,
+            cooling,
+            runs,
+            exact,
+        ),
+        compare_filter::<filter::Constant>("constant", graph, config, cooling, runs, exact),
+    ];
+    (exact, comparisons)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn jackknife_of_replicas() {
+        let combined = jackknife(&[1.0, 2.0, 3.0, 6.0]);
+        assert_eq!(combined.log_value, 3.0);
+        assert!((combined.value - 3f64.exp()).abs() < 1e-12);
+        // the jackknife error of a mean is the usual standard error
+        let standard_error = (14.0f64 / 3.0 / 4.0).sqrt();
+        assert!((combined.rel_std_error - standard_error).abs() < 1e-12);
+        assert_eq!(jackknife(&[2.0]).rel_std_error, f64::INFINITY);
+    }
+
+    #[test]
+    fn bounds_check() {
+        // two disjoint 4-cycles, 2 * 2 perfect matchings
+        let path: std::path::PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("4-cycles.json")).unwrap();
+        let n = NonZeroUsize::new(graph.size).unwrap();
+        let one = NonZeroUsize::new(1).unwrap();
+        let bounds = Estimate::graph_bounds(&graph);
+        let config = Config {
+            num_of_chains: 16,
+            warmup_times: 256,
+            weight_sample_intervals: 4,
+            num_of_weight_estimations: 64,
+            estimator_sample_intervals: 4,
+            num_of_estimator_estimations: 16,
+            seed: Some(3),
+            ..Config::default()
+        };
+        let mut state = MCState::<filter::Constant>::new(graph, config);
+        state.warmup();
+        let schedule = CoolingSchedule::from(CoolingConfig::new(n, one, one));
+        let permanent = state.cooling_evolve(schedule, false);
+        let estimate = state.estimate(permanent, bounds, Duration::ZERO, Duration::ZERO);
+        let criteria = |min_ess_ratio| ConvergenceCriteria { min_ess_ratio };
+        assert!(estimate.check(&criteria(0.0)).is_ok(), "{:?}", estimate);
+        // no run has an infinite effective sample size, nor a NaN one
+        let nan = Estimate {
+            effective_sample_size: f64::NAN,
+            ..estimate
+        };
+        assert!(nan.check(&criteria(0.0)).is_err());
+        assert!(estimate.check(&criteria(f64::INFINITY)).is_err());
+        // nor is it within the bounds [1, 1] of K_1
+        let misfit = Estimate {
+            bounds: Estimate::graph_bounds(&Graph::complete(1)),
+            ..estimate
+        };
+        assert!(misfit.check(&criteria(0.0)).is_err());
+    }
+
+    #[test]
+    fn lower_bound_below_exact() {
+        let path: std::path::PathBuf = env!("PWD").into();
+        for name in [
+            "2-cycles.json",
+            "4-cycles.json",
+            "box.json",
+            "complete.json",
+        ] {
+            let graph = Graph::load(path.join("data").join(name)).unwrap();
+            let bound = disjoint_matchings_lower_bound(&graph, |_, _| 1.0);
+            let exact = exact::ryser_permanent(&graph);
+            assert!(
+                1.0 <= bound && bound <= exact,
+                "{}: {} > {}",
+                name,
+                bound,
+                exact
+            );
+        }
+        let complete = Graph::complete(5);
+        assert_eq!(disjoint_matchings_lower_bound(&complete, |_, _| 1.0), 5.0);
+        assert_eq!(
+            disjoint_matchings_lower_bound(&complete, |_, _| 0.5),
+            5.0 / 32.0
+        );
+    }
+
+    #[test]
+    fn markdown_report() {
+        let estimate = Estimate {
+            value: 24.5,
+            log_value: 24.5f64.ln(),
+            rel_std_error: f64::INFINITY,
+            bounds: Estimate::binary_bounds(4),
+            effective_sample_size: 80.0,
+            nominal_sample_size: 100,
+            acceptance_rate: 0.25,
+            r_hat: 1.0125,
+            warmup_time: Duration::from_millis(250),
+            cooling_time: Duration::from_secs(2),
+            truncated: false,
+        };
+        let mut report = EstimatorReport {
+            estimate,
+            beta: 16.0,
+            weight: Weights::Dense(crate::cooling_state::Matrix::new(4, 1.0)),
+            beta_steps: vec![0.5, 1.0],
+            elapsed: Duration::from_secs(3),
+            failure: None,
+            resumed_at: None,
+        };
+        assert_eq!(report.edge_frequencies(), vec![vec![1.0; 4]; 4]);
+        let json = report.to_json().unwrap();
+        assert!(
+            json.starts_with(r#"{"estimate":24.5,"log_estimate":3.19"#),
+            "{}",
+            json
+        );
+        assert!(
+            json.ends_with(r#""rel_std_error":null,"beta_steps":[0.5,1.0]}"#),
+            "{}",
+            json
+        );
+        let markdown = report.to_markdown();
+        assert!(markdown.contains("| Estimate | 24.50000 |"), "{}", markdown);
+        assert!(markdown.contains("| Converged | yes |"), "{}", markdown);
+        assert!(markdown.contains("| R-hat | 1.0125 |"), "{}", markdown);
+        assert!(
+            markdown.contains("| 95% confidence interval | [0.00000, inf] |"),
+            "{}",
+            markdown
+        );
+        let partial = EstimatorReport {
+            resumed_at: Some(0.5),
+            ..report.clone()
+        };
+        assert!(partial
+            .to_json()
+            .unwrap()
+            .ends_with(r#""beta_steps":[0.5,1.0],"resumed_at":0.5}"#));
+        let markdown = partial.to_markdown();
+        assert!(
+            markdown.contains("| Partial ratio since beta = 0.50000 | 24.50000 |"),
+            "{}",
+            markdown
+        );
+        assert!(!markdown.contains("| Estimate |"), "{}", markdown);
+        // the log stays finite where the estimate overflows
+        let huge = EstimatorReport {
+            estimate: Estimate {
+                value: f64::INFINITY,
+                log_value: 1000.0,
+                ..estimate
+            },
+            ..report.clone()
+        };
+        let markdown = huge.to_markdown();
+        assert!(
+            markdown.contains("| ln(estimate) | 1000.00000 |"),
+            "{}",
+            markdown
+        );
+        let tight = EstimatorReport {
+            estimate: Estimate {
+                rel_std_error: 0.1,
+                ..estimate
+            },
+            ..report.clone()
+        };
+        assert!(
+            tight
+                .to_markdown()
+                .contains("| 95% confidence interval | [20.13937, 29.80480] |"),
+            "{}",
+            tight.to_markdown()
+        );
+        assert!(
+            markdown.contains("| Acceptance rate | 0.250 |"),
+            "{}",
+            markdown
+        );
+        report.failure = Some("estimate is outside of the bounds".to_string());
+        assert!(report
+            .to_markdown()
+            .contains("| Converged | no (estimate is outside of the bounds) |"));
+    }
+
+    #[test]
+    fn count_matchings_ignores_weights() {
+        // infinite costs are non-edges: the support is an 8-cycle with two perfect
+        // matchings, whose Gibbs weights only add up to about 0.0087
+        let inf = f64::INFINITY;
+        let cost = crate::graph::CostMatrix {
+            size: 4,
+            cost: [
+                [0.5, 3.0, inf, inf],
+                [inf, 2.0, 0.0, inf],
+                [inf, inf, 4.0, 1.5],
+                [0.25, inf, inf, 7.5],
+            ]
+            .iter()
+            .map(|row| row.to_vec().into_boxed_slice())
+            .collect(),
+        };
+        let support = cost.support();
+        assert_eq!(crate::exact::ryser_permanent(&support), 2.0);
+        let config = Config {
+            num_of_chains: 32,
+            warmup_times: 2048,
+            weight_sample_intervals: 8,
+            num_of_weight_estimations: 32,
+            estimator_sample_intervals: 8,
+            num_of_estimator_estimations: 8,
+            seed: Some(5),
+            ..Config::default()
+        };
+        let slow_down = NonZeroUsize::new(8).unwrap();
+        let count = estimate_once::<filter::Constant>(support, config, slow_down, slow_down);
+        // the short run is only trusted up to a factor of 4, as in the self-test,
+        // which still tells the count apart from the weighted sum and from 4!
+        assert!((count / 2.0).ln().abs() < 4f64.ln(), "count = {}", count);
+    }
+
+    #[test]
+    fn filters_are_unbiased_on_complete_graph() {
+        let path: std::path::PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("complete.json")).unwrap();
+        let config = Config {
+            num_of_chains: 8,
+            seed: Some(11),
+            ..Config::quick()
+        };
+        let one = NonZeroUsize::new(1).unwrap();
+        let (exact, comparisons) = compare_filters_exact(&graph, config, one, one, 4);
+        assert_eq!(exact, 120.0);
+        assert_eq!(comparisons.len(), 3);
+        for comparison in comparisons {
+            assert!(
+                comparison.bias_in_standard_errors() <= 3.0,
+                "{:?}",
+                comparison
+            );
+        }
+    }
+
+    #[test]
+    fn retry_doubles_warmup() {
+        // every swap on K_5 is accepted at beta = 0, and a chain holds in one
+        // of 5 steps, so a warmup of w steps turns each of the 5 matched edges
+        // over about 4w / 25 and at most w / 5 times; the chains count as mixed
+        // from a turnover of ln(5) ~ 1.61 on
+        let path: std::path::PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("complete.json")).unwrap();
+        let config = Config {
+            warmup_times: 8,
+            num_of_chains: 4,
+            seed: Some(1),
+            ..Config::default()
+        };
+        let mut attempts = Vec::new();
+        let warmup = |config: Config| {
+            let mut state = MCState::<filter::Constant>::new(graph.clone(), config);
+            Ok(state.warmup_with_stats())
+        };
+        let result = run_with_retries(
+            config,
+            3,
+            true,
+            |config| {
+                attempts.push((config.warmup_times, config.num_of_chains));
+                warmup(config)
+            },
+            |stats| match stats.mixed {
+                true => Ok(()),
+                false => anyhow::bail!("turnover {:.2} is too low", stats.turnover),
+            },
+        );
+        let (stats, verdict) = result.unwrap();
+        assert!(stats.mixed && stats.turnover <= 3.2, "{:?}", stats);
+        assert!(verdict.is_ok());
+        assert_eq!(attempts, vec![(8, 4), (16, 8)]);
+        let (stats, verdict) = run_with_retries(config, 2, false, warmup, |stats| {
+            anyhow::ensure!(stats.turnover >= 100.0, "turnover {:.2}", stats.turnover);
+            Ok(())
+        })
+        .unwrap();
+        // the third attempt warmed up for 32 steps
+        assert!(stats.turnover > 3.2 && stats.turnover <= 6.4, "{:?}", stats);
+        let error = format!("{:#}", verdict.unwrap_err());
+        assert!(
+            error.starts_with("not converged after 3 attempts"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn chains_grow_to_target() {
+        let config = Config {
+            num_of_chains: 4,
+            ..Config::default()
+        };
+        let mut chains = Vec::new();
+        // an error of 1 / sqrt(chains) needs 400 chains for a target of 0.05
+        let error = |config: &Config| 1.0 / (config.num_of_chains as f64).sqrt();
+        let (result, used) = run_to_rel_error(
+            config,
+            0.05,
+            65536,
+            |config| {
+                chains.push(config.num_of_chains);
+                Ok(config)
+            },
+            error,
+        )
+        .unwrap();
+        assert_eq!(chains, [4, 400]);
+        assert_eq!((result.num_of_chains, used), (400, 400));
+        // infinite errors grow straight to the cap
+        let (_, used) =
+            run_to_rel_error(config, 0.05, 100, Ok, |_: &Config| f64::INFINITY).unwrap();
+        assert_eq!(used, 100);
+        let (_, used) = run_to_rel_error(config, 0.05, 10, Ok, |_: &Config| 0.07).unwrap();
+        assert_eq!(used, 10);
+    }
+
+    #[test]
+    fn weighted_graph_run() {
+        let path: std::path::PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("weighted.json")).unwrap();
+        let exact = crate::exact::graph_permanent(&graph);
+        assert_eq!(exact, 51.5);
+        let config = Config {
+            num_of_chains: 64,
+            warmup_times: 512,
+            seed: Some(1),
+            ..Config::quick()
+        };
+        let slow_down = NonZeroUsize::new(8).unwrap();
+        let estimate = estimate_run::<crate::filter::Constant>(graph, config, slow_down, slow_down);
+        // ignoring the weights would estimate the 9 matchings, ln(51.5 / 9)
+        // ~ 1.7 off
+        assert!(estimate.rel_std_error < 0.2, "{:?}", estimate);
+        assert!(
+            (estimate.log_value - exact.ln()).abs() < 3.0 * estimate.rel_std_error,
+            "{:?} vs {}",
+            estimate,
+            exact
+        );
+    }
+}
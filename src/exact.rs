@@ -0,0 +1,424 @@
+use crate::{cooling_state::Matrix, graph::Graph};
+#[cfg(feature = "bigint")]
+use num_bigint::{BigInt, BigUint};
+#[cfg(feature = "complex")]
+use num_complex::Complex64;
+#[cfg(feature = "rational")]
+use num_rational::BigRational;
+use num_traits::{One, Zero};
+#[cfg(feature = "rational")]
+use serde::Deserialize;
+#[cfg(feature = "rational")]
+use std::{fs::File, io::Read, path::Path, str::FromStr};
+
+/// Entry type of a matrix whose permanent is taken by [`gray_code_ryser`].
+/// The arithmetic returns `None` on overflow, which only fixed-width integers
+/// do.
+trait RyserEntry: Clone + Zero + One {
+    fn try_add(&self, other: &Self) -> Option<Self>;
+    fn try_sub(&self, other: &Self) -> Option<Self>;
+    fn try_mul(&self, other: &Self) -> Option<Self>;
+}
+
+impl RyserEntry for i128 {
+    fn try_add(&self, other: &Self) -> Option<Self> {
+        self.checked_add(*other)
+    }
+    fn try_sub(&self, other: &Self) -> Option<Self> {
+        self.checked_sub(*other)
+    }
+    fn try_mul(&self, other: &Self) -> Option<Self> {
+        self.checked_mul(*other)
+    }
+}
+
+/// Entries whose arithmetic never fails: floats saturate to infinity and big
+/// numbers grow.
+macro_rules! unbounded_ryser_entry {
+    ($($(#[$attr:meta])* $t:ty),*) => {$(
+        $(#[$attr])*
+        impl RyserEntry for $t {
+            fn try_add(&self, other: &Self) -> Option<Self> {
+                Some(self + other)
+            }
+            fn try_sub(&self, other: &Self) -> Option<Self> {
+                Some(self - other)
+            }
+            fn try_mul(&self, other: &Self) -> Option<Self> {
+                Some(self * other)
+            }
+        }
+    )*};
+}
+
+unbounded_ryser_entry!(
+    f64,
+    #[cfg(feature = "complex")]
+    Complex64,
+    #[cfg(feature = "bigint")]
+    BigInt,
+    #[cfg(feature = "rational")]
+    BigRational
+);
+
+/// Largest dimension the Ryser routines accept.
+pub const MAX_RYSER_DIMENSION: usize = 30;
+
+fn check_ryser_dimension(n: usize) {
+    assert!(
+        n <= MAX_RYSER_DIMENSION,
+        "Ryser's formula needs 2^{} steps, only n <= {} is supported",
+        n,
+        MAX_RYSER_DIMENSION
+    );
+}
+
+/// Permanent of the `n x n` matrix given by its nonzero entries, as
+/// `columns[j]` of `(row, value)`, via Ryser's formula. The column subsets are
+/// visited in Gray-code order, so that each step updates the row sums by a
+/// single column, in `O(2^n * n)`. `None` if the arithmetic overflowed.
+fn gray_code_ryser<T: RyserEntry>(n: usize, columns: &[Vec<(usize, T)>]) -> Option<T> {
+    check_ryser_dimension(n);
+    let mut sums = vec![T::zero(); n];
+    let mut total = if n == 0 { T::one() } else { T::zero() };
+    for k in 1usize..(1 << n) {
+        let column = k.trailing_zeros() as usize;
+        // the Gray code of k differs from that of k - 1 in `column`
+        let gray = k ^ (k >> 1);
+        let added = gray & (1 << column) != 0;
+        for (row, x) in columns[column].iter() {
+            sums[*row] = if added {
+                sums[*row].try_add(x)
+            } else {
+                sums[*row].try_sub(x)
+            }?;
+        }
+        if sums.iter().any(Zero::is_zero) {
+            continue;
+        }
+        let product = sums.iter().try_fold(T::one(), |acc, x| acc.try_mul(x))?;
+        total = if gray.count_ones() as usize % 2 == n % 2 {
+            total.try_add(&product)
+        } else {
+            total.try_sub(&product)
+        }?;
+    }
+    Some(total)
+}
+
+/// Columns of the nonzero entries `entry(row, column)` of an `n x n` matrix,
+/// in the form [`gray_code_ryser`] takes.
+fn dense_columns<T: RyserEntry>(
+    n: usize,
+    entry: impl Fn(usize, usize) -> T,
+) -> Vec<Vec<(usize, T)>> {
+    (0..n)
+        .map(|j| {
+            (0..n)
+                .map(|i| (i, entry(i, j)))
+                .filter(|(_, x)| !x.is_zero())
+                .collect()
+        })
+        .collect()
+}
+
+/// Columns of the 0/1 adjacency matrix of `graph`, in the form
+/// [`gray_code_ryser`] takes.
+fn adjacency_columns<T: RyserEntry>(graph: &Graph) -> Vec<Vec<(usize, T)>> {
+    let mut columns = vec![Vec::new(); graph.size];
+    for (u, edges) in graph.edges.iter().enumerate() {
+        for v in edges.iter() {
+            columns[*v].push((u, T::one()));
+        }
+    }
+    columns
+}
+
+/// Exact permanent of the 0/1 adjacency matrix via Ryser's formula. Only
+/// meant for small graphs.
+pub fn ryser_permanent(graph: &Graph) -> f64 {
+    gray_code_ryser(graph.size, &adjacency_columns(graph)).expect("f64 does not overflow")
+}
+
+/// Exact permanent of a dense real matrix via Ryser's formula.
+pub fn ryser_permanent_matrix(matrix: &Matrix) -> f64 {
+    let n = matrix.dimension();
+    check_ryser_dimension(n);
+    let rows = matrix.rows().collect::<Vec<_>>();
+    gray_code_ryser(n, &dense_columns(n, |i, j| rows[i][j])).expect("f64 does not overflow")
+}
+
+/// Exact permanent of the row-major `n x n` matrix via Ryser's formula, in
+/// `O(2^n * n)`.
+pub fn permanent_ryser(matrix: &[f64], n: usize) -> f64 {
+    check_ryser_dimension(n);
+    assert_eq!(matrix.len(), n * n, "matrix is not {} x {}", n, n);
+    gray_code_ryser(n, &dense_columns(n, |i, j| matrix[i * n + j])).expect("f64 does not overflow")
+}
+
+/// Exact permanent of `graph`, weighted if it has weights, via
+/// [`permanent_ryser`] on its dense weight matrix.
+pub fn graph_permanent(graph: &Graph) -> f64 {
+    let n = graph.size;
+    let matrix: Vec<f64> = (0..n * n)
+        .map(|x| graph.edge_weight(x / n, x % n))
+        .collect();
+    permanent_ryser(&matrix, n)
+}
+
+/// Number of perfect matchings of `graph`, via Ryser's formula on its 0/1
+/// adjacency matrix in checked integer arithmetic. `None` once the count, or
+/// an intermediate sum, no longer fits; see [`permanent_bigint`] for those.
+pub fn permanent_01(graph: &Graph) -> Option<u64> {
+    let total = gray_code_ryser::<i128>(graph.size, &adjacency_columns(graph))?;
+    u64::try_from(total).ok()
+}
+
+/// Like [`permanent_01`], but accumulated in big integers, so the count stays
+/// exact once it exceeds what `u64` or the `f64` of [`ryser_permanent`] hold.
+#[cfg(feature = "bigint")]
+pub fn permanent_bigint(graph: &Graph) -> BigUint {
+    gray_code_ryser::<BigInt>(graph.size, &adjacency_columns(graph))
+        .and_then(|total| total.to_biguint())
+        .expect("the permanent of a 0/1 matrix is nonnegative")
+}
+
+/// Exact permanent of a square complex matrix via the Gray-code Ryser
+/// formula, in `O(2^n * n)`.
+///
+/// The MCMC estimator relies on nonnegative weights to define a probability
+/// distribution over matchings, so complex matrices (e.g. boson sampling
+/// amplitudes) can only go through this exact path.
+#[cfg(feature = "complex")]
+pub fn ryser_permanent_complex(matrix: &[Vec<Complex64>]) -> Complex64 {
+    let n = matrix.len();
+    check_ryser_dimension(n);
+    assert!(
+        matrix.iter().all(|row| row.len() == n),
+        "matrix is not square"
+    );
+    gray_code_ryser(n, &dense_columns(n, |i, j| matrix[i][j])).expect("f64 does not overflow")
+}
+
+/// Load a complex matrix given as a JSON array of rows of `[re, im]` pairs.
+#[cfg(feature = "complex")]
+pub fn load_complex_matrix<S: AsRef<std::path::Path>>(x: S) -> anyhow::Result<Vec<Vec<Complex64>>> {
+    let file = std::fs::File::open(x)?;
+    let rows: Vec<Vec<[f64; 2]>> = simd_json::from_reader(file)?;
+    let n = rows.len();
+    if let Some(i) = rows.iter().position(|row| row.len() != n) {
+        anyhow::bail!("row {} has {} entries, expected {}", i, rows[i].len(), n);
+    }
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            row.into_iter()
+                .map(|[re, im]| Complex64::new(re, im))
+                .collect()
+        })
+        .collect())
+}
+
+/// Neighbour and `"num/den"` weight of a rational edge.
+#[cfg(feature = "rational")]
+pub type RationalEdge = (usize, String);
+
+/// A bipartite graph whose edges carry exact rational weights, given in the
+/// input as `[v, "num/den"]` pairs.
+#[cfg(feature = "rational")]
+#[derive(Deserialize, Debug)]
+pub struct RationalGraph {
+    pub size: usize,
+    pub edges: Box<[Box<[RationalEdge]>]>,
+}
+
+#[cfg(feature = "rational")]
+impl RationalGraph {
+    pub fn load<S: AsRef<Path>>(x: S) -> anyhow::Result<Self> {
+        Self::from_reader(File::open(x)?)
+    }
+    pub fn from_reader<R: Read>(reader: R) -> anyhow::Result<Self> {
+        let graph: Self = simd_json::from_reader(reader)?;
+        graph.validate()?;
+        Ok(graph)
+    }
+    /// Check that there is one edge row per vertex and that every neighbour is
+    /// in range.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.edges.len() != self.size {
+            anyhow::bail!(
+                "{} edge rows for a graph of size {}",
+                self.edges.len(),
+                self.size
+            );
+        }
+        for (u, edges) in self.edges.iter().enumerate() {
+            if let Some((v, _)) = edges.iter().find(|(v, _)| *v >= self.size) {
+                anyhow::bail!("edge ({}, {}) is out of range", u, v);
+            }
+        }
+        Ok(())
+    }
+    /// Dense weight matrix of the graph; missing edges have weight zero.
+    pub fn weight_matrix(&self) -> anyhow::Result<Vec<Vec<BigRational>>> {
+        self.validate()?;
+        let mut matrix = vec![vec![BigRational::zero(); self.size]; self.size];
+        for (u, edges) in self.edges.iter().enumerate() {
+            for (v, weight) in edges.iter() {
+                matrix[u][*v] = BigRational::from_str(weight)
+                    .map_err(|e| anyhow::anyhow!("invalid weight {:?}: {}", weight, e))?;
+            }
+        }
+        Ok(matrix)
+    }
+}
+
+/// Exact weighted permanent via Ryser's formula, in `O(2^n * n)`.
+#[cfg(feature = "rational")]
+pub fn ryser_permanent_rational(graph: &RationalGraph) -> anyhow::Result<BigRational> {
+    let n = graph.size;
+    check_ryser_dimension(n);
+    let matrix = graph.weight_matrix()?;
+    Ok(
+        gray_code_ryser(n, &dense_columns(n, |i, j| matrix[i][j].clone()))
+            .expect("big rationals do not overflow"),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::graph::Graph;
+    use std::path::PathBuf;
+
+    fn load(name: &str) -> Graph {
+        let path: PathBuf = env!("PWD").into();
+        Graph::load(path.join("data").join(name)).unwrap()
+    }
+
+    #[test]
+    fn ryser_examples() {
+        // K_5 has 5! perfect matchings, a cycle of length 16 has exactly two
+        assert_eq!(super::ryser_permanent(&load("complete.json")), 120.0);
+        assert_eq!(super::ryser_permanent(&load("cycle.json")), 2.0);
+        assert_eq!(super::graph_permanent(&load("cycle.json")), 2.0);
+        assert_eq!(super::graph_permanent(&load("weighted.json")), 51.5);
+        assert_eq!(super::ryser_permanent(&load("box.json")), 1.0);
+    }
+
+    #[test]
+    fn gray_code_ryser() {
+        // box.json only admits the identity matching, K_5 has 5! of them
+        assert_eq!(super::permanent_01(&load("box.json")), Some(1));
+        assert_eq!(super::permanent_01(&load("complete.json")), Some(120));
+        assert_eq!(super::permanent_01(&load("cycle.json")), Some(2));
+        // 20! still fits into u64, 21! does not
+        let factorial = (1..=20u64).product::<u64>();
+        assert_eq!(super::permanent_01(&Graph::complete(20)), Some(factorial));
+        assert_eq!(super::permanent_01(&Graph::complete(21)), None);
+        let matrix = [1.0, 2.0, 0.0, 0.5, 1.0, 3.0, 0.0, 2.0, 1.5];
+        assert_eq!(super::permanent_ryser(&matrix, 3), 9.0);
+        let complete = vec![1.0; 36];
+        assert_eq!(super::permanent_ryser(&complete, 6), 720.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "n <= 30")]
+    fn ryser_rejects_large_matrices() {
+        super::permanent_ryser(&[], 31);
+    }
+
+    #[test]
+    fn ryser_dense_matrix() {
+        let mut matrix = crate::cooling_state::Matrix::new(3, 0.0);
+        for (i, row) in [[1.0, 2.0, 0.0], [0.5, 1.0, 3.0], [0.0, 2.0, 1.5]]
+            .iter()
+            .enumerate()
+        {
+            for (j, x) in row.iter().enumerate() {
+                matrix.set(i, j, *x);
+            }
+        }
+        // 1 * (1 * 1.5 + 3 * 2) + 2 * (0.5 * 1.5 + 3 * 0) = 9
+        assert_eq!(super::ryser_permanent_matrix(&matrix), 9.0);
+    }
+
+    #[cfg(feature = "complex")]
+    #[test]
+    fn complex_permanent() {
+        use num_complex::Complex64;
+        let c = Complex64::new;
+        let matrix = vec![
+            vec![c(1.0, 0.0), c(0.0, 1.0), c(0.0, 0.0)],
+            vec![c(0.0, 0.0), c(1.0, 0.0), c(0.0, 1.0)],
+            vec![c(0.0, 1.0), c(0.0, 0.0), c(1.0, 0.0)],
+        ];
+        // only the identity (1) and the 3-cycle (i^3 = -i) survive
+        assert_eq!(super::ryser_permanent_complex(&matrix), c(1.0, -1.0));
+        let matrix = vec![
+            vec![c(1.0, 2.0), c(3.0, -1.0)],
+            vec![c(0.0, 1.0), c(2.0, 0.0)],
+        ];
+        // (1 + 2i) * 2 + (3 - i) * i = 3 + 7i
+        assert_eq!(super::ryser_permanent_complex(&matrix), c(3.0, 7.0));
+    }
+
+    #[cfg(feature = "bigint")]
+    #[test]
+    fn bigint_permanent() {
+        use num_bigint::BigUint;
+        assert_eq!(
+            super::permanent_bigint(&Graph::complete(12)),
+            BigUint::from(479_001_600u32)
+        );
+        assert_eq!(
+            super::permanent_bigint(&load("cycle.json")),
+            BigUint::from(2u8)
+        );
+    }
+
+    #[cfg(feature = "rational")]
+    #[test]
+    fn half_integer_weights() {
+        use num_bigint::BigInt;
+        use num_rational::BigRational;
+        use num_traits::One;
+        let mut json = br#"{
+            "size": 3,
+            "edges": [
+                [[0, "1/2"], [1, "3/2"]],
+                [[0, "1"], [1, "5/2"], [2, "1/2"]],
+                [[1, "2"], [2, "3/2"]]
+            ]
+        }"#
+        .to_vec();
+        let graph: super::RationalGraph = simd_json::from_slice(&mut json).unwrap();
+        // 1/2 * (5/2 * 3/2 + 1/2 * 2) + 3/2 * (1 * 3/2 + 1/2 * 0) = 37/8
+        let expected = BigRational::new(BigInt::from(37), BigInt::from(8));
+        assert_eq!(super::ryser_permanent_rational(&graph).unwrap(), expected);
+        let mut json = br#"{"size": 0, "edges": []}"#.to_vec();
+        let graph: super::RationalGraph = simd_json::from_slice(&mut json).unwrap();
+        assert_eq!(
+            super::ryser_permanent_rational(&graph).unwrap(),
+            BigRational::one()
+        );
+    }
+
+    #[cfg(feature = "rational")]
+    #[test]
+    fn rational_graph_shape() {
+        let missing_row = br#"{"size": 3, "edges": [[[0, "1"]], [[1, "1"]]]}"#;
+        let error = super::RationalGraph::from_reader(&missing_row[..]).unwrap_err();
+        assert_eq!(error.to_string(), "2 edge rows for a graph of size 3");
+        let extra_row = br#"{"size": 1, "edges": [[[0, "1"]], [[0, "1"]]]}"#;
+        assert!(super::RationalGraph::from_reader(&extra_row[..]).is_err());
+        let out_of_range = br#"{"size": 1, "edges": [[[1, "1"]]]}"#;
+        let error = super::RationalGraph::from_reader(&out_of_range[..]).unwrap_err();
+        assert_eq!(error.to_string(), "edge (0, 1) is out of range");
+        // a graph built without `load` is still checked before it is used
+        let graph = super::RationalGraph {
+            size: 1,
+            edges: vec![vec![(0, "1".to_string())].into_boxed_slice(); 2].into_boxed_slice(),
+        };
+        assert!(super::ryser_permanent_rational(&graph).is_err());
+    }
+}
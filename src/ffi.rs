@@ -0,0 +1,176 @@
+//! C interface to the estimator, declared in `include/permanent.h`.
+//!
+//! The caller owns every buffer: the matrix is only read during the call and
+//! the result is written to `out_log`. Nothing is allocated for the caller, so
+//! there is nothing to free.
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::{graph::Graph, markov_chain::Config, Filter};
+
+/// The estimate was written to `out_log`.
+pub const PERMANENT_OK: i32 = 0;
+/// `matrix` or `out_log` is null.
+pub const PERMANENT_NULL_POINTER: i32 = 1;
+/// `n` or `chains` is zero, or an entry is negative or not finite.
+pub const PERMANENT_INVALID_ARGUMENT: i32 = 2;
+/// The matrix has no perfect matching, so its permanent is zero.
+pub const PERMANENT_NO_PERFECT_MATCHING: i32 = 3;
+/// The estimator panicked.
+pub const PERMANENT_INTERNAL_ERROR: i32 = 4;
+
+/// Estimate `graph` and write the logarithm of its permanent to `out_log`.
+///
+/// # Safety
+///
+/// `out_log` must be valid for a write of an `f64`.
+unsafe fn estimate_into(
+    graph: anyhow::Result<Graph>,
+    chains: usize,
+    seed: u64,
+    out_log: *mut f64,
+) -> i32 {
+    let Ok(graph) = graph else {
+        return PERMANENT_INVALID_ARGUMENT;
+    };
+    if graph.find_perfect_match().is_err() {
+        return PERMANENT_NO_PERFECT_MATCHING;
+    }
+    // a seeded call gives the same estimate whatever the thread count
+    let config = Config {
+        num_of_chains: chains,
+        seed: Some(seed),
+        deterministic_reduction: true,
+        ..Config::default()
+    };
+    // unwinding into the caller's frames is undefined behavior
+    match catch_unwind(AssertUnwindSafe(|| {
+        crate::estimate_permanent_with_error(&graph, &config, Filter::Additive)
+    })) {
+        Ok(estimate) => {
+            out_log.write(estimate.log_value);
+            PERMANENT_OK
+        }
+        Err(_) => PERMANENT_INTERNAL_ERROR,
+    }
+}
+
+/// Estimate the permanent of the row-major `n x n` nonnegative `matrix` with
+/// `chains` chains seeded by `seed`, writing its natural logarithm to
+/// `out_log` and returning one of the `PERMANENT_*` status codes.
+///
+/// # Safety
+///
+/// `matrix` must be null or valid for reads of `n * n` `f64`s, and `out_log`
+/// null or valid for a write of an `f64`.
+#[no_mangle]
+pub unsafe extern "C" fn permanent_estimate(
+    matrix: *const f64,
+    n: usize,
+    chains: usize,
+    seed: u64,
+    out_log: *mut f64,
+) -> i32 {
+    if matrix.is_null() || out_log.is_null() {
+        return PERMANENT_NULL_POINTER;
+    }
+    let Some(len) = n.checked_mul(n).filter(|_| n > 0 && chains > 0) else {
+        return PERMANENT_INVALID_ARGUMENT;
+    };
+    let data = std::slice::from_raw_parts(matrix, len);
+    estimate_into(Graph::from_weight_matrix(n, data), chains, seed, out_log)
+}
+
+/// Like [`permanent_estimate`], counting the perfect matchings of the 0/1
+/// `matrix`, of which every nonzero byte is an edge.
+///
+/// # Safety
+///
+/// `matrix` must be null or valid for reads of `n * n` bytes, and `out_log`
+/// null or valid for a write of an `f64`.
+#[no_mangle]
+pub unsafe extern "C" fn permanent_estimate_01(
+    matrix: *const u8,
+    n: usize,
+    chains: usize,
+    seed: u64,
+    out_log: *mut f64,
+) -> i32 {
+    if matrix.is_null() || out_log.is_null() {
+        return PERMANENT_NULL_POINTER;
+    }
+    let Some(len) = n.checked_mul(n).filter(|_| n > 0 && chains > 0) else {
+        return PERMANENT_INVALID_ARGUMENT;
+    };
+    let data: Vec<bool> = std::slice::from_raw_parts(matrix, len)
+        .iter()
+        .map(|x| *x != 0)
+        .collect();
+    estimate_into(
+        Graph::from_adjacency_matrix(n, &data),
+        chains,
+        seed,
+        out_log,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    extern "C" {
+        #[link_name = "permanent_estimate"]
+        fn raw_estimate(
+            matrix: *const f64,
+            n: usize,
+            chains: usize,
+            seed: u64,
+            out_log: *mut f64,
+        ) -> i32;
+        #[link_name = "permanent_estimate_01"]
+        fn raw_estimate_01(
+            matrix: *const u8,
+            n: usize,
+            chains: usize,
+            seed: u64,
+            out_log: *mut f64,
+        ) -> i32;
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut log = f64::NAN;
+        let path: std::path::PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("weighted.json")).unwrap();
+        let n = graph.size;
+        let dense: Vec<f64> = (0..n * n)
+            .map(|x| graph.edge_weight(x / n, x % n))
+            .collect();
+        let status = unsafe { raw_estimate(dense.as_ptr(), n, 4, 1, &mut log) };
+        assert_eq!(status, PERMANENT_OK);
+        let config = Config {
+            num_of_chains: 4,
+            seed: Some(1),
+            deterministic_reduction: true,
+            ..Config::default()
+        };
+        // the same run as the library's on the weighted graph, whose accuracy
+        // estimate::test::weighted_graph_run checks
+        let reference = crate::estimate_permanent_with_error(&graph, &config, Filter::Additive);
+        assert_eq!(log, reference.log_value);
+        let ones = [1; 4];
+        let status = unsafe { raw_estimate_01(ones.as_ptr(), 2, 4, 1, &mut log) };
+        assert_eq!(status, PERMANENT_OK);
+        assert!((log - 2f64.ln()).abs() < 1e-6, "log = {}", log);
+        let empty_row = [1, 1, 0, 0];
+        let status = unsafe { raw_estimate_01(empty_row.as_ptr(), 2, 4, 1, &mut log) };
+        assert_eq!(status, PERMANENT_NO_PERFECT_MATCHING);
+        let negative = [1.0, -1.0, 1.0, 1.0];
+        let status = unsafe { raw_estimate(negative.as_ptr(), 2, 4, 1, &mut log) };
+        assert_eq!(status, PERMANENT_INVALID_ARGUMENT);
+        let status = unsafe { raw_estimate(dense.as_ptr(), n, 0, 1, &mut log) };
+        assert_eq!(status, PERMANENT_INVALID_ARGUMENT);
+        let status = unsafe { raw_estimate(std::ptr::null(), 3, 4, 1, &mut log) };
+        assert_eq!(status, PERMANENT_NULL_POINTER);
+    }
+}
@@ -1,40 +1,170 @@
-use crate::{cooling_state::State, graph::Match};
-use rand::prelude::{IteratorRandom, SliceRandom};
+use crate::{
+    cooling_state::{CompensatedSum, State},
+    graph::Match,
+    kernel::{RotationKernel, ScanKernel, SwapKernel, TransitionKernel},
+    reservoir::Reservoir,
+};
+use rand::{
+    prelude::{IteratorRandom, SliceRandom},
+    Rng, SeedableRng,
+};
+use rand_chacha::ChaCha8Rng;
+use serde::Serialize;
+use std::num::NonZeroUsize;
 
-pub(crate) struct Additive;
+/// Random number generator owned by each chain.
+pub type ChainRng = ChaCha8Rng;
 
-pub(crate) struct Multiplicative;
+/// Generator of the chain with index `stream`: with a `seed` every chain gets
+/// its own stream of the same seeded generator, otherwise it is seeded from the
+/// operating system.
+pub fn chain_rng(seed: Option<u64>, stream: u64) -> ChainRng {
+    match seed {
+        Some(seed) => {
+            let mut rng = ChainRng::seed_from_u64(seed);
+            rng.set_stream(stream);
+            rng
+        }
+        None => ChainRng::from_entropy(),
+    }
+}
+
+pub struct Additive;
+
+pub struct Multiplicative;
+
+pub struct Constant;
+
+/// Smallest ratio between an updated weight sum of a chain and the largest
+/// term that went into the update, below which the sum is recomputed from
+/// scratch: smaller ratios have lost more than 20 of the 53 bits of an `f64`.
+const CANCELLATION_LIMIT: f64 = 1.0 / (1u64 << 20) as f64;
 
-pub(crate) struct Constant;
+/// Longest cycle of matched edges a single [`Proposal`] can rotate.
+pub const MAX_CYCLE_LEN: usize = 8;
 
-#[derive(Clone, Copy)]
+/// Rotate the edges at positions `p[0], ..., p[k - 1]`, `(u[i], v[i])`, into
+/// `(u[i], v[(i + 1) % k])`; for `k = 2` this swaps the endpoints of two edges.
+///
+/// A proposal always refers to distinct edges of a matching, so the removed
+/// and the added edges each have distinct `u` and distinct `v`; filters rely
+/// on this.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Proposal {
-    u1: usize,
-    v1: usize,
-    u2: usize,
-    v2: usize,
+    len: usize,
+    positions: [usize; MAX_CYCLE_LEN],
+    removed: [(usize, usize); MAX_CYCLE_LEN],
+    added: [(usize, usize); MAX_CYCLE_LEN],
+}
+
+impl Proposal {
+    /// Positions of the rotated edges in the matching, in cycle order.
+    pub fn positions(&self) -> &[usize] {
+        &self.positions[..self.len]
+    }
+    /// Swap of the edges at indices `p1` and `p2` of `matching`, or `None`
+    /// if the indices are equal.
+    pub fn new(matching: &Match, p1: usize, p2: usize) -> Option<Self> {
+        Self::cycle(matching, &[p1, p2])
+    }
+    /// Rotation of the edges at `positions` of `matching`, or `None` unless
+    /// there are between 2 and [`MAX_CYCLE_LEN`] distinct positions.
+    pub fn cycle(matching: &Match, positions: &[usize]) -> Option<Self> {
+        let len = positions.len();
+        if !(2..=MAX_CYCLE_LEN).contains(&len)
+            || (1..len).any(|i| positions[..i].contains(&positions[i]))
+        {
+            return None;
+        }
+        let mut proposal = Proposal {
+            len,
+            positions: [0; MAX_CYCLE_LEN],
+            removed: [(0, 0); MAX_CYCLE_LEN],
+            added: [(0, 0); MAX_CYCLE_LEN],
+        };
+        for (i, p) in positions.iter().copied().enumerate() {
+            let next = positions[(i + 1) % len];
+            proposal.positions[i] = p;
+            proposal.removed[i] = matching.edges[p];
+            proposal.added[i] = (matching.edges[p].0, matching.edges[next].1);
+        }
+        Some(proposal)
+    }
+    /// Edges leaving the matching, in cycle order.
+    pub fn removed(&self) -> &[(usize, usize)] {
+        &self.removed[..self.len]
+    }
+    /// Edges entering the matching; `added()[i]` takes the place of
+    /// `removed()[i]`.
+    pub fn added(&self) -> &[(usize, usize)] {
+        &self.added[..self.len]
+    }
+}
+
+/// Sum of `f` over `edges`, starting from `init`.
+fn sum_over(init: f64, edges: &[(usize, usize)], f: impl Fn(usize, usize) -> f64) -> f64 {
+    edges.iter().fold(init, |acc, &(u, v)| acc + f(u, v))
+}
+
+/// Metropolis acceptance probability `min(1, ratio * weight_ratio *
+/// exp(log_active_ratio + log_proposal_ratio))`, combined in log space so that
+/// a large `beta` cannot overflow an intermediate factor to infinity.
+pub fn acceptance_probability(
+    ratio: f64,
+    weight_ratio: f64,
+    log_active_ratio: f64,
+    log_proposal_ratio: f64,
+) -> f64 {
+    let log_probability = ratio.ln() + weight_ratio.ln() + log_active_ratio + log_proposal_ratio;
+    if log_probability.is_nan() {
+        return 0.0;
+    }
+    log_probability.min(0.0).exp()
 }
 
 pub trait MetropolisFilter {
     type MatchAttr: Send;
+    /// Attribute after replacing the `removed` edges by the `added` ones,
+    /// updated from the current `attr`; `total` is the cached weight sum of the
+    /// current matching.
+    fn next_attr(
+        attr: &Self::MatchAttr,
+        total: f64,
+        removed: &[(usize, usize)],
+        added: &[(usize, usize)],
+        state: &State,
+    ) -> Self::MatchAttr;
+    /// Filter ratio of replacing the `removed` edges by the `added` ones,
+    /// which takes the attribute from `attr` to `next_attr`.
     fn ratio(
         attr: &Self::MatchAttr,
-        matching: &Match,
-        proposal: &Proposal,
+        next_attr: &Self::MatchAttr,
+        removed: &[(usize, usize)],
+        added: &[(usize, usize)],
         state: &State,
-    ) -> (f64, Self::MatchAttr);
+    ) -> f64;
     fn initial_attr(matching: &Match, state: &State) -> Self::MatchAttr;
 }
 
 impl MetropolisFilter for Constant {
     type MatchAttr = ();
+    fn next_attr(
+        _attr: &Self::MatchAttr,
+        _total: f64,
+        _removed: &[(usize, usize)],
+        _added: &[(usize, usize)],
+        _state: &State,
+    ) -> Self::MatchAttr {
+    }
+
     fn ratio(
         _attr: &Self::MatchAttr,
-        _matching: &Match,
-        _proposal: &Proposal,
+        _next_attr: &Self::MatchAttr,
+        _removed: &[(usize, usize)],
+        _added: &[(usize, usize)],
         _state: &State,
-    ) -> (f64, Self::MatchAttr) {
-        (1.0, ())
+    ) -> f64 {
+        1.0
     }
 
     fn initial_attr(_matching: &Match, _state: &State) -> Self::MatchAttr {}
@@ -42,18 +172,32 @@ impl MetropolisFilter for Constant {
 
 impl MetropolisFilter for Additive {
     type MatchAttr = f64;
+    fn next_attr(
+        attr: &Self::MatchAttr,
+        _total: f64,
+        removed: &[(usize, usize)],
+        added: &[(usize, usize)],
+        state: &State,
+    ) -> Self::MatchAttr {
+        let weight = |u, v| state.weight_of_edge(u, v);
+        sum_over(
+            sum_over(*attr, removed, |u, v| -weight(u, v)),
+            added,
+            weight,
+        )
+    }
+
     fn ratio(
         attr: &Self::MatchAttr,
-        _matching: &Match,
-        proposal: &Proposal,
+        next_attr: &Self::MatchAttr,
+        removed: &[(usize, usize)],
+        added: &[(usize, usize)],
         state: &State,
-    ) -> (f64, Self::MatchAttr) {
-        let a = state.weight_of_edge(proposal.u1, proposal.v1);
-        let b = state.weight_of_edge(proposal.u2, proposal.v2);
-        let c = state.weight_of_edge(proposal.u1, proposal.v2);
-        let d = state.weight_of_edge(proposal.u2, proposal.v1);
-        let new_attr = *attr - a - b + c + d;
-        (new_attr / attr * (c + d) / (a + b), new_attr)
+    ) -> f64 {
+        let weight = |u, v| state.weight_of_edge(u, v);
+        let before = sum_over(0.0, removed, weight);
+        let after = sum_over(0.0, added, weight);
+        next_attr / attr * after / before
     }
 
     fn initial_attr(matching: &Match, state: &State) -> Self::MatchAttr {
@@ -67,27 +211,35 @@ impl MetropolisFilter for Additive {
 
 impl MetropolisFilter for Multiplicative {
     type MatchAttr = f64;
+    fn next_attr(
+        attr: &Self::MatchAttr,
+        total: f64,
+        removed: &[(usize, usize)],
+        added: &[(usize, usize)],
+        state: &State,
+    ) -> Self::MatchAttr {
+        let weight = |u, v| state.weight_of_edge(u, v);
+        let before = sum_over(0.0, removed, weight);
+        let after = sum_over(0.0, added, weight);
+        // attr is the square of the weight sum, so only the cross terms with
+        // the untouched edges and the rotated edges themselves change
+        let sum_without_rotated = sum_over(total, removed, |u, v| -weight(u, v));
+        *attr + 2.0 * sum_without_rotated * (after - before) + after * after - before * before
+    }
+
     fn ratio(
         attr: &Self::MatchAttr,
-        matching: &Match,
-        proposal: &Proposal,
+        next_attr: &Self::MatchAttr,
+        removed: &[(usize, usize)],
+        added: &[(usize, usize)],
         state: &State,
-    ) -> (f64, Self::MatchAttr) {
-        let a = state.weight_of_edge(proposal.u1, proposal.v1);
-        let b = state.weight_of_edge(proposal.u2, proposal.v2);
-        let c = state.weight_of_edge(proposal.u1, proposal.v2);
-        let d = state.weight_of_edge(proposal.u2, proposal.v1);
-        let mut new_attr = *attr;
-        for (i, j) in matching.edges.iter().copied().filter(|(i, j)| {
-            (*i != proposal.u1 || *j != proposal.v1) && (*i != proposal.u2 || *j != proposal.v2)
-        }) {
-            new_attr -= state.weight_of_edge(i, j) * a;
-            new_attr -= state.weight_of_edge(i, j) * b;
-            new_attr += state.weight_of_edge(i, j) * c;
-            new_attr += state.weight_of_edge(i, j) * d;
-        }
-        new_attr += c * c + d * d - a * a - b * b;
-        (new_attr / attr * (c * d) / (a * b), new_attr)
+    ) -> f64 {
+        let product = |edges: &[(usize, usize)]| {
+            edges
+                .iter()
+                .fold(1.0, |acc, &(u, v)| acc * state.weight_of_edge(u, v))
+        };
+        next_attr / attr * product(added) / product(removed)
     }
 
     fn initial_attr(matching: &Match, state: &State) -> Self::MatchAttr {
@@ -102,30 +254,128 @@ impl MetropolisFilter for Multiplicative {
     }
 }
 
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct TrajectoryPoint {
+    pub step: usize,
+    pub weight: f64,
+    pub active_count: usize,
+}
+
+/// Thinned record of a chain's weight and active count over its transitions.
+pub struct Trajectory {
+    thin: NonZeroUsize,
+    step: usize,
+    pub points: Vec<TrajectoryPoint>,
+}
+
+impl Trajectory {
+    pub fn new(thin: NonZeroUsize) -> Self {
+        Trajectory {
+            thin,
+            step: 0,
+            points: Vec::new(),
+        }
+    }
+}
+
+/// Matchings of a chain's estimator samples, subsampled by a reservoir with a
+/// generator of its own so that recording leaves the chain's stream alone.
+pub struct SampleRecorder {
+    pub reservoir: Reservoir<Vec<(usize, usize)>>,
+    pub rng: ChainRng,
+}
+
 pub struct AugmentedMatch<T: MetropolisFilter> {
     pub matching: Match,
     pub attr: T::MatchAttr,
     pub weight: f64,
     pub active_count: usize,
+    pub energy: f64,
+    pub trajectory: Option<Trajectory>,
+    /// matchings recorded at the accepted estimator samples
+    pub samples: Option<SampleRecorder>,
+    /// deterministic proposals replacing the random edge pair choice
+    pub scan: Option<ScanKernel>,
+    /// number of matched edges rotated by a random move
+    pub move_size: usize,
+    pub rng: ChainRng,
+    /// Metropolis moves proposed and accepted since the last reset
+    pub proposed: usize,
+    pub accepted: usize,
 }
 
 impl<T: MetropolisFilter> AugmentedMatch<T> {
-    pub fn choose_weighted_edge(&self, state: &State) -> (usize, usize) {
-        let mut rng = rand::thread_rng();
+    /// Chain starting at `matching`, with its attribute, weight and energy
+    /// computed under `state`.
+    pub fn new(matching: Match, state: &State, move_size: usize, rng: ChainRng) -> Self {
+        AugmentedMatch {
+            attr: T::initial_attr(&matching, state),
+            weight: state.weight_of_match(&matching),
+            active_count: state.active_count_of_match(&matching),
+            energy: state.energy_of_match(&matching),
+            matching,
+            trajectory: None,
+            samples: None,
+            scan: None,
+            move_size,
+            rng,
+            proposed: 0,
+            accepted: 0,
+        }
+    }
+    pub fn choose_weighted_edge(&mut self, state: &State) -> (usize, usize) {
         self.matching
             .edges
-            .choose_weighted(&mut rng, |x| state.weight_of_edge(x.0, x.1))
+            .choose_weighted(&mut self.rng, |x| state.weight_of_edge(x.0, x.1))
             .copied()
             .expect("failed to choose weighted edge")
     }
 
-    pub fn choose_edge_pairs(&self) -> (usize, usize) {
-        let indices = (0..self.matching.edges.len()).choose_multiple(&mut rand::thread_rng(), 2);
+    pub fn choose_edge_pairs(&mut self) -> (usize, usize) {
+        let indices = (0..self.matching.edges.len()).choose_multiple(&mut self.rng, 2);
         (indices[0], indices[1])
     }
     pub fn transit_n_times(&mut self, state: &State, n: usize) {
+        if self.trajectory.is_some() {
+            return self.transit_n_times_recorded(state, n);
+        }
         for _ in 0..n {
-            self.transit(self.choose_edge_pairs(), state);
+            self.step(state);
+        }
+    }
+    fn step(&mut self, state: &State) -> bool {
+        match self.scan.as_mut() {
+            Some(scan) => {
+                let proposal = scan.propose(&self.matching, &mut self.rng);
+                self.apply(proposal, 0.0, state)
+            }
+            // a swap is an odd permutation, so a chain that keeps swapping
+            // alternates the parity of its matching and, thinned at even
+            // intervals, never forgets the parity it started with; holding
+            // with probability 1/n makes the chain aperiodic
+            None => {
+                let n = self.matching.edges.len();
+                if n < 2 || self.rng.gen_range(0..n) == 0 {
+                    return false;
+                }
+                self.transit_k(self.move_size, state)
+            }
+        }
+    }
+    fn transit_n_times_recorded(&mut self, state: &State, n: usize) {
+        for _ in 0..n {
+            self.step(state);
+            let (weight, active_count) = (self.weight, self.active_count);
+            if let Some(trajectory) = self.trajectory.as_mut() {
+                trajectory.step += 1;
+                if trajectory.step % trajectory.thin == 0 {
+                    trajectory.points.push(TrajectoryPoint {
+                        step: trajectory.step,
+                        weight,
+                        active_count,
+                    });
+                }
+            }
         }
     }
     pub fn num_of_non_edges(&self, state: &State) -> usize {
@@ -135,46 +385,325 @@ impl<T: MetropolisFilter> AugmentedMatch<T> {
             .filter(|(u, v)| state.activity_of_edge(*u, *v) == 0)
             .count()
     }
-    pub fn rejection_sample(&mut self, state: &State, n: usize) -> Option<usize> {
+    /// Draw a matching proportional to its activity alone and return its energy.
+    pub fn rejection_sample(&mut self, state: &State, n: usize) -> Option<f64> {
+        self.rejection_sample_with(state, n, |_, _, _| {})
+    }
+    /// Like [`AugmentedMatch::rejection_sample`], but show every attempt to
+    /// `attempt` along with its acceptance probability and whether it was
+    /// accepted.
+    pub fn rejection_sample_with(
+        &mut self,
+        state: &State,
+        n: usize,
+        mut attempt: impl FnMut(&Self, f64, bool),
+    ) -> Option<f64> {
         for _ in 0..2 * state.weight.dimension() * state.weight.dimension() {
             self.transit_n_times(state, n);
-            if rand::random::<f64>() < 1.0 / self.weight as f64 + 2.0 * f64::EPSILON {
-                return Some(state.weight.dimension() - self.active_count);
+            let probability = (1.0 / self.weight + 2.0 * f64::EPSILON).min(1.0);
+            let accepted = self.rng.gen::<f64>() < probability;
+            attempt(self, probability, accepted);
+            if accepted {
+                if let Some(samples) = self.samples.as_mut() {
+                    samples
+                        .reservoir
+                        .push(self.matching.edges.to_vec(), &mut samples.rng);
+                }
+                return Some(self.energy);
             }
         }
         None
     }
     pub fn transit(&mut self, position: (usize, usize), state: &State) -> bool {
-        let proposal = Proposal {
-            u1: self.matching.edges[position.0].0,
-            v1: self.matching.edges[position.0].1,
-            u2: self.matching.edges[position.1].0,
-            v2: self.matching.edges[position.1].1,
+        match Proposal::new(&self.matching, position.0, position.1) {
+            Some(proposal) => self.apply(proposal, 0.0, state),
+            None => false,
+        }
+    }
+    /// Take one Metropolis step rotating `k` uniformly chosen matched edges;
+    /// `k = 2` is the plain edge pair swap.
+    pub fn transit_k(&mut self, k: usize, state: &State) -> bool {
+        if k == 2 {
+            self.transit_with(&mut SwapKernel, state)
+        } else {
+            self.transit_with(&mut RotationKernel::new(k), state)
+        }
+    }
+    /// Take one Metropolis step with a move proposed by `kernel`.
+    pub fn transit_with<K: TransitionKernel>(&mut self, kernel: &mut K, state: &State) -> bool {
+        let proposal = kernel.propose(&self.matching, &mut self.rng);
+        let log_proposal_ratio = kernel.log_proposal_ratio(&self.matching, &proposal);
+        self.apply(proposal, log_proposal_ratio, state)
+    }
+    /// Fraction of the proposed moves accepted since the last reset.
+    pub fn acceptance_rate(&self) -> f64 {
+        self.accepted as f64 / self.proposed as f64
+    }
+    pub fn reset_acceptance(&mut self) {
+        self.proposed = 0;
+        self.accepted = 0;
+    }
+    /// Accept or reject `proposal`, correcting for an asymmetric kernel by
+    /// `log_proposal_ratio`.
+    pub fn apply(&mut self, proposal: Proposal, log_proposal_ratio: f64, state: &State) -> bool {
+        self.proposed += 1;
+        let (removed, added) = (proposal.removed(), proposal.added());
+        let mut new_attr = T::next_attr(&self.attr, self.weight, removed, added, state);
+        let mut next_weight = sum_over(
+            sum_over(self.weight, removed, |u, v| -state.weight_of_edge(u, v)),
+            added,
+            |u, v| state.weight_of_edge(u, v),
+        );
+        // the weights of rarely visited edges grow huge, and swapping one of
+        // them out of the cached sum, or out of the attribute derived from it,
+        // cancels most of its digits
+        let largest = removed
+            .iter()
+            .chain(added)
+            .map(|&(u, v)| state.weight_of_edge(u, v))
+            .fold(self.weight, f64::max);
+        if next_weight < largest * CANCELLATION_LIMIT {
+            let mut next = self.matching.clone();
+            for (p, edge) in proposal.positions().iter().zip(added) {
+                next.edges[*p] = *edge;
+            }
+            next_weight = next
+                .edges
+                .iter()
+                .map(|&(u, v)| state.weight_of_edge(u, v))
+                .sum::<CompensatedSum>()
+                .value();
+            new_attr = T::initial_attr(&next, state);
+        }
+        let ratio = T::ratio(&self.attr, &new_attr, removed, added, state);
+        let next_active_count = added.iter().fold(
+            removed.iter().fold(self.active_count, |acc, &(u, v)| {
+                acc - state.activity_of_edge(u, v)
+            }),
+            |acc, &(u, v)| acc + state.activity_of_edge(u, v),
+        );
+        let next_energy = sum_over(
+            sum_over(self.energy, removed, |u, v| -state.energy_of_edge(u, v)),
+            added,
+            |u, v| state.energy_of_edge(u, v),
+        );
+        let probability = acceptance_probability(
+            ratio,
+            next_weight / self.weight,
+            -state.beta * (next_energy - self.energy),
+            log_proposal_ratio,
+        );
+        let threshold = match state.acceptance_threshold {
+            Some(threshold) => threshold,
+            None => self.rng.gen::<f64>(),
         };
-        let (ratio, new_attr) = T::ratio(&self.attr, &self.matching, &proposal, state);
-        let next_weight = self.weight
-            - state.weight_of_edge(proposal.u1, proposal.v1)
-            - state.weight_of_edge(proposal.u2, proposal.v2)
-            + state.weight_of_edge(proposal.u1, proposal.v2)
-            + state.weight_of_edge(proposal.u2, proposal.v1);
-        let next_active_count = self.active_count
-            - state.activity_of_edge(proposal.u1, proposal.v1)
-            - state.activity_of_edge(proposal.u2, proposal.v2)
-            + state.activity_of_edge(proposal.u1, proposal.v2)
-            + state.activity_of_edge(proposal.u2, proposal.v1);
-        let weight_ratio = next_weight / self.weight;
-        let active_ratio =
-            (state.beta * (next_active_count as isize - self.active_count as isize) as f64).exp();
-        let probability = (ratio * weight_ratio * active_ratio).min(1.0);
-        if rand::random::<f64>() < probability {
-            self.matching.edges[position.0] = (proposal.u1, proposal.v2);
-            self.matching.edges[position.1] = (proposal.u2, proposal.v1);
+        if threshold < probability {
+            for (p, edge) in proposal.positions().iter().zip(added) {
+                self.matching.edges[*p] = *edge;
+            }
             self.attr = new_attr;
             self.weight = next_weight;
             self.active_count = next_active_count;
+            self.energy = next_energy;
+            self.accepted += 1;
             true
         } else {
             false
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        acceptance_probability, chain_rng, Additive, AugmentedMatch, Constant, MetropolisFilter,
+        Multiplicative, Proposal,
+    };
+    use crate::{
+        cooling_state::State,
+        graph::{Graph, Match},
+    };
+    use rand::Rng;
+
+    #[test]
+    fn acceptance_at_large_beta() {
+        let beta = 1e6;
+        // exp(beta) overflows, which used to turn a zero weight ratio into NaN
+        // and the NaN into an accepted move
+        assert_eq!(acceptance_probability(1.0, 0.0, beta, 0.0), 0.0);
+        assert_eq!(acceptance_probability(1.0, 0.5, beta, 0.0), 1.0);
+        assert_eq!(acceptance_probability(1.0, 2.0, -beta, 0.0), 0.0);
+        let expected = (0.25f64.ln() + 2.0f64.ln() - 0.5).exp();
+        assert!((acceptance_probability(0.25, 2.0, -0.5, 0.0) - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn chain_streams() {
+        let draw = |stream| -> Vec<u64> {
+            let mut rng = chain_rng(Some(5), stream);
+            (0..8).map(|_| rng.gen()).collect()
+        };
+        // the same chain replays its stream, neighbouring chains do not share it
+        assert_eq!(draw(0), draw(0));
+        assert_ne!(draw(0), draw(1));
+        assert_ne!(draw(1), draw(2));
+    }
+
+    #[test]
+    fn transitions_at_large_beta() {
+        let path: std::path::PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("box.json")).unwrap();
+        let mut state = State::from(&graph);
+        state.beta = 1e6;
+        let mut rng = chain_rng(Some(8), 0);
+        let matching = Match::random(graph.size, &mut rng);
+        let mut chain = AugmentedMatch::<Constant>::new(matching, &state, 2, rng);
+        let start = chain.energy;
+        for _ in 0..1000 {
+            let energy = chain.energy;
+            let (p1, p2) = chain.choose_edge_pairs();
+            // exp(1e6 * delta) overflows, but a move adding non-edges must
+            // still be rejected and one removing them accepted
+            chain.transit((p1, p2), &state);
+            assert!(chain.energy <= energy);
+        }
+        assert!(chain.energy < start);
+        assert_eq!(chain.energy, 0.0);
+    }
+
+    #[test]
+    fn proposal_requires_distinct_edges() {
+        let matching = Match {
+            edges: vec![(0, 2), (1, 0), (2, 1)].into_boxed_slice(),
+        };
+        assert!(Proposal::new(&matching, 1, 1).is_none());
+        let proposal = Proposal::new(&matching, 0, 2).unwrap();
+        assert_eq!(proposal.positions(), [0, 2]);
+        assert_eq!(proposal.removed(), [(0, 2), (2, 1)]);
+        assert_eq!(proposal.added(), [(0, 1), (2, 2)]);
+        assert!(Proposal::cycle(&matching, &[0]).is_none());
+        assert!(Proposal::cycle(&matching, &[2, 0, 2]).is_none());
+        let rotation = Proposal::cycle(&matching, &[2, 0, 1]).unwrap();
+        assert_eq!(rotation.added(), [(2, 2), (0, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn rotations_keep_attrs_consistent() {
+        let path: std::path::PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("complete.json")).unwrap();
+        let n = graph.size;
+        // a huge weight cancels the cached sums whenever it is rotated out, so
+        // with it every move is accepted to rotate it in and out, which a
+        // cancelled attribute would reject by turning the ratio negative
+        for huge in [None, Some(1e12)] {
+            let mut state = State::from(&graph);
+            let mut rng = chain_rng(Some(9), 0);
+            for u in 0..n {
+                for v in 0..n {
+                    state.weight.set(u, v, rng.gen_range(0.5..2.0));
+                }
+            }
+            if let Some(huge) = huge {
+                state.weight.set(0, 0, huge);
+                state.acceptance_threshold = Some(0.0);
+            }
+            let matching = Match::random(n, &mut rng);
+            let mut chain = AugmentedMatch::<Additive>::new(matching, &state, 3, rng);
+            chain.transit_n_times(&state, 1000);
+            assert!(chain.accepted > 0);
+            if huge.is_some() {
+                assert_eq!(chain.accepted, chain.proposed);
+            }
+            let mut columns: Vec<_> = chain.matching.edges.iter().map(|e| e.1).collect();
+            columns.sort_unstable();
+            assert_eq!(columns, (0..n).collect::<Vec<_>>());
+            let expected = Additive::initial_attr(&chain.matching, &state);
+            assert!((chain.attr - expected).abs() < 1e-9 * expected);
+            assert!(
+                (chain.weight - state.weight_of_match(&chain.matching)).abs() < 1e-9 * expected
+            );
+        }
+    }
+
+    #[test]
+    fn multiplicative_attr_tracks_recomputation() {
+        let path: std::path::PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("complete.json")).unwrap();
+        let n = graph.size;
+        for huge in [None, Some(1e12)] {
+            let mut state = State::from(&graph);
+            let mut rng = chain_rng(Some(3), 0);
+            for u in 0..n {
+                for v in 0..n {
+                    state.weight.set(u, v, rng.gen_range(0.5..2.0));
+                }
+            }
+            if let Some(huge) = huge {
+                state.weight.set(0, 0, huge);
+                state.acceptance_threshold = Some(0.0);
+            }
+            let matching = Match::random(n, &mut rng);
+            let mut chain = AugmentedMatch::<Multiplicative>::new(matching, &state, 2, rng);
+            let mut accepted = 0;
+            for _ in 0..1000 {
+                let (p1, p2) = chain.choose_edge_pairs();
+                accepted += chain.transit((p1, p2), &state) as usize;
+            }
+            assert!(accepted > 0);
+            if huge.is_some() {
+                // a cancelled attribute turns the ratio of some moves negative
+                assert_eq!(accepted, 1000);
+            }
+            assert_eq!(chain.accepted, accepted);
+            assert_eq!(chain.proposed, 1000);
+            assert_eq!(chain.acceptance_rate(), accepted as f64 / 1000.0);
+            let expected = Multiplicative::initial_attr(&chain.matching, &state);
+            assert!((chain.attr - expected).abs() < 1e-9 * expected);
+        }
+    }
+
+    /// Parity of the permutation taking each row to its matched column.
+    fn is_odd(matching: &Match) -> bool {
+        let n = matching.edges.len();
+        let mut perm = vec![0; n];
+        for &(u, v) in matching.edges.iter() {
+            perm[u] = v;
+        }
+        let mut seen = vec![false; n];
+        let mut cycles = 0;
+        for start in 0..n {
+            if !seen[start] {
+                cycles += 1;
+                let mut u = start;
+                while !seen[u] {
+                    seen[u] = true;
+                    u = perm[u];
+                }
+            }
+        }
+        (n - cycles) % 2 == 1
+    }
+
+    #[test]
+    fn chains_forget_their_parity() {
+        let path: std::path::PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("complete.json")).unwrap();
+        let state = State::from(&graph);
+        // every swap on K_5 is accepted at beta = 0 and flips the parity, so
+        // without holding chains thinned at even intervals all stay even
+        let flipped = (0..400)
+            .filter(|stream| {
+                let rng = chain_rng(Some(5), *stream);
+                let mut chain = AugmentedMatch::<Constant>::new(graph.find_match(), &state, 2, rng);
+                let start = is_odd(&chain.matching);
+                chain.transit_n_times(&state, 16);
+                is_odd(&chain.matching) != start
+            })
+            .count();
+        assert!(
+            (150..250).contains(&flipped),
+            "{} of 400 chains flipped",
+            flipped
+        );
+    }
+}
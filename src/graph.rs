@@ -1,14 +1,186 @@
 use crate::dinic::DinicGraph;
-use rand::seq::SliceRandom;
-use serde::Deserialize;
-use std::{fs::File, path::Path};
+use crate::input::decompress;
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fs::File, io::Read, path::Path};
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Graph {
     pub size: usize,
     pub edges: Box<[Box<[usize]>]>,
+    /// nonnegative weight of every edge, parallel to `edges`; an unweighted
+    /// graph counts its perfect matchings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weights: Option<Box<[Box<[f64]>]>>,
 }
 
+/// Dense real cost matrix of an assignment problem.
+#[derive(Deserialize, Debug, Clone)]
+pub struct CostMatrix {
+    pub size: usize,
+    pub cost: Box<[Box<[f64]>]>,
+}
+
+impl CostMatrix {
+    pub fn load<S: AsRef<Path>>(x: S) -> anyhow::Result<Self> {
+        Self::from_reader(decompress(File::open(x)?)?)
+    }
+    pub fn from_reader<R: Read>(reader: R) -> anyhow::Result<Self> {
+        let matrix: Self = simd_json::from_reader(reader)?;
+        if matrix.cost.len() != matrix.size || matrix.cost.iter().any(|x| x.len() != matrix.size) {
+            anyhow::bail!("cost matrix is not {0}x{0}", matrix.size);
+        }
+        Ok(matrix)
+    }
+    /// Bounds of the permanent of `exp(-C / T)`: each of the `n!` assignments
+    /// contributes between the n-th powers of the smallest and largest entry.
+    pub fn gibbs_bounds(&self, temperature: f64) -> (f64, f64) {
+        let entries = || self.cost.iter().flatten().map(|c| (-c / temperature).exp());
+        let factorial = (1..=self.size).map(|x| x as f64).product::<f64>();
+        let n = self.size as i32;
+        (
+            factorial * entries().fold(f64::INFINITY, f64::min).powi(n),
+            factorial * entries().fold(0.0, f64::max).powi(n),
+        )
+    }
+    /// Smallest finite cost, or zero if there is none.
+    pub fn min_cost(&self) -> f64 {
+        let min = self
+            .cost
+            .iter()
+            .flatten()
+            .copied()
+            .filter(|c| c.is_finite())
+            .fold(f64::INFINITY, f64::min);
+        if min.is_finite() {
+            min
+        } else {
+            0.0
+        }
+    }
+    /// Support of the matrix weighted by the Gibbs factors `exp(-c / T)`.
+    pub fn gibbs_graph(&self, temperature: f64) -> Graph {
+        let support = self.support();
+        let weights = support
+            .edges
+            .iter()
+            .zip(self.cost.iter())
+            .map(|(edges, row)| {
+                edges
+                    .iter()
+                    .map(|v| (-row[*v] / temperature).exp())
+                    .collect()
+            })
+            .collect();
+        Graph {
+            weights: Some(weights),
+            ..support
+        }
+    }
+    /// 0/1 topology of the matrix: every finite cost is an edge of weight one.
+    pub fn support(&self) -> Graph {
+        Graph {
+            size: self.size,
+            weights: None,
+            edges: self
+                .cost
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .enumerate()
+                        .filter(|(_, c)| c.is_finite())
+                        .map(|(v, _)| v)
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Algorithm computing a maximum matching.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchingAlgorithm {
+    /// unit-capacity max flow through [`DinicGraph`]
+    #[default]
+    Dinic,
+    /// dedicated `O(E * sqrt(V))` bipartite matching, without a flow network
+    HopcroftKarp,
+}
+
+/// Maximum matching of `graph` by Hopcroft–Karp: each phase layers the graph
+/// by a BFS from the free rows and then augments along vertex-disjoint
+/// shortest paths, found by an iterative DFS.
+pub fn hopcroft_karp(graph: &Graph) -> Match {
+    const NIL: usize = usize::MAX;
+    let n = graph.size;
+    let mut pair_u = vec![NIL; n];
+    let mut pair_v = vec![NIL; n];
+    let mut dist = vec![NIL; n];
+    let mut cursor = vec![0; n];
+    let mut queue = std::collections::VecDeque::new();
+    loop {
+        for u in 0..n {
+            dist[u] = if pair_u[u] == NIL {
+                queue.push_back(u);
+                0
+            } else {
+                NIL
+            };
+        }
+        let mut found = false;
+        while let Some(u) = queue.pop_front() {
+            for v in graph.edges[u].iter().copied() {
+                match pair_v[v] {
+                    NIL => found = true,
+                    w if dist[w] == NIL => {
+                        dist[w] = dist[u] + 1;
+                        queue.push_back(w);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if !found {
+            break;
+        }
+        cursor.fill(0);
+        let mut stack = Vec::new();
+        for root in 0..n {
+            if pair_u[root] != NIL {
+                continue;
+            }
+            stack.push(root);
+            while let Some(u) = stack.last().copied() {
+                let Some(v) = graph.edges[u].get(cursor[u]).copied() else {
+                    // dead end for the rest of the phase
+                    dist[u] = NIL;
+                    stack.pop();
+                    continue;
+                };
+                match pair_v[v] {
+                    NIL => {
+                        for x in stack.drain(..) {
+                            let y = graph.edges[x][cursor[x]];
+                            pair_u[x] = y;
+                            pair_v[y] = x;
+                        }
+                    }
+                    w if dist[w] == dist[u] + 1 => stack.push(w),
+                    _ => cursor[u] += 1,
+                }
+            }
+        }
+    }
+    Match {
+        edges: (0..n)
+            .filter(|u| pair_u[*u] != NIL)
+            .map(|u| (u, pair_u[u]))
+            .collect(),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Match {
     pub(crate) edges: Box<[(usize, usize)]>,
 }
@@ -17,25 +189,663 @@ impl Match {
     pub fn size(&self) -> usize {
         self.edges.len()
     }
-    pub fn random(size: usize) -> Self {
-        let mut rng = rand::thread_rng();
+    /// Rename every vertex `x` of the matching to `permutation[x]`.
+    pub fn relabel(&self, permutation: &[usize]) -> Self {
+        Match {
+            edges: self
+                .edges
+                .iter()
+                .map(|(u, v)| (permutation[*u], permutation[*v]))
+                .collect(),
+        }
+    }
+    pub fn random<R: Rng + ?Sized>(size: usize, rng: &mut R) -> Self {
         let mut edges: Box<[usize]> = (0..size).collect();
-        edges.shuffle(&mut rng);
+        edges.shuffle(rng);
         Match {
             edges: edges.iter().copied().enumerate().collect(),
         }
     }
+    /// Matching of row `u` to column `perm[u]`, if `perm` is a permutation of
+    /// `0..perm.len()`.
+    pub fn from_permutation(perm: &[usize]) -> Result<Self, MatchError> {
+        let mut columns = vec![false; perm.len()];
+        for (u, v) in perm.iter().copied().enumerate() {
+            if v >= perm.len() {
+                return Err(MatchError::OutOfRange(u, v));
+            }
+            if std::mem::replace(&mut columns[v], true) {
+                return Err(MatchError::RepeatedColumn(v));
+            }
+        }
+        Ok(Match {
+            edges: perm.iter().copied().enumerate().collect(),
+        })
+    }
+    /// Edges `(u, v)` of the matching.
+    pub fn edges(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.edges.iter().copied()
+    }
+    /// Whether this is a perfect matching of `graph` using only its edges.
+    pub fn is_valid_in(&self, graph: &Graph) -> bool {
+        self.validate(graph).is_ok()
+    }
+    /// Check that this is a perfect matching of `graph`: one edge per row,
+    /// every row and column used once, and only edges of the graph.
+    pub fn validate(&self, graph: &Graph) -> Result<(), MatchError> {
+        if self.edges.len() != graph.size {
+            return Err(MatchError::WrongSize {
+                edges: self.edges.len(),
+                size: graph.size,
+            });
+        }
+        let mut rows = vec![false; graph.size];
+        let mut columns = vec![false; graph.size];
+        for (u, v) in self.edges.iter().copied() {
+            if u >= graph.size || v >= graph.size {
+                return Err(MatchError::OutOfRange(u, v));
+            }
+            if std::mem::replace(&mut rows[u], true) {
+                return Err(MatchError::RepeatedRow(u));
+            }
+            if std::mem::replace(&mut columns[v], true) {
+                return Err(MatchError::RepeatedColumn(v));
+            }
+            if !graph.edges[u].contains(&v) {
+                return Err(MatchError::NotAnEdge(u, v));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reason a [`Match`] is not a perfect matching of a graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchError {
+    /// the matching has `edges` edges for a graph of `size` vertices per side
+    WrongSize {
+        edges: usize,
+        size: usize,
+    },
+    /// an endpoint of the edge is not a vertex of the graph
+    OutOfRange(usize, usize),
+    RepeatedRow(usize),
+    RepeatedColumn(usize),
+    NotAnEdge(usize, usize),
+}
+
+impl std::fmt::Display for MatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MatchError::WrongSize { edges, size } => write!(
+                f,
+                "{} edges, the graph has {} vertices per side",
+                edges, size
+            ),
+            MatchError::OutOfRange(u, v) => write!(f, "edge ({}, {}) is out of range", u, v),
+            MatchError::RepeatedRow(u) => write!(f, "row {} is matched twice", u),
+            MatchError::RepeatedColumn(v) => write!(f, "column {} is matched twice", v),
+            MatchError::NotAnEdge(u, v) => write!(f, "({}, {}) is not an edge", u, v),
+        }
+    }
 }
 
+impl std::error::Error for MatchError {}
+
+/// Rows without edges and columns in no row's edges, each of which rules out
+/// a perfect matching on its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IsolatedVertices {
+    pub rows: Vec<usize>,
+    pub columns: Vec<usize>,
+}
+
+impl std::fmt::Display for IsolatedVertices {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "no perfect matching: isolated rows {:?} and columns {:?}",
+            self.rows, self.columns
+        )
+    }
+}
+
+impl std::error::Error for IsolatedVertices {}
+
+/// Contents of the (possibly compressed) text file `x`.
+fn read_to_string<S: AsRef<Path>>(x: S) -> anyhow::Result<String> {
+    Ok(std::io::read_to_string(decompress(File::open(x)?)?)?)
+}
+
+/// Parse a vertex of `0..n` on the zero-based `line` of an input file.
+fn parse_vertex(x: &str, n: usize, line: usize) -> anyhow::Result<usize> {
+    match x.parse::<usize>() {
+        Ok(v) if v < n => Ok(v),
+        Ok(v) => anyhow::bail!("line {}: vertex {} is out of range 0..{}", line + 1, v, n),
+        Err(e) => anyhow::bail!("line {}: {}: {:?}", line + 1, e, x),
+    }
+}
+
+/// Like [`parse_vertex`] for vertices numbered `1..=n`.
+fn parse_vertex_from_one(x: &str, n: usize, line: usize) -> anyhow::Result<usize> {
+    match x.parse::<usize>() {
+        Ok(v) if (1..=n).contains(&v) => Ok(v - 1),
+        Ok(v) => anyhow::bail!("line {}: vertex {} is out of range 1..={}", line + 1, v, n),
+        Err(e) => anyhow::bail!("line {}: {}: {:?}", line + 1, e, x),
+    }
+}
+
+/// Why a graph has no perfect matching: `rows` all of whose edges end in the
+/// fewer `columns`, violating Hall's condition, and the largest matching
+/// found with the vertices it leaves out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoPerfectMatching {
+    pub rows: Vec<usize>,
+    pub columns: Vec<usize>,
+    /// a maximum matching, whose size is the flow achieved
+    pub matching: Match,
+    /// vertices left out of `matching`
+    pub unmatched_rows: Vec<usize>,
+    pub unmatched_columns: Vec<usize>,
+}
+
+impl std::fmt::Display for NoPerfectMatching {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "no perfect matching: rows {:?} only have edges to the {} columns {:?} \
+             (a maximum matching leaves rows {:?} and columns {:?} unmatched)",
+            self.rows,
+            self.columns.len(),
+            self.columns,
+            self.unmatched_rows,
+            self.unmatched_columns
+        )
+    }
+}
+
+impl std::error::Error for NoPerfectMatching {}
+
 impl Graph {
+    /// Load a JSON graph, gzip or zstd compressed if the features allow.
     pub fn load<S: AsRef<Path>>(x: S) -> anyhow::Result<Self> {
-        let file = File::open(x)?;
-        simd_json::from_reader(file).map_err(Into::into)
+        Self::from_reader(decompress(File::open(x)?)?)
+    }
+    /// Read a JSON graph from `reader`, e.g. standard input.
+    pub fn from_reader<R: Read>(reader: R) -> anyhow::Result<Self> {
+        let graph: Self = simd_json::from_reader(reader)?;
+        graph.validate()?;
+        Ok(graph)
+    }
+    pub fn parse(json: &str) -> anyhow::Result<Self> {
+        let mut bytes = json.as_bytes().to_vec();
+        let graph: Self = simd_json::from_slice(&mut bytes)?;
+        graph.validate()?;
+        Ok(graph)
+    }
+    /// Load a graph on `n` vertices per side from whitespace separated `u v`
+    /// pairs, one edge per line; `#` starts a comment.
+    pub fn load_edge_list<S: AsRef<Path>>(x: S, n: usize) -> anyhow::Result<Self> {
+        Self::parse_edge_list(&read_to_string(x)?, n)
+    }
+    pub fn parse_edge_list(text: &str, n: usize) -> anyhow::Result<Self> {
+        let mut edges = vec![Vec::new(); n];
+        for (line, row) in text.lines().enumerate() {
+            let row = row.split('#').next().unwrap_or_default();
+            let fields: Vec<&str> = row.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            let [u, v] = fields[..] else {
+                anyhow::bail!("line {}: expected `u v`, got {:?}", line + 1, row.trim());
+            };
+            let (u, v) = (parse_vertex(u, n, line)?, parse_vertex(v, n, line)?);
+            edges[u].push(v);
+        }
+        Ok(Self::from_rows(n, edges))
+    }
+    /// Load an undirected graph in the DIMACS `.col` format: a `p edge n m`
+    /// line followed by `e u v` edges with vertices numbered from one, with
+    /// `c` comment lines. The result is its symmetric `n x n` adjacency
+    /// matrix.
+    pub fn load_dimacs<S: AsRef<Path>>(x: S) -> anyhow::Result<Self> {
+        Self::parse_dimacs(&read_to_string(x)?)
+    }
+    pub fn parse_dimacs(text: &str) -> anyhow::Result<Self> {
+        let mut edges: Option<Vec<Vec<usize>>> = None;
+        for (line, row) in text.lines().enumerate() {
+            let fields: Vec<&str> = row.split_whitespace().collect();
+            match (fields.first().copied(), edges.as_mut()) {
+                (None | Some("c"), _) => {}
+                (Some("p"), None) => {
+                    let [_, _, n, _] = fields[..] else {
+                        anyhow::bail!("line {}: expected `p edge n m`, got {:?}", line + 1, row);
+                    };
+                    let n = n
+                        .parse::<usize>()
+                        .map_err(|e| anyhow::anyhow!("line {}: {}: {:?}", line + 1, e, n))?;
+                    edges = Some(vec![Vec::new(); n]);
+                }
+                (Some("p"), Some(_)) => anyhow::bail!("line {}: second problem line", line + 1),
+                (Some("e"), Some(edges)) => {
+                    let [_, u, v] = fields[..] else {
+                        anyhow::bail!("line {}: expected `e u v`, got {:?}", line + 1, row);
+                    };
+                    let n = edges.len();
+                    let (u, v) = (
+                        parse_vertex_from_one(u, n, line)?,
+                        parse_vertex_from_one(v, n, line)?,
+                    );
+                    edges[u].push(v);
+                    edges[v].push(u);
+                }
+                (Some("e"), None) => {
+                    anyhow::bail!("line {}: edge before the problem line", line + 1)
+                }
+                (Some(x), _) => anyhow::bail!("line {}: unknown line type {:?}", line + 1, x),
+            }
+        }
+        let edges = edges.ok_or_else(|| anyhow::anyhow!("no problem line"))?;
+        Ok(Self::from_rows(edges.len(), edges))
+    }
+    /// Load a square matrix in Matrix Market coordinate format. A `pattern`
+    /// matrix is read as 0/1, a `real` or `integer` one as a weighted graph
+    /// without its explicit zeros; `symmetric` matrices list only one triangle.
+    /// Repeated entries are summed.
+    pub fn load_matrix_market<S: AsRef<Path>>(x: S) -> anyhow::Result<Self> {
+        Self::parse_matrix_market(&read_to_string(x)?)
+    }
+    pub fn parse_matrix_market(text: &str) -> anyhow::Result<Self> {
+        let mut lines = text.lines().enumerate();
+        let header = lines
+            .next()
+            .map(|(_, x)| x.to_ascii_lowercase())
+            .unwrap_or_default();
+        let header: Vec<&str> = header.split_whitespace().collect();
+        let [banner, "matrix", "coordinate", field, symmetry] = header[..] else {
+            anyhow::bail!("line 1: expected `%%MatrixMarket matrix coordinate <field> <symmetry>`");
+        };
+        if banner != "%%matrixmarket" {
+            anyhow::bail!("line 1: missing the %%MatrixMarket banner");
+        }
+        let weighted = match field {
+            "pattern" => false,
+            "real" | "integer" => true,
+            _ => anyhow::bail!("line 1: unsupported field {:?}", field),
+        };
+        let symmetric = match symmetry {
+            "general" => false,
+            "symmetric" => true,
+            _ => anyhow::bail!("line 1: unsupported symmetry {:?}", symmetry),
+        };
+        let mut lines = lines.filter(|(_, x)| !x.trim().is_empty() && !x.starts_with('%'));
+        let (line, size) = lines
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("missing the size line"))?;
+        let size: Vec<&str> = size.split_whitespace().collect();
+        let [rows, columns, _] = size[..] else {
+            anyhow::bail!("line {}: expected `rows columns entries`", line + 1);
+        };
+        if rows != columns {
+            anyhow::bail!(
+                "line {}: matrix is {}x{}, not square",
+                line + 1,
+                rows,
+                columns
+            );
+        }
+        let n = rows
+            .parse::<usize>()
+            .map_err(|e| anyhow::anyhow!("line {}: {}: {:?}", line + 1, e, rows))?;
+        let mut entries = vec![Vec::new(); n];
+        for (line, row) in lines {
+            let fields: Vec<&str> = row.split_whitespace().collect();
+            let (u, v, w) = match (weighted, &fields[..]) {
+                (false, [u, v]) => (u, v, 1.0),
+                (true, [u, v, w]) => (
+                    u,
+                    v,
+                    w.parse::<f64>()
+                        .map_err(|e| anyhow::anyhow!("line {}: {}: {:?}", line + 1, e, w))?,
+                ),
+                _ => anyhow::bail!("line {}: malformed entry {:?}", line + 1, row),
+            };
+            let (u, v) = (
+                parse_vertex_from_one(u, n, line)?,
+                parse_vertex_from_one(v, n, line)?,
+            );
+            entries[u].push((v, w));
+            if symmetric && u != v {
+                entries[v].push((u, w));
+            }
+        }
+        let mut edges = Vec::with_capacity(n);
+        let mut weights = Vec::with_capacity(n);
+        for mut row in entries {
+            row.sort_by_key(|x| x.0);
+            let mut merged: Vec<(usize, f64)> = Vec::with_capacity(row.len());
+            for (v, w) in row {
+                match merged.last_mut() {
+                    Some(last) if last.0 == v => last.1 += w,
+                    _ => merged.push((v, w)),
+                }
+            }
+            merged.retain(|x| x.1 != 0.0);
+            edges.push(merged.iter().map(|x| x.0).collect());
+            weights.push(merged.iter().map(|x| x.1).collect());
+        }
+        let graph = Graph {
+            size: n,
+            edges: edges.into_boxed_slice(),
+            weights: weighted.then(|| weights.into_boxed_slice()),
+        };
+        graph.validate()?;
+        Ok(graph)
+    }
+    /// Unweighted graph of adjacency rows, dropping repeated edges.
+    fn from_rows(n: usize, rows: Vec<Vec<usize>>) -> Self {
+        Graph {
+            size: n,
+            edges: rows
+                .into_iter()
+                .map(|mut row| {
+                    row.sort_unstable();
+                    row.dedup();
+                    row.into_boxed_slice()
+                })
+                .collect(),
+            weights: None,
+        }
+    }
+    /// Check that there is a row of edges per vertex, that every endpoint is
+    /// a vertex, and that the weights, if any, match the edges.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.edges.len() != self.size {
+            anyhow::bail!(
+                "{} edge rows for a graph of size {}",
+                self.edges.len(),
+                self.size
+            );
+        }
+        for (u, edges) in self.edges.iter().enumerate() {
+            if let Some(v) = edges.iter().find(|v| **v >= self.size) {
+                anyhow::bail!(
+                    "row {} has vertex {}, out of range for size {}",
+                    u,
+                    v,
+                    self.size
+                );
+            }
+        }
+        self.check_weights()
+    }
+    fn check_weights(&self) -> anyhow::Result<()> {
+        let Some(weights) = &self.weights else {
+            return Ok(());
+        };
+        if weights.len() != self.edges.len() {
+            anyhow::bail!(
+                "{} weight rows for {} rows",
+                weights.len(),
+                self.edges.len()
+            );
+        }
+        for (u, (edges, weights)) in self.edges.iter().zip(weights.iter()).enumerate() {
+            if edges.len() != weights.len() {
+                anyhow::bail!(
+                    "row {} has {} edges but {} weights",
+                    u,
+                    edges.len(),
+                    weights.len()
+                );
+            }
+            if let Some(w) = weights.iter().find(|w| !(w.is_finite() && **w >= 0.0)) {
+                anyhow::bail!("row {} has invalid weight {}", u, w);
+            }
+        }
+        Ok(())
     }
+    /// Weight of the edge `(u, v)`: one for an unweighted edge, zero for a
+    /// non-edge.
+    pub fn edge_weight(&self, u: usize, v: usize) -> f64 {
+        match self.edges[u].iter().position(|x| *x == v) {
+            Some(i) => self.weights.as_ref().map_or(1.0, |w| w[u][i]),
+            None => 0.0,
+        }
+    }
+    /// All edges `(u, v)` of the graph, for membership tests in code that has
+    /// no [`crate::cooling_state::State`] at hand. Hot paths should query the
+    /// adjacency `BitMatrix` of the state instead, which avoids hashing.
+    pub fn edge_set(&self) -> HashSet<(usize, usize)> {
+        self.edges
+            .iter()
+            .enumerate()
+            .flat_map(|(u, edges)| edges.iter().map(move |v| (u, *v)))
+            .collect()
+    }
+    /// Largest edge weight, one for an unweighted graph.
+    pub fn max_weight(&self) -> f64 {
+        match &self.weights {
+            Some(weights) => weights.iter().flatten().copied().fold(0.0, f64::max),
+            None => 1.0,
+        }
+    }
+    /// Graph of a dense `n x n` 0/1 matrix given row-major in `data`.
+    pub fn from_adjacency_matrix(n: usize, data: &[bool]) -> anyhow::Result<Self> {
+        if n.checked_mul(n) != Some(data.len()) {
+            anyhow::bail!("{} entries do not form a {1}x{1} matrix", data.len(), n);
+        }
+        let edges = data
+            .chunks(n.max(1))
+            .take(n)
+            .map(|row| {
+                row.iter()
+                    .enumerate()
+                    .filter(|(_, x)| **x)
+                    .map(|(v, _)| v)
+                    .collect()
+            })
+            .collect();
+        Ok(Graph {
+            size: n,
+            edges,
+            weights: None,
+        })
+    }
+    /// Graph of the dense row-major `n x n` matrix `data`: its nonzero
+    /// entries are the edges, weighted by their values unless all of them are
+    /// one.
+    pub fn from_weight_matrix(n: usize, data: &[f64]) -> anyhow::Result<Self> {
+        if n.checked_mul(n) != Some(data.len()) {
+            anyhow::bail!("{} entries do not form a {1}x{1} matrix", data.len(), n);
+        }
+        let mut edges = Vec::with_capacity(n);
+        let mut weights = Vec::with_capacity(n);
+        for row in data.chunks(n.max(1)).take(n) {
+            let (row_edges, row_weights): (Vec<usize>, Vec<f64>) = row
+                .iter()
+                .enumerate()
+                .filter(|(_, x)| **x != 0.0)
+                .map(|(v, x)| (v, *x))
+                .unzip();
+            edges.push(row_edges.into_boxed_slice());
+            weights.push(row_weights.into_boxed_slice());
+        }
+        let weighted = weights.iter().flatten().any(|x| *x != 1.0);
+        let graph = Graph {
+            size: n,
+            edges: edges.into_boxed_slice(),
+            weights: weighted.then(|| weights.into_boxed_slice()),
+        };
+        graph.validate()?;
+        Ok(graph)
+    }
+    /// Like [`Graph::from_weight_matrix`], for a square `ndarray` matrix.
+    #[cfg(feature = "ndarray")]
+    pub fn from_ndarray(a: ndarray::ArrayView2<f64>) -> anyhow::Result<Self> {
+        let (size, columns) = a.dim();
+        if size != columns {
+            anyhow::bail!("matrix of shape {}x{} is not square", size, columns);
+        }
+        Self::from_weight_matrix(size, &a.iter().copied().collect::<Vec<_>>())
+    }
+    pub fn complete(size: usize) -> Self {
+        Graph {
+            size,
+            edges: (0..size).map(|_| (0..size).collect()).collect(),
+            weights: None,
+        }
+    }
+    /// Degrees of all rows followed by all columns.
+    fn degrees(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut column_degree = vec![0; self.size];
+        for v in self.edges.iter().flat_map(|x| x.iter()) {
+            column_degree[*v] += 1;
+        }
+        self.edges.iter().map(|x| x.len()).chain(column_degree)
+    }
+    /// Largest degree of any row or column.
+    pub fn max_degree(&self) -> usize {
+        self.degrees().max().unwrap_or(0)
+    }
+    /// Smallest degree of any row or column.
+    pub fn min_degree(&self) -> usize {
+        self.degrees().min().unwrap_or(0)
+    }
+    /// Number of edges rotated by one move such that a random rotation stays
+    /// inside the graph with probability at least one half, i.e. the largest
+    /// `k` with `(min_degree / n)^k >= 1/2`, kept within `2..=MAX_MOVE_SIZE`
+    /// and rounded down to an even size: an odd rotation is an even
+    /// permutation, so the kernel spends half of the moves on swaps instead.
+    pub fn auto_move_size(&self) -> usize {
+        const MAX_MOVE_SIZE: usize = 4;
+        let density = self.min_degree() as f64 / self.size.max(1) as f64;
+        let k = if density >= 1.0 {
+            MAX_MOVE_SIZE
+        } else {
+            (0.5f64.ln() / density.ln()).floor() as usize
+        };
+        let k = k.clamp(2, MAX_MOVE_SIZE.min(self.size).max(2));
+        k - k % 2
+    }
+    /// Relabel vertices by descending total (row plus column) degree, applying
+    /// the same permutation to both sides so that the permanent is unchanged.
+    /// Returns the new graph and the permutation mapping new labels to old ones.
+    pub fn relabel_by_degree(&self) -> (Graph, Vec<usize>) {
+        let mut degree = vec![0; self.size];
+        for (u, edges) in self.edges.iter().enumerate() {
+            degree[u] += edges.len();
+            for v in edges.iter().copied() {
+                degree[v] += 1;
+            }
+        }
+        let mut permutation: Vec<usize> = (0..self.size).collect();
+        permutation.sort_by_key(|x| std::cmp::Reverse(degree[*x]));
+        let mut inverse = vec![0; self.size];
+        for (new, old) in permutation.iter().copied().enumerate() {
+            inverse[old] = new;
+        }
+        let rows: Vec<Vec<(usize, f64)>> = permutation
+            .iter()
+            .map(|old| {
+                let mut row: Vec<_> = self.edges[*old]
+                    .iter()
+                    .map(|v| (inverse[*v], self.edge_weight(*old, *v)))
+                    .collect();
+                row.sort_unstable_by_key(|x| x.0);
+                row
+            })
+            .collect();
+        (
+            Graph {
+                size: self.size,
+                edges: rows
+                    .iter()
+                    .map(|x| x.iter().map(|e| e.0).collect())
+                    .collect(),
+                weights: self.weights.as_ref().map(|_| {
+                    rows.iter()
+                        .map(|x| x.iter().map(|e| e.1).collect())
+                        .collect()
+                }),
+            },
+            permutation,
+        )
+    }
+    /// Add edges until every row and column has degree `d`, by routing the
+    /// missing degrees through the non-edges with a max flow. Any completion
+    /// adds exactly `n * d - |E|` edges, so the result is minimal.
+    ///
+    /// This changes the permanent; it is only meant for experiments on regular
+    /// instances. Returns `None` if `d` is below the maximum degree or no
+    /// completion exists.
+    pub fn augment_to_regular(&self, d: usize) -> Option<Graph> {
+        let mut column_degree = vec![0; self.size];
+        for v in self.edges.iter().flat_map(|x| x.iter()) {
+            column_degree[*v] += 1;
+        }
+        if d < self.max_degree() || d > self.size {
+            return None;
+        }
+        let src = 2 * self.size;
+        let sink = 2 * self.size + 1;
+        let mut graph = DinicGraph::<u32>::new(2 * self.size + 2, src, sink);
+        let mut missing = 0;
+        for (u, edges) in self.edges.iter().enumerate() {
+            graph.add_edge(src, u, (d - edges.len()) as u32);
+            graph.add_edge(u + self.size, sink, (d - column_degree[u]) as u32);
+            missing += d - edges.len();
+            for v in (0..self.size).filter(|v| !edges.contains(v)) {
+                graph.add_edge(u, v + self.size, 1);
+            }
+        }
+        if graph.calculate_flow() as usize != missing {
+            return None;
+        }
+        let mut rows: Vec<Vec<(usize, f64)>> = self
+            .edges
+            .iter()
+            .enumerate()
+            .map(|(u, x)| x.iter().map(|v| (*v, self.edge_weight(u, *v))).collect())
+            .collect();
+        // added edges get unit weight
+        for (u, v, _) in graph.current_flow().filter(|x| x.0 < self.size) {
+            rows[u].push((v - self.size, 1.0));
+        }
+        for row in rows.iter_mut() {
+            row.sort_unstable_by_key(|x| x.0);
+        }
+        Some(Graph {
+            size: self.size,
+            edges: rows
+                .iter()
+                .map(|x| x.iter().map(|e| e.0).collect())
+                .collect(),
+            weights: self.weights.as_ref().map(|_| {
+                rows.iter()
+                    .map(|x| x.iter().map(|e| e.1).collect())
+                    .collect()
+            }),
+        })
+    }
+    /// Maximum matching of the graph, perfect if the graph has one; see
+    /// [`Graph::find_perfect_match`] to tell the two apart.
     pub fn find_match(&self) -> Match {
+        self.find_perfect_match()
+            .unwrap_or_else(|error| error.matching)
+    }
+    pub fn find_match_with(&self, algorithm: MatchingAlgorithm) -> Match {
+        match algorithm {
+            MatchingAlgorithm::Dinic => self.dinic_match(),
+            MatchingAlgorithm::HopcroftKarp => hopcroft_karp(self),
+        }
+    }
+    /// Unit-capacity flow network of the graph: rows `0..n`, columns
+    /// `n..2n`, then the source and the sink.
+    fn matching_network(&self) -> DinicGraph {
         let src = 2 * self.size;
         let sink = 2 * self.size + 1;
-        let mut graph = DinicGraph::new(2 * self.size + 2, src, sink);
+        let mut graph = DinicGraph::<u32>::new(2 * self.size + 2, src, sink);
         for i in 0..self.size {
             graph.add_edge(src, i, 1);
             graph.add_edge(i + self.size, sink, 1);
@@ -45,19 +855,105 @@ impl Graph {
                 graph.add_edge(u, v + self.size, 1);
             }
         }
-        let flow = graph.calculate_flow();
-        let mut edges = Vec::with_capacity(flow as usize);
-        for (u, v, _) in graph.current_flow().filter(|x| x.0 < self.size) {
-            edges.push((u, v - self.size));
-        }
+        graph
+    }
+    /// Matching carried by the maximum flow of [`Graph::matching_network`].
+    fn network_match(&self, graph: &DinicGraph) -> Match {
         Match {
-            edges: edges.into_boxed_slice(),
+            edges: graph
+                .current_flow()
+                .filter(|x| x.0 < self.size)
+                .map(|(u, v, _)| (u, v - self.size))
+                .collect(),
+        }
+    }
+    fn dinic_match(&self) -> Match {
+        let mut graph = self.matching_network();
+        graph.calculate_flow();
+        self.network_match(&graph)
+    }
+    /// Check that every row has an edge and every column is the end of one,
+    /// in time linear in the edges, before anything more expensive runs.
+    pub fn check_feasible(&self) -> Result<(), IsolatedVertices> {
+        let mut covered = vec![false; self.size];
+        for v in self.edges.iter().flat_map(|edges| edges.iter()) {
+            covered[*v] = true;
+        }
+        let rows: Vec<usize> = (0..self.size)
+            .filter(|u| self.edges[*u].is_empty())
+            .collect();
+        let columns: Vec<usize> = (0..self.size).filter(|v| !covered[*v]).collect();
+        if rows.is_empty() && columns.is_empty() {
+            Ok(())
+        } else {
+            Err(IsolatedVertices { rows, columns })
+        }
+    }
+    /// Perfect matching of the graph, or the rows violating Hall's condition
+    /// that prevent one, read off a minimum cut of the matching network.
+    pub fn find_perfect_match(&self) -> Result<Match, NoPerfectMatching> {
+        let mut graph = self.matching_network();
+        if graph.calculate_flow() as usize == self.size {
+            return Ok(self.network_match(&graph));
+        }
+        // the rows on the source side only reach columns on the source side,
+        // which are all matched to those rows, so they outnumber their columns
+        let (source_side, _) = graph.min_cut();
+        let rows = source_side
+            .iter()
+            .copied()
+            .filter(|x| *x < self.size)
+            .collect();
+        let columns = source_side
+            .iter()
+            .copied()
+            .filter(|x| (self.size..2 * self.size).contains(x))
+            .map(|x| x - self.size)
+            .collect();
+        let matching = self.network_match(&graph);
+        let (unmatched_rows, unmatched_columns) = self.unmatched_vertices(&matching);
+        Err(NoPerfectMatching {
+            rows,
+            columns,
+            matching,
+            unmatched_rows,
+            unmatched_columns,
+        })
+    }
+    /// Rows and columns that `matching` leaves out.
+    fn unmatched_vertices(&self, matching: &Match) -> (Vec<usize>, Vec<usize>) {
+        let (mut rows, mut columns) = (vec![true; self.size], vec![true; self.size]);
+        for (u, v) in matching.edges.iter().copied() {
+            rows[u] = false;
+            columns[v] = false;
+        }
+        let unmatched = |free: Vec<bool>| (0..self.size).filter(|x| free[*x]).collect();
+        (unmatched(rows), unmatched(columns))
+    }
+    /// Edge-disjoint perfect matchings, found greedily by removing the edges
+    /// of each matching before looking for the next one.
+    pub fn disjoint_matchings(&self) -> Vec<Match> {
+        let mut remaining = self.clone();
+        let mut matchings = Vec::new();
+        loop {
+            let matching = remaining.find_match();
+            if matching.size() != self.size || self.size == 0 {
+                return matchings;
+            }
+            for (u, v) in matching.edges.iter().copied() {
+                remaining.edges[u] = remaining.edges[u]
+                    .iter()
+                    .copied()
+                    .filter(|x| *x != v)
+                    .collect();
+            }
+            matchings.push(matching);
         }
     }
     pub fn find_random_match(&self) -> Match {
         let src = 2 * self.size;
         let sink = 2 * self.size + 1;
-        let mut graph = DinicGraph::new(2 * self.size + 2, src, sink);
+        let mut graph = DinicGraph::<u32>::new(2 * self.size + 2, src, sink);
         let shuffle_vector = {
             let mut rng = rand::thread_rng();
             let mut shuffle_vector: Box<[usize]> = (0..self.size).collect();
@@ -91,10 +987,75 @@ impl Graph {
     }
 }
 
+/// Random bipartite graph on `n + n` vertices with every edge present
+/// independently with probability `edge_prob`.
+pub fn gen_random_bipartite(n: usize, edge_prob: f64, seed: u64) -> Graph {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let edge_prob = edge_prob.clamp(0.0, 1.0);
+    Graph {
+        size: n,
+        edges: (0..n)
+            .map(|_| (0..n).filter(|_| rng.gen_bool(edge_prob)).collect())
+            .collect(),
+        weights: None,
+    }
+}
+
+/// Random bipartite graph on `n + n` vertices with a perfect matching: the
+/// edges of a random permutation plus `extra_edges` distinct random edges (at
+/// most all the other `n * n - n`).
+pub fn gen_with_perfect_matching(n: usize, extra_edges: usize, seed: u64) -> Graph {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut permutation: Vec<usize> = (0..n).collect();
+    permutation.shuffle(&mut rng);
+    let mut rows: Vec<Vec<usize>> = permutation.into_iter().map(|v| vec![v]).collect();
+    let mut edges: HashSet<(usize, usize)> = rows
+        .iter()
+        .enumerate()
+        .map(|(u, row)| (u, row[0]))
+        .collect();
+    let target = edges.len() + extra_edges.min(n * n - n);
+    while edges.len() < target {
+        let (u, v) = (rng.gen_range(0..n), rng.gen_range(0..n));
+        if edges.insert((u, v)) {
+            rows[u].push(v);
+        }
+    }
+    Graph::from_rows(n, rows)
+}
+
 #[cfg(test)]
 mod test {
     use std::path::PathBuf;
 
+    #[test]
+    fn random_graphs() {
+        for seed in 0..16 {
+            let graph = super::gen_with_perfect_matching(24, 3 * seed as usize, seed);
+            graph.validate().unwrap();
+            assert_eq!(
+                graph.edges.iter().map(|x| x.len()).sum::<usize>(),
+                24 + 3 * seed as usize
+            );
+            assert_eq!(graph.find_match().size(), graph.size);
+        }
+        let full = super::gen_with_perfect_matching(4, 100, 1);
+        assert_eq!(full.edge_set().len(), 16);
+        let graph = super::gen_random_bipartite(64, 0.25, 7);
+        let edges = graph.edges.iter().map(|x| x.len()).sum::<usize>();
+        assert!((768..1280).contains(&edges), "{} edges", edges);
+        assert_eq!(
+            graph.edge_set(),
+            super::gen_random_bipartite(64, 0.25, 7).edge_set()
+        );
+        assert!(super::gen_random_bipartite(8, 0.0, 7).edge_set().is_empty());
+        let json = simd_json::to_string(&graph).unwrap();
+        assert_eq!(
+            super::Graph::parse(&json).unwrap().edge_set(),
+            graph.edge_set()
+        );
+    }
+
     #[test]
     fn box_example() {
         let path: PathBuf = env!("PWD").into();
@@ -109,6 +1070,170 @@ mod test {
         }
     }
 
+    #[test]
+    fn weighted_graph() {
+        let path: PathBuf = env!("PWD").into();
+        let graph = super::Graph::load(path.join("data").join("weighted.json")).unwrap();
+        assert_eq!(graph.edge_weight(1, 0), 3.0);
+        assert_eq!(graph.edge_weight(1, 2), 0.0);
+        assert_eq!(graph.max_weight(), 3.0);
+        let dense = |graph: &super::Graph| -> Vec<f64> {
+            let n = graph.size;
+            (0..n * n)
+                .map(|x| graph.edge_weight(x / n, x % n))
+                .collect()
+        };
+        // relabeling carries the weights along
+        let (relabeled, _) = graph.relabel_by_degree();
+        assert_eq!(crate::exact::permanent_ryser(&dense(&graph), 4), 51.5);
+        assert_eq!(crate::exact::permanent_ryser(&dense(&relabeled), 4), 51.5);
+        let invalid = r#"{"size": 1, "edges": [[0]], "weights": [[-1.0]]}"#;
+        assert!(super::Graph::parse(invalid).is_err());
+        let missing = r#"{"size": 2, "edges": [[0, 1], [1]], "weights": [[1.0], [1.0]]}"#;
+        assert!(super::Graph::parse(missing).is_err());
+    }
+
+    #[test]
+    fn matching_algorithms_agree() {
+        let path: PathBuf = env!("PWD").into();
+        let mut checked = 0;
+        for entry in std::fs::read_dir(path.join("data")).unwrap() {
+            let Ok(graph) = super::Graph::load(entry.unwrap().path()) else {
+                continue;
+            };
+            let dinic = graph.find_match_with(super::MatchingAlgorithm::Dinic);
+            let hopcroft_karp = graph.find_match_with(super::MatchingAlgorithm::HopcroftKarp);
+            assert_eq!(dinic.size(), hopcroft_karp.size());
+            let mut rows: Vec<_> = hopcroft_karp.edges.iter().map(|x| x.0).collect();
+            let mut columns: Vec<_> = hopcroft_karp.edges.iter().map(|x| x.1).collect();
+            rows.sort_unstable();
+            rows.dedup();
+            columns.sort_unstable();
+            columns.dedup();
+            assert_eq!(rows.len(), hopcroft_karp.size());
+            assert_eq!(columns.len(), hopcroft_karp.size());
+            assert!(hopcroft_karp
+                .edges
+                .iter()
+                .all(|(u, v)| graph.edges[*u].contains(v)));
+            checked += 1;
+        }
+        assert!(checked > 0);
+        // one row short of a perfect matching
+        let graph = super::Graph::parse(r#"{"size": 3, "edges": [[0], [0], [1, 2]]}"#).unwrap();
+        assert_eq!(super::hopcroft_karp(&graph).size(), 2);
+    }
+
+    #[test]
+    fn edge_set() {
+        let path: PathBuf = env!("PWD").into();
+        let graph = super::Graph::load(path.join("data").join("box.json")).unwrap();
+        let edges = graph.edge_set();
+        // box.json is lower triangular: the diagonal plus a staircase below it
+        let expected = [
+            (0, 0),
+            (1, 0),
+            (1, 1),
+            (2, 0),
+            (2, 1),
+            (2, 2),
+            (3, 2),
+            (3, 3),
+            (4, 2),
+            (4, 3),
+            (4, 4),
+            (5, 4),
+            (5, 5),
+            (6, 4),
+            (6, 5),
+            (6, 6),
+        ];
+        assert_eq!(edges, expected.into_iter().collect());
+        assert!((0..graph.size).all(|x| edges.contains(&(x, x))));
+    }
+
+    #[test]
+    fn relabel_by_degree() {
+        let path: PathBuf = env!("PWD").into();
+        let path = path.join("data").join("box.json");
+        let graph = super::Graph::load(path).unwrap();
+        let (relabeled, permutation) = graph.relabel_by_degree();
+        assert_eq!(
+            crate::exact::ryser_permanent(&relabeled),
+            crate::exact::ryser_permanent(&graph)
+        );
+        let matching = relabeled.find_random_match().relabel(&permutation);
+        assert_eq!(matching.size(), graph.size);
+        for (u, v) in matching.edges.iter() {
+            assert!(graph.edges[*u].contains(v));
+        }
+    }
+
+    #[test]
+    fn augment_to_regular() {
+        let path: PathBuf = env!("PWD").into();
+        let path = path.join("data").join("box.json");
+        let graph = super::Graph::load(path).unwrap();
+        assert!(graph.augment_to_regular(2).is_none());
+        assert!(graph.augment_to_regular(8).is_none());
+        let regular = graph.augment_to_regular(3).unwrap();
+        let mut column_degree = vec![0; regular.size];
+        for (u, edges) in regular.edges.iter().enumerate() {
+            assert_eq!(edges.len(), 3);
+            assert!(graph.edges[u].iter().all(|v| edges.contains(v)));
+            for v in edges.iter() {
+                column_degree[*v] += 1;
+            }
+        }
+        assert!(column_degree.iter().all(|x| *x == 3));
+    }
+
+    #[test]
+    fn adjacency_matrix() {
+        #[rustfmt::skip]
+        let data = [
+            true, true, false,
+            false, true, true,
+            true, false, true,
+        ];
+        let graph = super::Graph::from_adjacency_matrix(3, &data).unwrap();
+        assert_eq!(graph.size, 3);
+        assert_eq!(&*graph.edges[0], &[0, 1]);
+        assert_eq!(&*graph.edges[1], &[1, 2]);
+        assert_eq!(&*graph.edges[2], &[0, 2]);
+        assert_eq!(crate::exact::ryser_permanent(&graph), 2.0);
+        assert!(super::Graph::from_adjacency_matrix(3, &data[..8]).is_err());
+        assert!(super::Graph::from_adjacency_matrix(0, &[]).is_ok());
+    }
+
+    #[test]
+    fn move_size_follows_degree() {
+        let path: PathBuf = env!("PWD").into();
+        let cycle = super::Graph::load(path.join("data").join("cycle.json")).unwrap();
+        assert_eq!((cycle.min_degree(), cycle.max_degree()), (2, 2));
+        assert_eq!(cycle.auto_move_size(), 2);
+        let complete = super::Graph::complete(8);
+        assert_eq!((complete.min_degree(), complete.max_degree()), (8, 8));
+        assert_eq!(complete.auto_move_size(), 4);
+        // too small for 4 edges, which would leave 3
+        assert_eq!(super::Graph::complete(3).auto_move_size(), 2);
+        // K_10 without the edges (u, u + 1) and (u, u + 2) has density 0.8,
+        // where rotations of up to 3 edges stay inside half of the time
+        let dense = |missing: usize| {
+            let adjacency: Vec<bool> = (0..100)
+                .map(|x| {
+                    let shift = (10 + x % 10 - x / 10) % 10;
+                    shift == 0 || shift > missing
+                })
+                .collect();
+            super::Graph::from_adjacency_matrix(10, &adjacency).unwrap()
+        };
+        assert_eq!(dense(2).min_degree(), 8);
+        assert_eq!(dense(2).auto_move_size(), 2);
+        assert_eq!(dense(1).min_degree(), 9);
+        assert_eq!(dense(1).auto_move_size(), 4);
+    }
+
     #[test]
     fn complete_example() {
         let path: PathBuf = env!("PWD").into();
@@ -121,4 +1246,215 @@ mod test {
             println!("{} -> {}", u, v);
         }
     }
+
+    #[test]
+    fn malformed_graph() {
+        let path: PathBuf = env!("PWD").into();
+        let error = super::Graph::load(path.join("data").join("malformed.json")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "row 1 has vertex 3, out of range for size 3"
+        );
+        let error = super::Graph::parse(r#"{"size": 3, "edges": [[0], [1]]}"#).unwrap_err();
+        assert_eq!(error.to_string(), "2 edge rows for a graph of size 3");
+    }
+
+    #[test]
+    fn graph_from_reader() {
+        let path: PathBuf = env!("PWD").into();
+        let bytes = std::fs::read(path.join("data").join("weighted.json")).unwrap();
+        let graph = super::Graph::from_reader(bytes.as_slice()).unwrap();
+        let expected = super::Graph::load(path.join("data").join("weighted.json")).unwrap();
+        assert_eq!(graph.edges, expected.edges);
+        assert_eq!(graph.weights, expected.weights);
+        assert!(super::Graph::from_reader(&b"{"[..]).is_err());
+    }
+
+    #[test]
+    fn edge_list_and_dimacs() {
+        let path: PathBuf = env!("PWD").into();
+        let expected = super::Graph::load(path.join("data").join("box.json")).unwrap();
+        let mut text = String::from("# box.json as an edge list\n\n");
+        for (u, row) in expected.edges.iter().enumerate() {
+            for v in row.iter().rev() {
+                text.push_str(&format!("{} {} # ({}, {})\n", u, v, u, v));
+            }
+        }
+        text.push_str("0 0\n");
+        let graph = super::Graph::parse_edge_list(&text, expected.size).unwrap();
+        assert_eq!(graph.edges, expected.edges);
+        let error = super::Graph::parse_edge_list("0 1\n1 7\n", 3).unwrap_err();
+        assert_eq!(error.to_string(), "line 2: vertex 7 is out of range 0..3");
+        let error = super::Graph::parse_edge_list("0 1\n\n1 2 3\n", 3).unwrap_err();
+        assert!(error.to_string().starts_with("line 3:"), "{}", error);
+        assert!(super::Graph::parse_edge_list("0 x", 3).is_err());
+
+        let triangle = "c triangle\np edge 3 3\ne 1 2\ne 2 3\ne 1 3\n";
+        let graph = super::Graph::parse_dimacs(triangle).unwrap();
+        assert_eq!(graph.size, 3);
+        assert_eq!(&*graph.edges[0], [1, 2]);
+        // cycle covers of the triangle: its two orientations
+        assert_eq!(crate::exact::permanent_01(&graph), Some(2));
+        let error = super::Graph::parse_dimacs("p edge 3 1\ne 1 4\n").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: vertex 4 is out of range 1..=3");
+        assert!(super::Graph::parse_dimacs("e 1 2\n").is_err());
+        assert!(super::Graph::parse_dimacs("c no problem line\n").is_err());
+    }
+
+    #[test]
+    fn matrix_market() {
+        let path: PathBuf = env!("PWD").into();
+        let json = super::Graph::load(path.join("data").join("weighted.json")).unwrap();
+        let mtx = super::Graph::load_matrix_market(path.join("data").join("weighted.mtx")).unwrap();
+        assert_eq!(mtx.size, json.size);
+        assert_eq!(mtx.edges, json.edges);
+        assert_eq!(mtx.weights, json.weights);
+
+        let triangle = "%%MatrixMarket matrix coordinate pattern symmetric\n3 3 3\n2 1\n3 2\n3 1\n";
+        let graph = super::Graph::parse_matrix_market(triangle).unwrap();
+        assert!(graph.weights.is_none());
+        assert_eq!(
+            graph.edges,
+            super::Graph::parse_dimacs("p edge 3 3\ne 1 2\ne 2 3\ne 1 3\n")
+                .unwrap()
+                .edges
+        );
+
+        let error = super::Graph::parse_matrix_market(
+            "%%MatrixMarket matrix coordinate real general\n%\n2 3 1\n1 1 1.0\n",
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "line 3: matrix is 2x3, not square");
+        assert!(super::Graph::parse_matrix_market(
+            "%%MatrixMarket matrix coordinate real general\n2 2 1\n1 2\n"
+        )
+        .is_err());
+        assert!(super::Graph::parse_matrix_market(
+            "%%MatrixMarket matrix coordinate real general\n2 2 1\n1 2 -1.0\n"
+        )
+        .is_err());
+        assert!(
+            super::Graph::parse_matrix_market("%%MatrixMarket matrix array real general\n")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn hall_violation() {
+        // rows 0, 1 and 3 only reach columns 0 and 1
+        let graph =
+            super::Graph::parse(r#"{"size": 4, "edges": [[0], [0, 1], [1, 2, 3], [1]]}"#).unwrap();
+        let violation = graph.find_perfect_match().err().unwrap();
+        assert_eq!(violation.rows, [0, 1, 3]);
+        assert_eq!(violation.columns, [0, 1]);
+        assert!(violation.to_string().starts_with(
+            "no perfect matching: rows [0, 1, 3] only have edges to the 2 columns [0, 1]"
+        ));
+        let path: PathBuf = env!("PWD").into();
+        let graph = super::Graph::load(path.join("data").join("box.json")).unwrap();
+        assert_eq!(graph.find_perfect_match().unwrap().size(), graph.size);
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn from_ndarray() {
+        let a = ndarray::array![[0.0, 2.0, 0.0], [1.0, 0.0, 0.5], [0.0, 3.0, 1.0]];
+        let graph = super::Graph::from_ndarray(a.view()).unwrap();
+        assert_eq!(graph.size, 3);
+        assert_eq!(graph.edges[1].as_ref(), [0, 2]);
+        assert_eq!(graph.edge_weight(2, 1), 3.0);
+        assert_eq!(graph.edge_weight(0, 0), 0.0);
+        let ones = ndarray::Array2::<f64>::eye(3);
+        assert!(super::Graph::from_ndarray(ones.view())
+            .unwrap()
+            .weights
+            .is_none());
+        let negative = ndarray::array![[1.0, -1.0], [1.0, 1.0]];
+        assert!(super::Graph::from_ndarray(negative.view()).is_err());
+        let wide = ndarray::Array2::<f64>::ones((2, 3));
+        assert!(super::Graph::from_ndarray(wide.view()).is_err());
+    }
+
+    #[test]
+    fn isolated_vertices() {
+        let graph = super::Graph::parse(r#"{"size": 3, "edges": [[0, 1], [], [1]]}"#).unwrap();
+        let isolated = graph.check_feasible().err().unwrap();
+        assert_eq!(isolated.rows, [1]);
+        assert_eq!(isolated.columns, [2]);
+        assert_eq!(
+            isolated.to_string(),
+            "no perfect matching: isolated rows [1] and columns [2]"
+        );
+        let path: PathBuf = env!("PWD").into();
+        let graph = super::Graph::load(path.join("data").join("box.json")).unwrap();
+        assert_eq!(graph.check_feasible(), Ok(()));
+    }
+
+    #[test]
+    fn match_validation() {
+        use super::{Match, MatchError};
+        let path: PathBuf = env!("PWD").into();
+        let graph = super::Graph::load(path.join("data").join("box.json")).unwrap();
+        let matching = graph.find_match();
+        assert!(matching.is_valid_in(&graph));
+        // box.json only has the diagonal as a perfect matching
+        let invalid = |edges: &[(usize, usize)]| {
+            Match {
+                edges: edges.into(),
+            }
+            .validate(&graph)
+            .unwrap_err()
+        };
+        let mut edges: Vec<_> = (0..7).map(|u| (u, u)).collect();
+        assert_eq!(
+            invalid(&edges[1..]),
+            MatchError::WrongSize { edges: 6, size: 7 }
+        );
+        edges[6] = (6, 7);
+        assert_eq!(invalid(&edges), MatchError::OutOfRange(6, 7));
+        edges[6] = (5, 6);
+        assert_eq!(invalid(&edges), MatchError::RepeatedRow(5));
+        edges[6] = (6, 5);
+        assert_eq!(invalid(&edges), MatchError::RepeatedColumn(5));
+        edges[0] = (0, 1);
+        edges[1] = (1, 0);
+        edges[6] = (6, 6);
+        assert_eq!(invalid(&edges), MatchError::NotAnEdge(0, 1));
+        assert_eq!(
+            MatchError::NotAnEdge(0, 1).to_string(),
+            "(0, 1) is not an edge"
+        );
+    }
+
+    #[test]
+    fn match_from_permutation() {
+        use super::{Match, MatchError};
+        let matching = Match::from_permutation(&[2, 0, 1]).unwrap();
+        assert_eq!(
+            matching.edges().collect::<Vec<_>>(),
+            [(0, 2), (1, 0), (2, 1)]
+        );
+        assert_eq!(
+            Match::from_permutation(&[2, 0, 3]).err(),
+            Some(MatchError::OutOfRange(2, 3))
+        );
+        assert_eq!(
+            Match::from_permutation(&[1, 0, 1]).err(),
+            Some(MatchError::RepeatedColumn(1))
+        );
+        assert_eq!(Match::from_permutation(&[]).unwrap().size(), 0);
+    }
+
+    #[test]
+    fn no_perfect_match() {
+        // rows 0 and 1 both only reach column 0
+        let graph = super::Graph::parse(r#"{"size": 3, "edges": [[0], [0], [1, 2]]}"#).unwrap();
+        let error = graph.find_perfect_match().unwrap_err();
+        assert_eq!(error.matching.size(), 2);
+        assert!(error.unmatched_rows == [0] || error.unmatched_rows == [1]);
+        assert_eq!(error.unmatched_columns.len(), 1);
+        assert_eq!(graph.find_match(), error.matching);
+        let graph = super::Graph::parse(r#"{"size": 2, "edges": [[0, 1], [0]]}"#).unwrap();
+        assert_eq!(graph.find_perfect_match().unwrap().size(), 2);
+    }
 }
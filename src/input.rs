@@ -0,0 +1,148 @@
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// Path standing for standard input.
+pub const STDIN_PATH: &str = "-";
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Open `path` for reading, or standard input for [`STDIN_PATH`], with
+/// [`decompress`] applied.
+pub fn open(path: &Path) -> anyhow::Result<Box<dyn Read>> {
+    if path == Path::new(STDIN_PATH) {
+        return decompress(std::io::stdin().lock());
+    }
+    decompress(std::fs::File::open(path)?)
+}
+
+/// Read `reader` through a decompressor if it starts with a gzip or zstd magic
+/// number. Each format needs the cargo feature of the same name.
+pub fn decompress<R: Read + 'static>(reader: R) -> anyhow::Result<Box<dyn Read>> {
+    let mut reader = BufReader::new(reader);
+    let head = reader.fill_buf()?;
+    if head.starts_with(&GZIP_MAGIC) {
+        gzip(reader)
+    } else if head.starts_with(&ZSTD_MAGIC) {
+        zstd(reader)
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+#[cfg(feature = "gzip")]
+fn gzip<R: BufRead + 'static>(reader: R) -> anyhow::Result<Box<dyn Read>> {
+    Ok(Box::new(flate2::bufread::MultiGzDecoder::new(reader)))
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gzip<R: BufRead + 'static>(_reader: R) -> anyhow::Result<Box<dyn Read>> {
+    anyhow::bail!("gzip input needs the `gzip` feature")
+}
+
+#[cfg(feature = "zstd")]
+fn zstd<R: BufRead + 'static>(reader: R) -> anyhow::Result<Box<dyn Read>> {
+    Ok(Box::new(zstd::stream::read::Decoder::with_buffer(reader)?))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd<R: BufRead + 'static>(_reader: R) -> anyhow::Result<Box<dyn Read>> {
+    anyhow::bail!("zstd input needs the `zstd` feature")
+}
+
+/// Expand a graph path into the files to process: an existing file stands for
+/// itself, a directory for all `*.json` files in it, and anything else is read
+/// as a glob pattern. The files are returned in sorted order. [`STDIN_PATH`]
+/// is kept as is.
+pub fn expand_graph_paths(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if path.is_file() || path == Path::new(STDIN_PATH) {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let pattern = if path.is_dir() {
+        path.join("*.json")
+    } else {
+        path.to_path_buf()
+    };
+    let pattern = pattern
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("{} is not valid unicode", pattern.display()))?;
+    let mut paths = Vec::new();
+    for entry in glob::glob(pattern)? {
+        let entry = entry?;
+        if entry.is_file() {
+            paths.push(entry);
+        }
+    }
+    if paths.is_empty() {
+        anyhow::bail!("no graph file matches {}", pattern);
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::graph::Graph;
+    use std::path::PathBuf;
+
+    #[test]
+    fn expand_directory_and_glob() {
+        let data: PathBuf = PathBuf::from(env!("PWD")).join("data");
+        let dir = std::env::temp_dir().join(format!("graphs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["box.json", "complete.json", "cycle.json"] {
+            std::fs::copy(data.join(name), dir.join(name)).unwrap();
+        }
+        std::fs::write(dir.join("notes.txt"), "not a graph").unwrap();
+        let from_glob = super::expand_graph_paths(&dir.join("*.json")).unwrap();
+        let from_dir = super::expand_graph_paths(&dir).unwrap();
+        assert_eq!(from_glob.len(), 3);
+        assert_eq!(from_glob, from_dir);
+        for path in from_glob.iter() {
+            assert!(Graph::load(path).is_ok(), "{}", path.display());
+        }
+        let single = super::expand_graph_paths(&dir.join("box.json")).unwrap();
+        assert_eq!(single, vec![dir.join("box.json")]);
+        assert!(super::expand_graph_paths(&dir.join("*.bin")).is_err());
+        assert_eq!(
+            super::expand_graph_paths(std::path::Path::new("-")).unwrap(),
+            vec![PathBuf::from("-")]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compressed_input() {
+        let data: PathBuf = PathBuf::from(env!("PWD")).join("data");
+        let json = std::fs::read(data.join("weighted.json")).unwrap();
+        let expected = Graph::load(data.join("weighted.json")).unwrap();
+        let check = |bytes: Vec<u8>, available: bool| {
+            let path = std::env::temp_dir().join(format!("compressed-{}", std::process::id()));
+            std::fs::write(&path, bytes).unwrap();
+            match Graph::load(&path) {
+                Ok(graph) => {
+                    assert!(available);
+                    assert_eq!(graph.weights, expected.weights);
+                }
+                Err(e) => assert!(!available && e.to_string().contains("feature"), "{}", e),
+            }
+            std::fs::remove_file(path).unwrap();
+        };
+        #[cfg(feature = "gzip")]
+        let gzip = {
+            use std::io::Write;
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&json).unwrap();
+            encoder.finish().unwrap()
+        };
+        #[cfg(not(feature = "gzip"))]
+        let gzip = [&super::GZIP_MAGIC[..], &json].concat();
+        check(gzip, cfg!(feature = "gzip"));
+        #[cfg(feature = "zstd")]
+        let zstd = zstd::encode_all(json.as_slice(), 0).unwrap();
+        #[cfg(not(feature = "zstd"))]
+        let zstd = [&super::ZSTD_MAGIC[..], &json].concat();
+        check(zstd, cfg!(feature = "zstd"));
+    }
+}
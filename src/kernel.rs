@@ -0,0 +1,191 @@
+use crate::{
+    filter::{Proposal, MAX_CYCLE_LEN},
+    graph::Match,
+};
+use rand::{
+    seq::{IteratorRandom, SliceRandom},
+    Rng,
+};
+
+/// Proposal mechanics of the Markov chain, kept apart from the Metropolis
+/// acceptance and the filter math in [`crate::filter`].
+pub trait TransitionKernel {
+    /// Propose the next move from the current `matching`.
+    fn propose<R: Rng + ?Sized>(&mut self, matching: &Match, rng: &mut R) -> Proposal;
+    /// `ln(q(x | x') / q(x' | x))`, zero for symmetric kernels.
+    fn log_proposal_ratio(&self, matching: &Match, proposal: &Proposal) -> f64;
+}
+
+/// Swap the endpoints of two uniformly chosen edges of the matching.
+pub struct SwapKernel;
+
+impl TransitionKernel for SwapKernel {
+    fn propose<R: Rng + ?Sized>(&mut self, matching: &Match, rng: &mut R) -> Proposal {
+        let indices = (0..matching.edges.len()).choose_multiple(rng, 2);
+        Proposal::new(matching, indices[0], indices[1]).expect("indices are distinct")
+    }
+
+    fn log_proposal_ratio(&self, _matching: &Match, _proposal: &Proposal) -> f64 {
+        0.0
+    }
+}
+
+/// Rotate `k` uniformly chosen edges of the matching along a uniformly
+/// random cyclic order.
+///
+/// A rotation of odd length is an even permutation, so odd rotations alone
+/// never change the parity of the matching and cannot reach half of them.
+/// For odd `k`, every move is therefore a swap with probability one half.
+///
+/// A rotation and its inverse rotate the same edges along reversed orders,
+/// which are equally likely, so the kernel is symmetric.
+pub struct RotationKernel {
+    k: usize,
+}
+
+impl RotationKernel {
+    /// Kernel rotating `k` edges, with `k` clamped into `2..=MAX_CYCLE_LEN`.
+    pub fn new(k: usize) -> Self {
+        RotationKernel {
+            k: k.clamp(2, MAX_CYCLE_LEN),
+        }
+    }
+}
+
+impl TransitionKernel for RotationKernel {
+    fn propose<R: Rng + ?Sized>(&mut self, matching: &Match, rng: &mut R) -> Proposal {
+        let mut k = self.k.min(matching.edges.len());
+        if k % 2 == 1 && rng.gen::<bool>() {
+            k = 2;
+        }
+        let mut indices = (0..matching.edges.len()).choose_multiple(rng, k);
+        indices.shuffle(rng);
+        Proposal::cycle(matching, &indices).expect("indices are distinct")
+    }
+
+    fn log_proposal_ratio(&self, _matching: &Match, _proposal: &Proposal) -> f64 {
+        0.0
+    }
+}
+
+/// Swap edge pairs in a systematic scan over all `C(n, 2)` position pairs
+/// `(i, j)`, `i < j`, in lexicographic order.
+///
+/// This breaks the random-scan assumption the sampler relies on, so it is
+/// only meant for reproducing and isolating bugs in the transition math.
+pub struct ScanKernel {
+    next: (usize, usize),
+}
+
+impl Default for ScanKernel {
+    fn default() -> Self {
+        ScanKernel { next: (0, 1) }
+    }
+}
+
+impl TransitionKernel for ScanKernel {
+    fn propose<R: Rng + ?Sized>(&mut self, matching: &Match, _rng: &mut R) -> Proposal {
+        let n = matching.edges.len();
+        let (i, j) = self.next;
+        self.next = if j + 1 < n {
+            (i, j + 1)
+        } else if i + 2 < n {
+            (i + 1, i + 2)
+        } else {
+            (0, 1)
+        };
+        Proposal::new(matching, i, j).expect("indices are distinct")
+    }
+
+    fn log_proposal_ratio(&self, _matching: &Match, _proposal: &Proposal) -> f64 {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        cooling_state::State,
+        filter::{AugmentedMatch, Constant},
+        graph::Graph,
+    };
+    use std::{collections::HashMap, path::PathBuf};
+
+    #[test]
+    fn scan_kernel_visits_every_pair() {
+        let mut rng = rand::thread_rng();
+        let matching = Match::random(6, &mut rng);
+        let mut kernel = ScanKernel::default();
+        for _ in 0..3 {
+            let mut pairs: Vec<(usize, usize)> = (0..15)
+                .map(|_| {
+                    let proposal = kernel.propose(&matching, &mut rng);
+                    (proposal.positions()[0], proposal.positions()[1])
+                })
+                .collect();
+            assert!(pairs.iter().all(|(i, j)| i < j && *j < 6));
+            pairs.sort_unstable();
+            pairs.dedup();
+            assert_eq!(pairs.len(), 15);
+        }
+    }
+
+    #[test]
+    fn swap_kernel_reproduces_edge_pair_transitions() {
+        use rand::Rng;
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("box.json")).unwrap();
+        let mut state = State::from(&graph);
+        // non-edges at beta = 1 reject some moves, so the acceptance draws
+        // have to line up as well
+        state.beta = 1.0;
+        let mut rng = crate::filter::chain_rng(Some(4), 0);
+        for u in 0..graph.size {
+            for v in 0..graph.size {
+                state.weight.set(u, v, rng.gen_range(0.5..2.0));
+            }
+        }
+        let matching = Match::random(graph.size, &mut rng);
+        let chain = |stream| {
+            let rng = crate::filter::chain_rng(Some(4), stream);
+            AugmentedMatch::<crate::filter::Additive>::new(matching.clone(), &state, 2, rng)
+        };
+        let (mut old, mut new) = (chain(1), chain(1));
+        for _ in 0..1000 {
+            let (p1, p2) = old.choose_edge_pairs();
+            let proposal = SwapKernel.propose(&new.matching, &mut new.rng);
+            assert_eq!(proposal.positions(), [p1, p2]);
+            assert_eq!(
+                old.transit((p1, p2), &state),
+                new.apply(proposal, 0.0, &state)
+            );
+            assert_eq!(old.matching.edges, new.matching.edges);
+            assert_eq!(old.weight, new.weight);
+        }
+        assert!(0 < old.accepted && old.accepted < old.proposed);
+    }
+
+    #[test]
+    fn swap_kernel_walks_every_matching() {
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("complete.json")).unwrap();
+        let state = State::from(&graph);
+        let rng = crate::filter::chain_rng(Some(7), 0);
+        let mut chain = AugmentedMatch::<Constant>::new(graph.find_match(), &state, 2, rng);
+        let mut visits = HashMap::new();
+        for _ in 0..12000 {
+            chain.transit_with(&mut SwapKernel, &state);
+            *visits.entry(chain.matching.edges.clone()).or_insert(0usize) += 1;
+        }
+        // uniform weights at beta = 0 accept every move, and the swaps reach
+        // each of the 5! matchings about equally often
+        assert_eq!(chain.accepted, chain.proposed);
+        assert_eq!(visits.len(), 120);
+        assert!(
+            visits.values().all(|x| (50..150).contains(x)),
+            "{:?}",
+            visits
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use std::num::NonZeroUsize;
+
+use crate::{
+    graph::Graph,
+    markov_chain::{Config, PermanentEstimate},
+};
+
+pub mod bounds;
+pub mod cooling_schedule;
+pub mod cooling_state;
+pub mod diagnostics;
+pub mod dinic;
+pub mod estimate;
+pub mod exact;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod filter;
+pub mod graph;
+pub mod input;
+pub mod kernel;
+
+pub mod markov_chain;
+pub mod metrics;
+mod par;
+#[cfg(feature = "python")]
+mod python;
+pub mod reservoir;
+pub mod self_test;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Filter {
+    Additive,
+    Multiplicative,
+    Constant,
+}
+
+impl std::str::FromStr for Filter {
+    type Err = String;
+    /// Parse the name of a filter, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "additive" => Ok(Filter::Additive),
+            "multiplicative" => Ok(Filter::Multiplicative),
+            "constant" => Ok(Filter::Constant),
+            _ => Err(format!(
+                "expected `additive`, `multiplicative` or `constant`, got {:?}",
+                s
+            )),
+        }
+    }
+}
+
+/// Slow-down of the additive and multiplicative cooling increments used by
+/// [`estimate_permanent`], the same as the command line default.
+pub const DEFAULT_SLOW_DOWN: NonZeroUsize = NonZeroUsize::new(4).unwrap();
+
+/// Estimate the permanent of the adjacency matrix of `graph`.
+///
+/// The chains run on the current rayon pool: the global one, which rayon sets
+/// up on first use unless the caller already has, or the pool of an enclosing
+/// `ThreadPool::install` (see [`estimate_permanent_in`]).
+pub fn estimate_permanent(graph: &Graph, config: &Config, filter: Filter) -> f64 {
+    estimate_permanent_with_error(graph, config, filter).value
+}
+
+/// Like [`estimate_permanent`], with the logarithm and the relative error of
+/// the estimate.
+pub fn estimate_permanent_with_error(
+    graph: &Graph,
+    config: &Config,
+    filter: Filter,
+) -> PermanentEstimate {
+    let (add, mul) = (DEFAULT_SLOW_DOWN, DEFAULT_SLOW_DOWN);
+    match filter {
+        Filter::Additive => {
+            estimate::estimate_run::<filter::Additive>(graph.clone(), *config, add, mul)
+        }
+        Filter::Multiplicative => {
+            estimate::estimate_run::<filter::Multiplicative>(graph.clone(), *config, add, mul)
+        }
+        Filter::Constant => {
+            estimate::estimate_run::<filter::Constant>(graph.clone(), *config, add, mul)
+        }
+    }
+}
+
+/// Estimate the permanent of the square matrix `a`, whose nonzero entries
+/// are the weighted edges of the graph; see [`Graph::from_ndarray`].
+#[cfg(feature = "ndarray")]
+pub fn estimate_permanent_ndarray(
+    a: ndarray::ArrayView2<f64>,
+    config: &Config,
+    filter: Filter,
+) -> anyhow::Result<f64> {
+    Ok(estimate_permanent(&Graph::from_ndarray(a)?, config, filter))
+}
+
+/// Estimate the permanent from `replicas` independent runs seeded
+/// `base_seed`, `base_seed + 1`, ..., combined by
+/// [`estimate::jackknife`]. Each run gets the whole time budget of `config`.
+pub fn estimate_permanent_replicated(
+    graph: &Graph,
+    config: &Config,
+    filter: Filter,
+    replicas: NonZeroUsize,
+    base_seed: u64,
+) -> PermanentEstimate {
+    let estimates = (0..replicas.get() as u64)
+        .map(|r| {
+            let config = Config {
+                seed: Some(base_seed.wrapping_add(r)),
+                ..*config
+            };
+            estimate_permanent_with_error(graph, &config, filter)
+        })
+        .collect::<Vec<_>>();
+    let log_values = estimates.iter().map(|x| x.log_value).collect::<Vec<_>>();
+    PermanentEstimate {
+        truncated: estimates.iter().any(|x| x.truncated),
+        ..estimate::jackknife(&log_values)
+    }
+}
+
+/// [`estimate_permanent`] on the given thread pool.
+#[cfg(feature = "parallel")]
+pub fn estimate_permanent_in(
+    pool: &rayon::ThreadPool,
+    graph: &Graph,
+    config: &Config,
+    filter: Filter,
+) -> f64 {
+    pool.install(|| estimate_permanent(graph, config, filter))
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn filter_names() {
+        use super::Filter;
+        assert_eq!("additive".parse(), Ok(Filter::Additive));
+        assert_eq!("Constant".parse(), Ok(Filter::Constant));
+        assert!("metropolis".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn replicated_estimate() {
+        let config = crate::markov_chain::Config {
+            deterministic_reduction: true,
+            ..crate::markov_chain::Config::quick()
+        };
+        let graph = crate::graph::Graph::complete(4);
+        let replicas = std::num::NonZeroUsize::new(3).unwrap();
+        let run = |seed| {
+            super::estimate_permanent_replicated(
+                &graph,
+                &config,
+                super::Filter::Additive,
+                replicas,
+                seed,
+            )
+        };
+        let estimate = run(7);
+        assert_eq!(estimate, run(7));
+        assert!(estimate.rel_std_error.is_finite());
+        assert!(
+            (estimate.log_value - 24f64.ln()).abs() < 1.0,
+            "{:?}",
+            estimate
+        );
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn estimate_from_ndarray() {
+        let path: std::path::PathBuf = env!("PWD").into();
+        let graph = crate::graph::Graph::load(path.join("data").join("weighted.json")).unwrap();
+        let n = graph.size;
+        let dense = (0..n * n)
+            .map(|x| graph.edge_weight(x / n, x % n))
+            .collect();
+        let a = ndarray::Array2::from_shape_vec((n, n), dense).unwrap();
+        let config = crate::markov_chain::Config {
+            num_of_chains: 64,
+            warmup_times: 512,
+            seed: Some(1),
+            deterministic_reduction: true,
+            ..crate::markov_chain::Config::quick()
+        };
+        let estimate =
+            super::estimate_permanent_ndarray(a.view(), &config, super::Filter::Constant).unwrap();
+        let reference =
+            super::estimate_permanent_with_error(&graph, &config, super::Filter::Constant);
+        assert_eq!(estimate, reference.value);
+        // the permanent of weighted.json is 51.5
+        assert!(
+            (reference.log_value - 51.5f64.ln()).abs() < 3.0 * reference.rel_std_error,
+            "{:?}",
+            reference
+        );
+        let wide = ndarray::Array2::from_elem((2, 3), 1.0);
+        assert!(
+            super::estimate_permanent_ndarray(wide.view(), &config, super::Filter::Constant)
+                .is_err()
+        );
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn estimate_on_own_pool() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let config = crate::markov_chain::Config::quick();
+        let graph = crate::graph::Graph::complete(4);
+        let estimate =
+            super::estimate_permanent_in(&pool, &graph, &config, super::Filter::Constant);
+        assert!((estimate - 24.0).abs() < 1e-6, "estimate = {}", estimate);
+    }
+}
@@ -1,32 +1,56 @@
-use std::num::NonZeroUsize;
+use std::{
+    io::Write,
+    num::NonZeroUsize,
+    ops::ControlFlow,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
+};
 
-use clap::{Parser, ValueEnum};
-use filter::MetropolisFilter;
-use tracing::{error, info, level_filters::LevelFilter};
+use anyhow::Context;
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use tracing::{error, info, level_filters::LevelFilter, warn};
 use tracing_subscriber::EnvFilter;
 
-use crate::{
-    cooling_schedule::CoolingConfig,
-    graph::Graph,
-    markov_chain::{Config, MCState},
+use permanent::{
+    bounds,
+    cooling_schedule::{CoolingConfig, CoolingSchedule},
+    cooling_state::{Matrix, State},
+    diagnostics::MixingProfile,
+    estimate::{self, ConvergenceCriteria, Estimate, EstimatorReport, WeightScale},
+    filter::{self, MetropolisFilter, MAX_CYCLE_LEN},
+    graph::{self, CostMatrix, Graph},
+    input,
+    markov_chain::{self, AnnealingDirection, Config, MCState, PermanentEstimate, WarmupMode},
+    metrics::MetricsSink,
+    self_test, Filter,
 };
 
-pub mod cooling_schedule;
-pub mod cooling_state;
-pub mod dinic;
-pub mod filter;
-pub mod graph;
-
-pub mod markov_chain;
-
+#[cfg(feature = "mimalloc")]
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
+/// Set by the first Ctrl-C: the cooling stops after the current step, and no
+/// further attempt or graph is started. A second Ctrl-C quits immediately.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
 #[derive(Parser, Debug)]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 pub struct Cli {
-    /// Path to the graph file.
-    #[arg(short, long)]
-    pub graph_path: std::path::PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// The flags of `estimate`, still accepted without the subcommand but
+    /// deprecated.
+    #[command(flatten)]
+    pub estimate: EstimateArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct EstimateArgs {
+    /// Path to the graph file, or a directory or glob pattern of graph files
+    /// to estimate one after another; `-` reads standard input.
+    #[arg(short, long, required = true)]
+    pub graph_path: Option<std::path::PathBuf>,
     /// Number of chains.
     #[arg(short, long, default_value_t = 2048)]
     pub num_of_chains: usize,
@@ -57,44 +81,597 @@ pub struct Cli {
     /// Metroplis filter to use.
     #[arg(short = 'f', long, default_value = "additive")]
     pub filter: Filter,
+    /// Number of matched edges rotated by one move, or `auto` to derive it
+    /// from the degrees of the graph.
+    #[arg(long, default_value = "2")]
+    pub move_size: MoveSize,
+    /// Resume the cooling schedule from the first beta that is at least this value.
+    /// Without the steps before it, the run reports the partial ratio
+    /// `Z(beta) / Z(start)` of the partition functions instead of the permanent.
+    #[arg(long)]
+    pub resume_schedule_at: Option<f64>,
+    /// Write the thinned trajectory of one chain to this path as JSONL.
+    #[arg(long)]
+    pub chain_thinning_output: Option<std::path::PathBuf>,
+    /// Index of the chain whose trajectory is recorded.
+    #[arg(long, default_value_t = 0)]
+    pub thinning_chain: usize,
+    /// Number of transitions between two recorded trajectory points.
+    #[arg(long, default_value_t = NonZeroUsize::new(1).unwrap())]
+    pub thinning_interval: NonZeroUsize,
+    /// Clamp each cooling ratio into `[1/r, r]`. This stabilizes noisy runs
+    /// against single-step blowups but biases the estimate.
+    #[arg(long)]
+    pub cooling_ratio_clamp: Option<f64>,
+    /// Relabel vertices by degree before sampling for better locality.
+    #[arg(long, default_value_t = false)]
+    pub relabel: bool,
+    /// Dump the initial weights, matchings and beta to this path as JSON.
+    #[arg(long)]
+    pub dump_initial_state: Option<std::path::PathBuf>,
+    /// Retry a run that fails the convergence check up to this many times,
+    /// doubling the warmup after each failure.
+    #[arg(long, default_value_t = 0)]
+    pub auto_retry: usize,
+    /// Also double the number of chains on each retry.
+    #[arg(long, default_value_t = false)]
+    pub retry_grow_chains: bool,
+    /// Minimal ratio of effective to nominal sample size for a converged run.
+    #[arg(long, default_value_t = 0.05)]
+    pub min_ess_ratio: f64,
+    /// Treat the input as a cost matrix `C` and estimate the permanent of `exp(-C / T)`.
+    #[arg(long, default_value_t = false)]
+    pub cost_input: bool,
+    /// Ignore the weights of a cost matrix input and estimate the number of
+    /// perfect matchings of its support.
+    #[arg(long, default_value_t = false)]
+    pub count_matchings: bool,
+    /// Temperature `T` of the Gibbs matrix in cost input mode.
+    #[arg(long, default_value_t = 1.0)]
+    pub temperature: f64,
+    /// Number of matchings kept by `--reservoir-output` and `--dump-samples`.
+    #[arg(long, default_value_t = 200)]
+    pub reservoir_size: usize,
+    /// Write a uniform subsample of the matchings at the final beta to this
+    /// path as JSON.
+    #[arg(long)]
+    pub reservoir_output: Option<std::path::PathBuf>,
+    /// Write a uniform subsample of the matchings of the estimator samples of
+    /// all cooling steps to this path as JSON lines. Recording only observes
+    /// those samples, so the estimate stays the same.
+    #[arg(long, value_name = "PATH")]
+    pub dump_samples: Option<std::path::PathBuf>,
+    /// Warn when a cooling step accepts fewer estimator samples than this.
+    #[arg(long, default_value_t = 0)]
+    pub estimator_variance_floor: usize,
+    /// Report raw cooling ratios above one instead of clamping them to one.
+    #[arg(long, default_value_t = false)]
+    pub allow_ratio_above_one: bool,
+    /// Only profile the mixing of a single chain for this many transitions
+    /// after warmup, instead of estimating the permanent.
+    #[arg(long)]
+    pub profile_mixing: Option<usize>,
+    /// Largest lag reported by the mixing profile.
+    #[arg(long, default_value_t = 64)]
+    pub max_lag: usize,
+    /// Only run every filter this many times on the (small) graph and report
+    /// its bias against the exact permanent.
+    #[arg(long)]
+    pub compare_filters_exact: Option<usize>,
+    /// Print the power sums `trace(W^k)` of the final weight matrix for
+    /// `k = 1..=K` as a JSON array.
+    #[arg(long, value_name = "K")]
+    pub output_newton_girard: Option<usize>,
+    /// Cycle through all edge pairs in order instead of sampling them. This
+    /// breaks the random-scan assumption of the sampler; for debugging only.
+    #[arg(long, default_value_t = false)]
+    pub deterministic_proposals: bool,
+    /// Accept a move iff its probability exceeds this fixed threshold instead
+    /// of a uniform draw; for debugging only.
+    #[arg(long)]
+    pub acceptance_threshold: Option<f64>,
+    /// Remove the first-order bias of each cooling ratio, estimated from the
+    /// spread across chains. Needs at least two chains.
+    #[arg(long, default_value_t = false)]
+    pub bias_correction: bool,
+    /// Report a certified lower bound on the permanent from edge-disjoint
+    /// perfect matchings and warn if the estimate falls below it.
+    #[arg(long, default_value_t = false)]
+    pub lower_bound: bool,
+    /// Report the van der Waerden and Bregman-Minc bounds on the permanent
+    /// and warn if the estimate falls outside of them.
+    #[arg(long, default_value_t = false)]
+    pub bounds: bool,
+    /// Seed of the random number generators; with a fixed seed and
+    /// `--deterministic-reduction` the run is reproducible whatever the thread
+    /// count.
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Stop starting cooling steps once this much wall-clock time, such as
+    /// `90s` or `30m`, has passed, and report the estimate so far.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub time_budget: Option<std::time::Duration>,
+    /// Rerun with more chains until the relative standard error of the
+    /// estimate is at most this, or `--max-chains` is reached.
+    #[arg(long)]
+    pub target_rel_error: Option<f64>,
+    /// Most chains a run with `--target-rel-error` grows to.
+    #[arg(long, default_value_t = 65536)]
+    pub max_chains: usize,
+    /// Number of independent runs, seeded `seed`, `seed + 1`, ...; their
+    /// estimates are combined by geometric mean with a jackknife error.
+    #[arg(long, default_value = "1")]
+    pub replicas: NonZeroUsize,
+    /// Sum the chains' contributions in chain order instead of whatever order
+    /// the threads finish in; slower, but the floating-point rounding then no
+    /// longer depends on the thread count.
+    #[arg(long, default_value_t = false)]
+    pub deterministic_reduction: bool,
+    /// Checkpoint the sampler to PATH during cooling; if PATH already holds a
+    /// checkpoint, skip the warmup and continue the cooling from it. The
+    /// checkpoint is removed once the cooling finishes. With `--replicas`
+    /// every replica after the first checkpoints to PATH with `.replica<R>`
+    /// inserted before the extension.
+    #[arg(long, value_name = "PATH")]
+    pub checkpoint: Option<std::path::PathBuf>,
+    /// Number of cooling steps between two checkpoints.
+    #[arg(long, default_value = "1")]
+    pub checkpoint_every: NonZeroUsize,
+    /// Estimate the cooling ratios forward, in reverse, or from both
+    /// directions combined by Bennett's acceptance ratio.
+    #[arg(long, value_enum, default_value_t = AnnealingDirection::Forward)]
+    pub annealing_direction: AnnealingDirection,
+    /// Adapt the beta increments to keep every cooling ratio within
+    /// `[LOW, HIGH]`, re-running a step with half the increment when its ratio
+    /// falls below `LOW`.
+    #[arg(long, num_args = 2, value_names = ["LOW", "HIGH"])]
+    pub adaptive_band: Option<Vec<f64>>,
+    /// Repeat the warmup until the Gelman–Rubin R-hat of the chain weights
+    /// drops below this threshold, e.g. 1.1.
+    #[arg(long)]
+    pub r_hat_threshold: Option<f64>,
+    /// After the warmup, restart the chains whose weight lies more than this
+    /// many standard deviations from the median from a random matching.
+    #[arg(long, value_name = "SIGMAS")]
+    pub restart_outliers: Option<f64>,
+    /// Reduce the variance of the cooling ratios with the acceptance coins of
+    /// the rejection sampler as control variates.
+    #[arg(long)]
+    pub control_variate: bool,
+    /// Run the warmup for `--warmup-times` transitions, or until the chains
+    /// have advanced several measured autocorrelation times.
+    #[arg(long, value_enum, default_value_t = WarmupMode::Fixed)]
+    pub warmup_mode: WarmupMode,
+    /// Initial weight matrix of the chains.
+    #[arg(long, value_enum, default_value_t = WeightInit::Uniform)]
+    pub init: WeightInit,
+    /// Rank of the SVD truncation for `--init spectral`.
+    #[arg(long, default_value_t = 1)]
+    pub init_rank: usize,
+    /// Rounds of row and column normalization for `--init sinkhorn`.
+    #[arg(long, default_value_t = 64)]
+    pub sinkhorn_iterations: usize,
+    /// Format of the input file.
+    #[arg(long, value_enum, default_value_t = InputFormat::Graph)]
+    pub format: InputFormat,
+    /// Number of vertices per side of an `--format edge-list` graph.
+    #[arg(long, required_if_eq("format", "edge-list"))]
+    pub vertices: Option<usize>,
+    /// Format of the summary written to stdout at the end of the run.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    pub output_format: OutputFormat,
+    /// Stream per-step metrics as JSON lines to `host:port` or `unix:/path`.
+    #[arg(long)]
+    pub metrics_socket: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Estimate the permanent of a graph.
+    Estimate(Box<EstimateArgs>),
+    /// Compute the permanent of a small graph exactly.
+    Exact(ExactArgs),
+    /// Estimate the permanent of a small graph and report its relative error
+    /// against the exact value.
+    Validate(Box<EstimateArgs>),
+    /// Write a random bipartite graph as JSON.
+    Generate(GenerateArgs),
+    /// Run short estimates on the bundled fixtures and compare them with the
+    /// exact permanents.
+    SelfTest,
+}
+
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("random").required(true).args(["edge_prob", "extra_edges"])))]
+pub struct GenerateArgs {
+    /// Number of vertices per side.
+    #[arg(long)]
+    pub size: usize,
+    /// Include every edge independently with this probability.
+    #[arg(long)]
+    pub edge_prob: Option<f64>,
+    /// Plant a random perfect matching and add this many random edges.
+    #[arg(long)]
+    pub extra_edges: Option<usize>,
+    /// Seed of the generator.
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+    /// Write the graph to this path instead of standard output.
+    #[arg(short, long)]
+    pub output: Option<std::path::PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct ExactArgs {
+    /// Path to the graph file; `-` reads standard input.
+    pub graph_path: std::path::PathBuf,
+    /// Format of the input file.
+    #[arg(long, value_enum, default_value_t = InputFormat::Graph)]
+    pub format: InputFormat,
+    /// Number of vertices per side of an `--format edge-list` graph.
+    #[arg(long, required_if_eq("format", "edge-list"))]
+    pub vertices: Option<usize>,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum WeightInit {
+    /// Every edge starts with the same weight.
+    Uniform,
+    /// Experimental: weights from the top singular vectors of the adjacency.
+    Spectral,
+    /// Weights from the Sinkhorn (doubly stochastic) scaling of the adjacency.
+    Sinkhorn,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// Bipartite graph (or cost matrix with `--cost-input`).
+    Graph,
+    /// Lines of `u v` edges, with `--vertices` vertices per side.
+    EdgeList,
+    /// Undirected DIMACS `.col` graph, read as its symmetric adjacency matrix.
+    Dimacs,
+    /// Square Matrix Market coordinate matrix, weighted unless a pattern.
+    MatrixMarket,
+    /// Complex matrix of `[re, im]` pairs, computed exactly with Ryser's
+    /// formula since MCMC only handles nonnegative weights. Needs the
+    /// `complex` feature.
+    ComplexMatrix,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// log lines only
+    Human,
+    /// a Markdown table summarizing the run
+    Markdown,
+    /// a JSON object with the estimate, its log, relative error and betas
+    Json,
 }
 
-#[derive(Parser, Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
-pub enum Filter {
-    Additive,
-    Multiplicative,
-    Constant,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveSize {
+    Auto,
+    Fixed(usize),
+}
+
+impl std::str::FromStr for MoveSize {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(MoveSize::Auto),
+            _ => match s.parse::<usize>() {
+                Ok(k) if (2..=MAX_CYCLE_LEN).contains(&k) => Ok(MoveSize::Fixed(k)),
+                _ => Err(format!(
+                    "expected `auto` or an integer between 2 and {}, got {:?}",
+                    MAX_CYCLE_LEN, s
+                )),
+            },
+        }
+    }
 }
 
 fn run_chain<F: MetropolisFilter + Send + Sync + 'static>(
     graph: Graph,
+    cost: Option<&CostMatrix>,
     config: Config,
-    add_factor: NonZeroUsize,
-    mul_factor: NonZeroUsize,
-) {
+    replica: usize,
+    cli: &EstimateArgs,
+) -> anyhow::Result<EstimatorReport> {
     let size = graph.size;
-    let mut state = MCState::<F>::new(graph, config);
-    state.warmup();
-    info!("Warmup finished");
+    let scale = match cost {
+        Some(cost) => WeightScale::of_cost(cost, cli.temperature),
+        None => WeightScale::of(&graph),
+    };
+    let bounds = match cost {
+        Some(cost) => cost.gibbs_bounds(cli.temperature),
+        None => Estimate::graph_bounds(&graph),
+    };
+    let (graph, permutation) = if cli.relabel && cost.is_none() {
+        graph.relabel_by_degree()
+    } else {
+        (graph, (0..size).collect())
+    };
+    let mut global_state = match cost {
+        Some(cost) => State::from_cost(cost, cli.temperature),
+        None => State::from(&graph),
+    };
+    if cli.init == WeightInit::Spectral {
+        global_state.spectral_init(cli.init_rank);
+        info!("Weights initialized from a rank {} SVD", cli.init_rank);
+    }
+    let mut state = MCState::<F>::with_state(graph, global_state, config);
+    let mut resumed = false;
+    let checkpoint = cli
+        .checkpoint
+        .as_deref()
+        .map(|path| replica_checkpoint(path, replica));
+    if let Some(path) = &checkpoint {
+        if path.exists() {
+            state.restore(path)?;
+            resumed = true;
+            info!(
+                "Resuming from {} at beta = {:.5}",
+                path.display(),
+                state.global_state.beta
+            );
+        }
+        state.set_checkpoint(path.clone(), cli.checkpoint_every);
+    }
+    if cli.chain_thinning_output.is_some() {
+        state.record_trajectory(cli.thinning_chain, cli.thinning_interval)?;
+    }
+    if let Some(path) = &cli.dump_initial_state {
+        let writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        simd_json::to_writer(writer, &state.snapshot())?;
+        info!("Initial state written to {}", path.display());
+    }
+    if let Some(addr) = &cli.metrics_socket {
+        state.set_metrics_sink(MetricsSink::connect(addr)?);
+        info!("Streaming metrics to {}", addr);
+    }
+    let warmup_start = Instant::now();
+    if !resumed {
+        let stats = state.warmup_with_stats();
+        info!(
+            "Warmup finished: acceptance rate {:.3}, chain weights {:.3}/{:.3}/{:.3} (min/mean/max), turnover {:.2}",
+            stats.acceptance_rate,
+            stats.min_weight,
+            stats.mean_weight,
+            stats.max_weight,
+            stats.turnover
+        );
+        if !stats.mixed {
+            warn!(
+                "chains accepted only {:.2} moves per matched edge, --warmup-times may be too short",
+                stats.turnover
+            );
+        }
+        if let Some(tau) = state.correlation_time() {
+            info!(
+                "Correlation time {:.1} transitions, --weight-sample-intervals {} would sample nearly independently",
+                tau,
+                (2.0 * tau).ceil()
+            );
+        }
+    }
+    let warmup_time = warmup_start.elapsed();
     let cooling_cfg = CoolingConfig {
         n: NonZeroUsize::new(size).unwrap(),
-        additive_ratio: add_factor,
-        multiplicative_ratio: mul_factor,
+        additive_ratio: cli.additive_slow_down,
+        multiplicative_ratio: cli.multiplicative_slow_down,
     };
-    let schedule = crate::cooling_schedule::CoolingSchedule::from(cooling_cfg);
-    state.cooling_evolve(schedule, false);
-    info!("final weight matrix:");
-    for i in 0..size {
-        for j in 0..size {
-            // print state.global_state.weight.get(i, j)
-            print!("{:.2} ", 1.0 / state.global_state.weight.get(i, j));
+    let mut schedule = CoolingSchedule::from(cooling_cfg);
+    let mut resumed_at = None;
+    if let Some(beta) = cli.resume_schedule_at {
+        if resumed {
+            anyhow::bail!("a checkpoint resumes at its own beta, drop --resume-schedule-at");
         }
-        println!();
+        match schedule.fast_forward(beta) {
+            Some(start) => {
+                info!("Resuming cooling schedule at beta = {:.5}", start);
+                state.global_state.beta = start;
+                resumed_at = Some(start);
+            }
+            None => anyhow::bail!("cooling schedule never reaches beta = {:.5}", beta),
+        }
+    }
+    if cli.dump_samples.is_some() {
+        state.record_samples(cli.reservoir_size);
     }
+    let cooling_start = Instant::now();
+    let mut progress = |progress| {
+        markov_chain::log_progress(progress)?;
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    };
+    let permanent = state.cooling_evolve_with(scale.schedule(schedule), false, &mut progress);
+    let cooling_time = cooling_start.elapsed();
+    if INTERRUPTED.load(Ordering::Relaxed) {
+        warn!(
+            "interrupted at beta = {:.5}, the estimate only covers the cooling so far",
+            state.global_state.beta
+        );
+    } else if let Some(path) = checkpoint.as_ref().filter(|_| !permanent.truncated) {
+        // a finished cooling has nothing left to resume, and a retry must not
+        // pick it up again in place of its own warmup
+        if path.exists() {
+            std::fs::remove_file(path)?;
+            info!("Cooling finished, checkpoint {} removed", path.display());
+        }
+    }
+    let (permanent, bounds) = match resumed_at {
+        // the cooling started at ln(n!) as if every skipped ratio were one
+        Some(start) => {
+            let log_value = permanent.log_value - markov_chain::ln_factorial(size);
+            info!(
+                "ln(Z({:.5}) / Z({:.5})) = {:.5} +/- {:.5} over {} chains, not an estimate of the permanent",
+                state.global_state.beta,
+                start,
+                log_value,
+                permanent.rel_std_error,
+                config.num_of_chains
+            );
+            let partial = PermanentEstimate {
+                value: log_value.exp(),
+                log_value,
+                ..permanent
+            };
+            (partial, (0.0, f64::INFINITY))
+        }
+        None => {
+            let permanent = scale.apply(permanent);
+            info!(
+                "ln(estimate) = {:.5} +/- {:.5} over {} chains",
+                permanent.log_value, permanent.rel_std_error, config.num_of_chains
+            );
+            (permanent, bounds)
+        }
+    };
+    let value = permanent.value;
+    if state.clamped_ratios() > 0 {
+        warn!(
+            "{} cooling ratios were clamped, the estimate is biased",
+            state.clamped_ratios()
+        );
+    }
+    if state.undersampled_steps() > 0 {
+        warn!(
+            "{} cooling steps were undersampled, the estimate is noise-dominated",
+            state.undersampled_steps()
+        );
+    }
+    if let Some(path) = &cli.reservoir_output {
+        let samples: Vec<Vec<(usize, usize)>> = state
+            .sample_reservoir(cli.reservoir_size)
+            .into_iter()
+            .map(|x| {
+                x.into_iter()
+                    .map(|(u, v)| (permutation[u], permutation[v]))
+                    .collect()
+            })
+            .collect();
+        let writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        simd_json::to_writer(writer, &samples)?;
+        info!(
+            "{} sampled matchings written to {}",
+            samples.len(),
+            path.display()
+        );
+    }
+    if let Some(path) = &cli.dump_samples {
+        let samples = state.take_samples();
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        for matching in samples.iter() {
+            let edges: Vec<(usize, usize)> = matching
+                .iter()
+                .map(|(u, v)| (permutation[*u], permutation[*v]))
+                .collect();
+            simd_json::to_writer(&mut writer, &edges)?;
+            writeln!(writer)?;
+        }
+        info!(
+            "{} sampled matchings written to {}",
+            samples.len(),
+            path.display()
+        );
+    }
+    if let (Some(path), Some(trajectory)) = (&cli.chain_thinning_output, state.trajectory()) {
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        for point in trajectory {
+            simd_json::to_writer(&mut writer, point)?;
+            writeln!(writer)?;
+        }
+        info!(
+            "{} trajectory points written to {}",
+            trajectory.len(),
+            path.display()
+        );
+    }
+    let estimate = state.estimate(permanent, bounds, warmup_time, cooling_time);
+    info!(
+        "effective sample size: {:.1} (nominal {}), acceptance rate {:.3}",
+        estimate.effective_sample_size, estimate.nominal_sample_size, estimate.acceptance_rate
+    );
+    if cli.lower_bound && resumed_at.is_none() {
+        let bound = match cost {
+            Some(cost) => estimate::disjoint_matchings_lower_bound(state.graph(), |u, v| {
+                (-cost.cost[u][v] / cli.temperature).exp()
+            }),
+            None => estimate::disjoint_matchings_lower_bound(state.graph(), |u, v| {
+                state.graph().edge_weight(u, v)
+            }),
+        };
+        info!("certified lower bound: {:.5}", bound);
+        if value < bound {
+            warn!(
+                "estimate {:.5} is below the certified lower bound {:.5}, the estimator failed",
+                value, bound
+            );
+        }
+    }
+    if cli.bounds && resumed_at.is_none() {
+        let weighted_graph = cost.map(|cost| cost.gibbs_graph(cli.temperature));
+        let graph = weighted_graph.as_ref().unwrap_or(state.graph());
+        let lower = bounds::van_der_waerden_lower(graph);
+        let upper = bounds::bregman_minc_upper(graph);
+        info!(
+            "van der Waerden lower bound: {:.5}, Bregman-Minc upper bound: {:.5}",
+            lower, upper
+        );
+        if !(lower..=upper).contains(&value) {
+            warn!(
+                "estimate {:.5} is outside of the analytic bounds [{:.5}, {:.5}]",
+                value, lower, upper
+            );
+        }
+    }
+    let report = EstimatorReport {
+        resumed_at,
+        ..state.into_report(estimate)
+    };
+    if let Some(max_power) = cli.output_newton_girard {
+        let traces = report.weight.to_dense().power_traces(max_power);
+        println!("{}", simd_json::to_string(&traces)?);
+    }
+    if cli.output_format != OutputFormat::Json {
+        info!("final weight matrix:");
+        let mut inverse = vec![0; size];
+        for (new, old) in permutation.iter().copied().enumerate() {
+            inverse[old] = new;
+        }
+        let frequencies = report.edge_frequencies();
+        let mut matrix = Matrix::new(size, 0.0);
+        for (u, i) in inverse.iter().copied().enumerate() {
+            for (v, j) in inverse.iter().copied().enumerate() {
+                matrix.set(u, v, frequencies[i][j]);
+            }
+        }
+        println!("{}", matrix);
+    }
+    Ok(report)
+}
+
+fn profile_mixing<F: MetropolisFilter + Send + Sync + 'static>(
+    graph: Graph,
+    config: Config,
+    steps: usize,
+    cli: &EstimateArgs,
+) -> MixingProfile {
+    let config = Config {
+        num_of_chains: 1,
+        ..config
+    };
+    MCState::<F>::new(graph, config).profile_mixing(steps, cli.max_lag)
 }
 
 fn main() {
+    // logs go to stderr, so that graphs and reports on stdout can be piped
     tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
         .with_env_filter(
             EnvFilter::builder()
                 .with_default_directive(LevelFilter::INFO.into())
@@ -103,21 +680,361 @@ fn main() {
         )
         .init();
     let cli = Cli::parse();
-    let thd_cnt = cli.num_of_threads.unwrap_or_else(|| {
-        std::thread::available_parallelism()
-            .map(|x| x.get())
-            .unwrap_or(1)
-    });
-    info!("Using {} threads", thd_cnt);
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(thd_cnt)
-        .build_global()
-        .unwrap();
-    let graph = Graph::load(cli.graph_path).unwrap();
+    let num_of_threads = match &cli.command {
+        Some(Command::Estimate(args) | Command::Validate(args)) => args.num_of_threads,
+        Some(_) => None,
+        None => cli.estimate.num_of_threads,
+    };
+    #[cfg(feature = "parallel")]
+    {
+        let thd_cnt = num_of_threads.unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|x| x.get())
+                .unwrap_or(1)
+        });
+        info!("Using {} threads", thd_cnt);
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(thd_cnt)
+            .build_global()
+            .unwrap();
+    }
+    #[cfg(not(feature = "parallel"))]
+    if num_of_threads.is_some_and(|n| n > 1) {
+        warn!("built without the `parallel` feature, running on a single thread");
+    }
+    if matches!(
+        cli.command,
+        None | Some(Command::Estimate(_) | Command::Validate(_))
+    ) {
+        let handler = ctrlc::set_handler(|| {
+            if INTERRUPTED.swap(true, Ordering::Relaxed) {
+                std::process::exit(130);
+            }
+            warn!("interrupted, finishing the current step; press Ctrl-C again to quit");
+        });
+        if let Err(e) = handler {
+            warn!("failed to install the Ctrl-C handler: {}", e);
+        }
+    }
+    let failed = match &cli.command {
+        Some(Command::SelfTest) => !run_self_test(),
+        Some(Command::Exact(args)) => !run_exact(args),
+        Some(Command::Generate(args)) => match run_generate(args) {
+            Ok(()) => false,
+            Err(e) => {
+                error!("{:#}", e);
+                true
+            }
+        },
+        Some(Command::Estimate(args)) => {
+            !for_each_graph(args, |path| run_graph(args, path).map(drop))
+        }
+        Some(Command::Validate(args)) => !for_each_graph(args, |path| run_validate(args, path)),
+        None => {
+            warn!("running without a subcommand is deprecated, use `estimate`");
+            !for_each_graph(&cli.estimate, |path| {
+                run_graph(&cli.estimate, path).map(drop)
+            })
+        }
+    };
+    if INTERRUPTED.load(Ordering::Relaxed) {
+        std::process::exit(130);
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// Run `f` on every graph matched by the graph path of `cli`, logging the
+/// graphs it fails on and returning whether there were none.
+fn for_each_graph(cli: &EstimateArgs, mut f: impl FnMut(&Path) -> anyhow::Result<()>) -> bool {
+    let pattern = cli.graph_path.as_ref().expect("graph path is required");
+    let paths = match input::expand_graph_paths(pattern) {
+        Ok(paths) => paths,
+        Err(e) => {
+            error!("{:#}", e);
+            return false;
+        }
+    };
+    let mut failures = 0;
+    for path in paths.iter() {
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            break;
+        }
+        if paths.len() > 1 {
+            info!("Processing {}", path.display());
+        }
+        if let Err(e) = f(path) {
+            error!("{:#}", e);
+            failures += 1;
+        }
+    }
+    if failures > 0 && paths.len() > 1 {
+        error!("{} of {} graphs failed", failures, paths.len());
+    }
+    failures == 0
+}
+
+/// Run the self-test and print a line per fixture, returning whether every
+/// fixture passed.
+fn run_self_test() -> bool {
+    let results = self_test::run();
+    let mut failures = 0;
+    for (name, result) in results.iter() {
+        match result {
+            Ok(()) => println!("PASS {}", name),
+            Err(e) => {
+                error!("{}: {:#}", name, e);
+                println!("FAIL {}", name);
+                failures += 1;
+            }
+        }
+    }
+    println!("{} passed, {} failed", results.len() - failures, failures);
+    failures == 0
+}
+
+/// Print the exact permanent of the graph given by `args`, returning whether
+/// it could be computed.
+fn run_exact(args: &ExactArgs) -> bool {
+    if args.format == InputFormat::ComplexMatrix {
+        return match run_complex(&args.graph_path) {
+            Ok(()) => true,
+            Err(e) => {
+                error!("{:#}", e);
+                false
+            }
+        };
+    }
+    match load_graph(args.format, args.vertices, &args.graph_path)
+        .and_then(|graph| exact_permanent(&graph))
+    {
+        Ok(value) => {
+            println!("{}", value);
+            true
+        }
+        Err(e) => {
+            error!("{}: {:#}", args.graph_path.display(), e);
+            false
+        }
+    }
+}
+
+/// Write the random graph described by `args`.
+fn run_generate(args: &GenerateArgs) -> anyhow::Result<()> {
+    let graph = match (args.edge_prob, args.extra_edges) {
+        (Some(p), _) => graph::gen_random_bipartite(args.size, p, args.seed),
+        (None, extra) => {
+            graph::gen_with_perfect_matching(args.size, extra.unwrap_or_default(), args.seed)
+        }
+    };
+    match &args.output {
+        Some(path) => simd_json::to_writer(std::fs::File::create(path)?, &graph)?,
+        None => println!("{}", simd_json::to_string(&graph)?),
+    }
+    Ok(())
+}
+
+/// Exact permanent of `graph`, or an error if it is too large for Ryser's
+/// formula.
+fn exact_permanent(graph: &Graph) -> anyhow::Result<f64> {
+    if graph.size > permanent::exact::MAX_RYSER_DIMENSION {
+        anyhow::bail!(
+            "only n <= {} can be computed exactly, got {}",
+            permanent::exact::MAX_RYSER_DIMENSION,
+            graph.size
+        );
+    }
+    Ok(permanent::exact::graph_permanent(graph))
+}
+
+/// Estimate the permanent of the graph at `graph_path` and print its relative
+/// error against the exact value.
+fn run_validate(cli: &EstimateArgs, graph_path: &Path) -> anyhow::Result<()> {
+    if cli.cost_input || cli.format == InputFormat::ComplexMatrix {
+        anyhow::bail!("validate only supports graph inputs");
+    }
+    let exact = load_graph(cli.format, cli.vertices, graph_path)
+        .and_then(|graph| exact_permanent(&graph))
+        .with_context(|| graph_path.display().to_string())?;
+    if let Some(estimate) = run_graph(cli, graph_path)? {
+        println!(
+            "{}: exact {} estimate {:.5} relative error {:.5}",
+            graph_path.display(),
+            exact,
+            estimate,
+            (estimate - exact).abs() / exact
+        );
+    }
+    Ok(())
+}
+
+/// Exact permanent of the complex matrix at `path`.
+#[cfg(feature = "complex")]
+fn run_complex(path: &Path) -> anyhow::Result<()> {
+    let matrix =
+        permanent::exact::load_complex_matrix(path).context("failed to load complex matrix")?;
+    if matrix.len() > permanent::exact::MAX_RYSER_DIMENSION {
+        anyhow::bail!(
+            "complex matrices are computed exactly, only n <= {} is supported",
+            permanent::exact::MAX_RYSER_DIMENSION
+        );
+    }
+    let value = permanent::exact::ryser_permanent_complex(&matrix);
+    info!("exact permanent: {}", value);
+    Ok(())
+}
+
+#[cfg(not(feature = "complex"))]
+fn run_complex(_path: &Path) -> anyhow::Result<()> {
+    anyhow::bail!("complex matrices need the `complex` feature");
+}
+
+/// Load the graph at `path` in `format`.
+fn load_graph(format: InputFormat, vertices: Option<usize>, path: &Path) -> anyhow::Result<Graph> {
+    let reader = input::open(path)?;
+    match format {
+        InputFormat::EdgeList => Graph::parse_edge_list(
+            &std::io::read_to_string(reader)?,
+            vertices.unwrap_or_default(),
+        ),
+        InputFormat::Dimacs => Graph::parse_dimacs(&std::io::read_to_string(reader)?),
+        InputFormat::MatrixMarket => Graph::parse_matrix_market(&std::io::read_to_string(reader)?),
+        _ => Graph::from_reader(reader),
+    }
+}
+
+/// Checkpoint of replica `replica`, counting from zero: the first keeps
+/// `path`, the others insert `.replica<R>` before its extension.
+fn replica_checkpoint(path: &Path, replica: usize) -> std::path::PathBuf {
+    if replica == 0 {
+        return path.to_path_buf();
+    }
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!(".replica{}", replica + 1));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+/// Run `run` once per replica, given its index, with consecutive seeds and
+/// combine the reports into the last one, whose estimate becomes the geometric
+/// mean of all of them with a jackknife error. A single replica is passed
+/// through untouched.
+fn run_replicas(
+    config: Config,
+    replicas: NonZeroUsize,
+    mut run: impl FnMut(Config, usize) -> anyhow::Result<EstimatorReport>,
+) -> anyhow::Result<EstimatorReport> {
+    if replicas.get() == 1 {
+        return run(config, 0);
+    }
+    let base_seed = config.seed.unwrap_or_else(rand::random);
+    let mut log_values = Vec::with_capacity(replicas.get());
+    let mut combined: Option<EstimatorReport> = None;
+    for r in 0..replicas.get() {
+        let report = run(
+            Config {
+                seed: Some(base_seed.wrapping_add(r as u64)),
+                ..config
+            },
+            r,
+        )?;
+        info!("replica {}: estimate {:.5}", r + 1, report.estimate.value);
+        log_values.push(report.estimate.log_value);
+        combined = Some(match combined {
+            None => report,
+            Some(previous) => EstimatorReport {
+                estimate: Estimate {
+                    effective_sample_size: previous.estimate.effective_sample_size
+                        + report.estimate.effective_sample_size,
+                    nominal_sample_size: previous.estimate.nominal_sample_size
+                        + report.estimate.nominal_sample_size,
+                    // the worst mixed replica speaks for all
+                    r_hat: previous.estimate.r_hat.max(report.estimate.r_hat),
+                    warmup_time: previous.estimate.warmup_time + report.estimate.warmup_time,
+                    cooling_time: previous.estimate.cooling_time + report.estimate.cooling_time,
+                    truncated: previous.estimate.truncated || report.estimate.truncated,
+                    ..report.estimate
+                },
+                ..report
+            },
+        });
+    }
+    let mut report = combined.expect("at least one replica");
+    let aggregate = estimate::jackknife(&log_values);
+    report.estimate.value = aggregate.value;
+    report.estimate.log_value = aggregate.log_value;
+    report.estimate.rel_std_error = aggregate.rel_std_error;
+    Ok(report)
+}
+
+/// Estimate the permanent of the graph at `graph_path` as configured by `cli`,
+/// returning the estimate unless the mode only prints a report.
+fn run_graph(cli: &EstimateArgs, graph_path: &Path) -> anyhow::Result<Option<f64>> {
+    if cli.format == InputFormat::ComplexMatrix {
+        run_complex(graph_path)?;
+        return Ok(None);
+    }
+    let cost = if cli.cost_input {
+        let cost = input::open(graph_path)
+            .and_then(CostMatrix::from_reader)
+            .with_context(|| format!("failed to load {}", graph_path.display()))?;
+        info!("Cost matrix loaded at temperature {}", cli.temperature);
+        Some(cost)
+    } else {
+        None
+    };
+    let (graph, cost) = match cost {
+        Some(cost) if cli.count_matchings => (cost.support(), None),
+        Some(cost) => (Graph::complete(cost.size), Some(cost)),
+        None => {
+            let graph = load_graph(cli.format, cli.vertices, graph_path)
+                .with_context(|| format!("failed to load {}", graph_path.display()))?;
+            (graph, None)
+        }
+    };
     info!("Graph loaded: {:?}", graph);
-    if graph.find_match().size() != graph.size {
-        error!("Graph does not have a perfect matching");
-        return;
+    graph.check_feasible()?;
+    graph.find_perfect_match()?;
+    if let Some(target) = cli.target_rel_error {
+        if target.is_nan() || target <= 0.0 {
+            anyhow::bail!("Target relative error must be positive, got {}", target);
+        }
+    }
+    if let Some(sigmas) = cli.restart_outliers {
+        if sigmas.is_nan() || sigmas <= 0.0 {
+            anyhow::bail!("Outlier threshold must be positive, got {}", sigmas);
+        }
+    }
+    if let Some(r) = cli.cooling_ratio_clamp {
+        if r.is_nan() || r < 1.0 {
+            anyhow::bail!("Cooling ratio clamp must be at least 1, got {}", r);
+        }
+    }
+    let move_size = match cli.move_size {
+        MoveSize::Auto => graph.auto_move_size(),
+        MoveSize::Fixed(k) => k,
+    };
+    info!(
+        "move size {} for degrees in [{}, {}]",
+        move_size,
+        graph.min_degree(),
+        graph.max_degree()
+    );
+    if let Some([low, high]) = cli.adaptive_band.as_deref() {
+        if !(0.0 < *low && low < high && *high <= 1.0) {
+            anyhow::bail!(
+                "Adaptive band needs 0 < LOW < HIGH <= 1, got [{}, {}]",
+                low,
+                high
+            );
+        }
+    }
+    if cli.bias_correction && cli.num_of_chains < 2 {
+        anyhow::bail!("Bias correction needs at least 2 chains");
     }
     let config = Config {
         num_of_chains: cli.num_of_chains,
@@ -126,6 +1043,23 @@ fn main() {
         estimator_sample_intervals: cli.estimator_sample_intervals,
         num_of_weight_estimations: cli.num_of_weight_estimations,
         num_of_estimator_estimations: cli.num_of_estimator_estimations,
+        ratio_clamp: cli.cooling_ratio_clamp,
+        estimator_variance_floor: cli.estimator_variance_floor,
+        allow_ratio_above_one: cli.allow_ratio_above_one,
+        deterministic_proposals: cli.deterministic_proposals,
+        acceptance_threshold: cli.acceptance_threshold,
+        bias_correction: cli.bias_correction,
+        seed: cli.seed,
+        annealing_direction: cli.annealing_direction,
+        adaptive_band: cli.adaptive_band.as_deref().map(|x| (x[0], x[1])),
+        r_hat_threshold: cli.r_hat_threshold,
+        warmup_mode: cli.warmup_mode,
+        deterministic_reduction: cli.deterministic_reduction,
+        sinkhorn_iterations: (cli.init == WeightInit::Sinkhorn).then_some(cli.sinkhorn_iterations),
+        move_size,
+        time_budget: cli.time_budget,
+        restart_outliers: cli.restart_outliers,
+        control_variate: cli.control_variate,
     };
     info!(
         "additive increment is slow down by {}",
@@ -136,24 +1070,178 @@ fn main() {
         cli.multiplicative_slow_down
     );
     info!("{:#?}", config);
-    match cli.filter {
-        Filter::Additive => run_chain::<filter::Additive>(
-            graph,
-            config,
-            cli.additive_slow_down,
-            cli.multiplicative_slow_down,
-        ),
-        Filter::Multiplicative => run_chain::<filter::Multiplicative>(
-            graph,
+    if let Some(runs) = cli.compare_filters_exact {
+        let (exact, comparisons) = estimate::compare_filters_exact(
+            &graph,
             config,
             cli.additive_slow_down,
             cli.multiplicative_slow_down,
-        ),
-        Filter::Constant => run_chain::<filter::Constant>(
-            graph,
-            config,
-            cli.additive_slow_down,
-            cli.multiplicative_slow_down,
-        ),
+            runs,
+        );
+        info!("exact permanent: {}", exact);
+        for x in comparisons {
+            let verdict = if x.is_flagged() { "BIASED" } else { "ok" };
+            println!(
+                "{:<16} mean {:.5} se {:.5} bias {:.5} ({:.2} se) {}",
+                x.filter,
+                x.mean,
+                x.standard_error,
+                x.bias,
+                x.bias_in_standard_errors(),
+                verdict
+            );
+        }
+        return Ok(None);
+    }
+    if let Some(steps) = cli.profile_mixing {
+        let profile = match cli.filter {
+            Filter::Additive => profile_mixing::<filter::Additive>(graph, config, steps, cli),
+            Filter::Multiplicative => {
+                profile_mixing::<filter::Multiplicative>(graph, config, steps, cli)
+            }
+            Filter::Constant => profile_mixing::<filter::Constant>(graph, config, steps, cli),
+        };
+        println!("lag weight active_count");
+        for (lag, (w, a)) in profile
+            .weight_acf
+            .iter()
+            .zip(profile.active_count_acf.iter())
+            .enumerate()
+        {
+            println!("{} {:.5} {:.5}", lag, w, a);
+        }
+        info!(
+            "integrated autocorrelation time: weight {:.3}, active count {:.3}",
+            profile.weight_tau, profile.active_count_tau
+        );
+        return Ok(None);
+    }
+    let criteria = ConvergenceCriteria {
+        min_ess_ratio: cli.min_ess_ratio,
+    };
+    let start = Instant::now();
+    // the budget covers all attempts and replicas, each getting what is left
+    let deadline = cli.time_budget.map(|budget| start + budget);
+    let run_once = |config, replica| {
+        let config = Config {
+            time_budget: deadline.map(|x| x.saturating_duration_since(Instant::now())),
+            ..config
+        };
+        match cli.filter {
+            _ if INTERRUPTED.load(Ordering::Relaxed) => anyhow::bail!("interrupted"),
+            Filter::Additive => {
+                run_chain::<filter::Additive>(graph.clone(), cost.as_ref(), config, replica, cli)
+            }
+            Filter::Multiplicative => run_chain::<filter::Multiplicative>(
+                graph.clone(),
+                cost.as_ref(),
+                config,
+                replica,
+                cli,
+            ),
+            Filter::Constant => {
+                run_chain::<filter::Constant>(graph.clone(), cost.as_ref(), config, replica, cli)
+            }
+        }
+    };
+    let run = |config| run_replicas(config, cli.replicas, &run_once);
+    let result = estimate::run_with_retries(
+        config,
+        cli.auto_retry,
+        cli.retry_grow_chains,
+        |config| match cli.target_rel_error {
+            Some(target) => estimate::run_to_rel_error(
+                config,
+                target,
+                cli.max_chains,
+                run,
+                |report: &EstimatorReport| report.estimate.rel_std_error,
+            )
+            .map(|(report, _)| report),
+            None => run(config),
+        },
+        // a truncated run is final, a retry would start without any budget
+        |report| match report.estimate.truncated {
+            true => Ok(()),
+            false => report.estimate.check(&criteria),
+        },
+    );
+    let (mut report, verdict) = result?;
+    report.elapsed = start.elapsed();
+    report.failure = verdict.as_ref().err().map(|e| format!("{:#}", e));
+    match cli.output_format {
+        OutputFormat::Human => {}
+        OutputFormat::Markdown => print!("{}", report.to_markdown()),
+        OutputFormat::Json => match report.to_json() {
+            Ok(json) => println!("{}", json),
+            Err(e) => error!("failed to write the report: {:#}", e),
+        },
+    }
+    verdict?;
+    if let Some(beta) = report.resumed_at {
+        info!(
+            "partial ratio since beta = {:.5}: {:.5}",
+            beta, report.estimate.value
+        );
+        return Ok(None);
+    }
+    info!("estimate: {:.5}", report.estimate.value);
+    if cli.count_matchings {
+        info!("perfect matchings: {}", report.estimate.value.round());
+    }
+    Ok(Some(report.estimate.value))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{num::NonZeroUsize, path::PathBuf};
+
+    use clap::Parser;
+    use permanent::{filter, graph::Graph, markov_chain::Config};
+
+    #[test]
+    fn replicas_checkpoint_apart() {
+        let dir = std::env::temp_dir().join(format!("replicas-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let checkpoint = dir.join("checkpoint.json");
+        let path: PathBuf = env!("PWD").into();
+        let graph_path = path.join("data").join("box.json");
+        let cli = super::Cli::parse_from([
+            "permanent".as_ref(),
+            "estimate".as_ref(),
+            "--graph-path".as_ref(),
+            graph_path.as_os_str(),
+            "--checkpoint".as_ref(),
+            checkpoint.as_os_str(),
+        ]);
+        let Some(super::Command::Estimate(args)) = cli.command else {
+            panic!("expected the estimate subcommand");
+        };
+        let graph = Graph::load(&graph_path).unwrap();
+        let config = Config {
+            num_of_chains: 4,
+            warmup_times: 64,
+            weight_sample_intervals: 4,
+            num_of_weight_estimations: 16,
+            estimator_sample_intervals: 4,
+            num_of_estimator_estimations: 4,
+            seed: Some(1),
+            ..Config::default()
+        };
+        let report = super::run_replicas(config, NonZeroUsize::new(2).unwrap(), |config, r| {
+            super::run_chain::<filter::Constant>(graph.clone(), None, config, r, &args)
+        })
+        .unwrap();
+        // the second replica warms up and cools on its own instead of
+        // returning the estimate the first one left behind
+        assert!(report.estimate.rel_std_error > 0.0);
+        assert!(report.estimate.rel_std_error.is_finite());
+        assert!(!checkpoint.exists());
+        assert!(!super::replica_checkpoint(&checkpoint, 1).exists());
+        assert_eq!(
+            super::replica_checkpoint(&checkpoint, 1),
+            dir.join("checkpoint.replica2.json")
+        );
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -1,12 +1,26 @@
-use crate::cooling_schedule::CoolingSchedule;
-use crate::cooling_state::{Matrix, State};
-use crate::filter::{AugmentedMatch, MetropolisFilter};
+use crate::cooling_schedule::Schedule;
+use crate::cooling_state::{CompensatedSum, Matrix, SparseWeights, State, Weights};
+use crate::diagnostics::{self, MixingProfile};
+use crate::estimate::{Estimate, EstimatorReport};
+use crate::filter::{
+    chain_rng, AugmentedMatch, ChainRng, MetropolisFilter, SampleRecorder, Trajectory,
+    TrajectoryPoint, MAX_CYCLE_LEN,
+};
 use crate::graph;
 use crate::graph::Match;
-use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+use crate::kernel::ScanKernel;
+use crate::metrics::{MetricsSink, StepMetrics};
+use crate::par::{self, IntoParallelRefIterator, IntoParallelRefMutIterator};
+#[cfg(feature = "parallel")]
+use crate::par::{IndexedParallelIterator, ParallelIterator};
+use crate::reservoir::Reservoir;
+use serde::{Deserialize, Serialize};
 use std::iter::Sum;
-use std::sync::atomic::AtomicUsize;
-use tracing::info;
+use std::num::NonZeroUsize;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Config {
@@ -22,55 +36,205 @@ pub struct Config {
     pub num_of_weight_estimations: usize,
     /// number of samples to from each chain for estimator estimation
     pub num_of_estimator_estimations: usize,
+    /// clamp each cooling ratio into `[1/r, r]` (biases the estimate)
+    pub ratio_clamp: Option<f64>,
+    /// warn when a cooling step accepts fewer estimator samples than this
+    pub estimator_variance_floor: usize,
+    /// return raw cooling ratios above one instead of clamping them
+    pub allow_ratio_above_one: bool,
+    /// cycle through all edge pairs instead of sampling them (debugging only)
+    pub deterministic_proposals: bool,
+    /// accept a move iff its probability exceeds this value (debugging only)
+    pub acceptance_threshold: Option<f64>,
+    /// remove the first-order bias of each cooling ratio (needs two chains)
+    pub bias_correction: bool,
+    /// seed of the chains' generators, drawn from the system if `None`
+    pub seed: Option<u64>,
+    /// which way the cooling ratios are estimated
+    pub annealing_direction: AnnealingDirection,
+    /// adapt the beta increments to keep the cooling ratios in `[low, high]`
+    pub adaptive_band: Option<(f64, f64)>,
+    /// extend the warmup until the R-hat of the chain weights drops below
+    pub r_hat_threshold: Option<f64>,
+    /// how long the warmup runs
+    pub warmup_mode: WarmupMode,
+    /// sum the chains' contributions in chain order, so that a seeded run
+    /// does not depend on the thread count; off by default, as the threads'
+    /// own order is faster
+    pub deterministic_reduction: bool,
+    /// start from the weights of this many rounds of Sinkhorn scaling of the
+    /// graph instead of uniform ones
+    pub sinkhorn_iterations: Option<usize>,
+    /// number of matched edges rotated by one move, 2 for edge pair swaps
+    pub move_size: usize,
+    /// wall-clock time after which no further cooling step is started,
+    /// counted from the construction of the sampler
+    pub time_budget: Option<Duration>,
+    /// after the warmup, restart the chains whose weight lies more than this
+    /// many standard deviations from the median from a random matching
+    pub restart_outliers: Option<f64>,
+    /// correct each cooling ratio with the rejection sampler's acceptance
+    /// coins as control variates; see [`MCState::cooling_evolve`]
+    pub control_variate: bool,
 }
 
-struct AtomicMatrix {
-    size: usize,
-    data: Vec<AtomicUsize>,
+/// Length of the warmup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum WarmupMode {
+    /// `warmup_times` transitions per chain
+    #[default]
+    Fixed,
+    /// until the chains have advanced several integrated autocorrelation
+    /// times of their weight, measured on a few of them
+    Autocorrelation,
 }
 
-impl AtomicMatrix {
-    pub fn new(size: usize) -> Self {
-        AtomicMatrix {
-            size,
-            data: (0..size * size).map(|_| AtomicUsize::new(0)).collect(),
-        }
-    }
-    pub fn inc(&self, u: usize, v: usize) {
-        self.data[u * self.size + v].fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-    }
-    pub fn finish(self, state: &State) -> Matrix {
-        std::sync::atomic::fence(std::sync::atomic::Ordering::SeqCst);
-        let mut matrix = Matrix::new(self.size, 0.0);
-        let sum = matrix
-            .par_mut_rows()
-            .enumerate()
-            .map(|(i, row)| {
-                let mut sum = 0.0;
-                for (j, item) in row.iter_mut().enumerate() {
-                    let value = self.data[i * self.size + j]
-                        .load(std::sync::atomic::Ordering::Relaxed)
-                        .max(1) as f64;
-                    let value = value / state.weight_of_edge(i, j);
-                    *item = value;
-                    sum += value;
-                }
-                sum
-            })
-            .sum::<f64>();
-        let scale = self.size as f64 / sum;
-        matrix.transform(|x| (1.0 / (x * scale)).min(f64::MAX / ((2 * self.size) as f64)));
+/// Direction of the annealed importance sampling estimate.
+///
+/// The forward estimate of each ratio `Z(b') / Z(b)` averages
+/// `exp(-(b' - b) * E)` over samples at `b`, the reverse one inverts the
+/// average of `exp((b' - b) * E)` over samples at `b'`. Their biases have
+/// opposite signs in log space; `Both` combines the two sample sets with
+/// Bennett's acceptance ratio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum AnnealingDirection {
+    #[default]
+    Forward,
+    Reverse,
+    Both,
+}
+
+/// Natural logarithms of the estimates of each annealing direction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DirectionalEstimates {
+    pub forward: f64,
+    pub reverse: f64,
+    pub combined: f64,
+}
+
+/// Edge sample counts of the chains of one worker, kept private to the worker
+/// so that parallel chains never contend on a shared counter, and merged into
+/// a single matrix once the weight samples are taken. Counts are kept per
+/// [`Weights::cell`], so non-edges of sparse weights share one counter.
+struct CountMatrix {
+    data: Vec<usize>,
+}
+
+impl CountMatrix {
+    pub fn new(weights: &Weights) -> Self {
+        CountMatrix {
+            data: vec![0; weights.cells()],
+        }
+    }
+    pub fn inc(&mut self, cell: usize) {
+        self.data[cell] += 1;
+    }
+    /// Add the counts of `other` to `self`.
+    pub fn merge(mut self, other: CountMatrix) -> Self {
+        for (count, other) in self.data.iter_mut().zip(other.data) {
+            *count += other;
+        }
+        self
+    }
+    /// Turn the merged counts of all chains into the next weight matrix.
+    pub fn finish(&self, state: &State, deterministic: bool) -> Weights {
+        match &state.weight {
+            Weights::Dense(weight) => Weights::Dense(self.finish_dense(weight, deterministic)),
+            Weights::Sparse(weight) => Weights::Sparse(self.finish_sparse(weight)),
+        }
+    }
+    fn finish_dense(&self, weight: &Matrix, deterministic: bool) -> Matrix {
+        let size = weight.dimension();
+        let mut matrix = Matrix::new(size, 0.0);
+        let rows = matrix.par_mut_rows().enumerate().map(|(i, row)| {
+            let mut sum = CompensatedSum::default();
+            for (j, item) in row.iter_mut().enumerate() {
+                let value = self.data[i * size + j].max(1) as f64;
+                let value = value / weight.get(i, j);
+                *item = value;
+                sum.add(value);
+            }
+            sum.value()
+        });
+        let sum = if deterministic {
+            // summed in row order so that seeded runs are reproducible
+            rows.collect::<Vec<f64>>()
+                .into_iter()
+                .sum::<CompensatedSum>()
+                .value()
+        } else {
+            rows.sum()
+        };
+        let scale = size as f64 / sum;
+        matrix.transform(|x| (1.0 / (x * scale)).min(f64::MAX / ((2 * size) as f64)));
         matrix
     }
+    /// Like the dense update, with the non-edge counts pooled: each of the `m`
+    /// non-edges is credited `max(count / m, 1)` samples.
+    fn finish_sparse(&self, weight: &SparseWeights) -> SparseWeights {
+        let size = weight.dimension();
+        let mut next = weight.clone();
+        let non_edges = (size * size - weight.edges()) as f64;
+        let mut sum = CompensatedSum::default();
+        for (i, value) in next.values_mut().iter_mut().enumerate() {
+            *value = self.data[i].max(1) as f64 / *value;
+            sum.add(*value);
+        }
+        let pooled = self.data[weight.edges()] as f64;
+        let non_edge = (pooled / non_edges).max(1.0) / weight.non_edge();
+        sum.add(non_edges * non_edge);
+        let scale = size as f64 / sum.value();
+        let cap = f64::MAX / ((2 * size) as f64);
+        for value in next.values_mut().iter_mut() {
+            *value = (1.0 / (*value * scale)).min(cap);
+        }
+        if non_edges > 0.0 {
+            next.set_non_edge((1.0 / (non_edge * scale)).min(cap));
+        }
+        next
+    }
 }
 
 pub struct MCState<T: MetropolisFilter> {
-    #[allow(dead_code)]
     graph: graph::Graph,
     size: usize,
     config: Config,
     pub global_state: State,
     chains: Vec<AugmentedMatch<T>>,
+    /// chain weights observed at each weight estimation of the last step
+    weight_traces: Vec<Vec<f64>>,
+    /// number of cooling ratios that hit the clamp
+    clamped_ratios: usize,
+    /// number of estimator samples accepted in the last cooling step
+    accepted_samples: usize,
+    /// number of cooling steps whose raw ratio exceeded one
+    ratios_above_one: usize,
+    /// number of cooling steps below the estimator variance floor
+    undersampled_steps: usize,
+    /// number of cooling steps re-run with a halved increment
+    adaptive_retries: usize,
+    /// betas reached by the cooling steps so far
+    beta_steps: Vec<f64>,
+    /// live feed of per-step metrics
+    metrics: Option<MetricsSink>,
+    /// energies of the estimator samples of the last step, kept unless
+    /// annealing forward only
+    level_energies: Vec<f64>,
+    /// estimates of both directions of the last non-forward cooling
+    directional: Option<DirectionalEstimates>,
+    /// integrated autocorrelation time measured by the last warmup
+    correlation_time: Option<f64>,
+    /// file the cooling checkpoints to, every so many steps
+    checkpoint: Option<(PathBuf, NonZeroUsize)>,
+    /// log estimate and its variance restored from a checkpoint taken during
+    /// a cooling, picked up by the next one
+    resumed_estimate: Option<(f64, f64)>,
+    /// construction time, the start of `config.time_budget`
+    started: Instant,
+    /// number of chains restarted by the last warmup
+    restarted_chains: usize,
 }
 
 impl Default for Config {
@@ -82,34 +246,791 @@ impl Default for Config {
             estimator_sample_intervals: 128,
             num_of_weight_estimations: 2048,
             num_of_estimator_estimations: 16,
+            ratio_clamp: None,
+            estimator_variance_floor: 0,
+            allow_ratio_above_one: false,
+            deterministic_proposals: false,
+            acceptance_threshold: None,
+            bias_correction: false,
+            seed: None,
+            annealing_direction: AnnealingDirection::Forward,
+            adaptive_band: None,
+            r_hat_threshold: None,
+            warmup_mode: WarmupMode::Fixed,
+            deterministic_reduction: false,
+            sinkhorn_iterations: None,
+            move_size: 2,
+            time_budget: None,
+            restart_outliers: None,
+            control_variate: false,
+        }
+    }
+}
+
+impl Config {
+    /// Start from the default configuration.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+    /// A few short chains with few samples, enough for tests on the small
+    /// fixtures to run in milliseconds.
+    #[cfg(test)]
+    pub(crate) fn quick() -> Self {
+        Config {
+            num_of_chains: 4,
+            warmup_times: 64,
+            weight_sample_intervals: 4,
+            num_of_weight_estimations: 16,
+            estimator_sample_intervals: 4,
+            num_of_estimator_estimations: 4,
+            ..Config::default()
+        }
+    }
+}
+
+/// A `Config` setting that `ConfigBuilder::build` rejects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    ZeroChains,
+    ZeroWarmup,
+    /// a sample interval or sample count, named by its field, is zero
+    ZeroSamples(&'static str),
+    /// the move size is outside `2..=MAX_CYCLE_LEN`
+    MoveSize(usize),
+    /// the outlier threshold of `restart_outliers` is not positive
+    RestartThreshold,
+    /// the bound of `ratio_clamp` is below 1 or NaN
+    RatioClamp,
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::ZeroChains => write!(f, "at least one chain is needed"),
+            ConfigError::ZeroWarmup => write!(f, "warmup needs at least one transition"),
+            ConfigError::ZeroSamples(field) => write!(f, "{} must be positive", field),
+            ConfigError::MoveSize(k) => write!(
+                f,
+                "move size must be between 2 and {}, got {}",
+                MAX_CYCLE_LEN, k
+            ),
+            ConfigError::RestartThreshold => {
+                write!(f, "restart_outliers threshold must be positive")
+            }
+            ConfigError::RatioClamp => write!(f, "ratio_clamp must be at least 1"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Chainable construction of a `Config`, named field by field.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    pub fn num_of_chains(mut self, num_of_chains: usize) -> Self {
+        self.config.num_of_chains = num_of_chains;
+        self
+    }
+    pub fn warmup_times(mut self, warmup_times: usize) -> Self {
+        self.config.warmup_times = warmup_times;
+        self
+    }
+    pub fn weight_sample_intervals(mut self, weight_sample_intervals: usize) -> Self {
+        self.config.weight_sample_intervals = weight_sample_intervals;
+        self
+    }
+    pub fn estimator_sample_intervals(mut self, estimator_sample_intervals: usize) -> Self {
+        self.config.estimator_sample_intervals = estimator_sample_intervals;
+        self
+    }
+    pub fn num_of_weight_estimations(mut self, num_of_weight_estimations: usize) -> Self {
+        self.config.num_of_weight_estimations = num_of_weight_estimations;
+        self
+    }
+    pub fn num_of_estimator_estimations(mut self, num_of_estimator_estimations: usize) -> Self {
+        self.config.num_of_estimator_estimations = num_of_estimator_estimations;
+        self
+    }
+    pub fn ratio_clamp(mut self, ratio_clamp: Option<f64>) -> Self {
+        self.config.ratio_clamp = ratio_clamp;
+        self
+    }
+    pub fn estimator_variance_floor(mut self, estimator_variance_floor: usize) -> Self {
+        self.config.estimator_variance_floor = estimator_variance_floor;
+        self
+    }
+    pub fn allow_ratio_above_one(mut self, allow_ratio_above_one: bool) -> Self {
+        self.config.allow_ratio_above_one = allow_ratio_above_one;
+        self
+    }
+    pub fn deterministic_proposals(mut self, deterministic_proposals: bool) -> Self {
+        self.config.deterministic_proposals = deterministic_proposals;
+        self
+    }
+    pub fn acceptance_threshold(mut self, acceptance_threshold: Option<f64>) -> Self {
+        self.config.acceptance_threshold = acceptance_threshold;
+        self
+    }
+    pub fn bias_correction(mut self, bias_correction: bool) -> Self {
+        self.config.bias_correction = bias_correction;
+        self
+    }
+    pub fn seed(mut self, seed: Option<u64>) -> Self {
+        self.config.seed = seed;
+        self
+    }
+    pub fn annealing_direction(mut self, annealing_direction: AnnealingDirection) -> Self {
+        self.config.annealing_direction = annealing_direction;
+        self
+    }
+    pub fn adaptive_band(mut self, adaptive_band: Option<(f64, f64)>) -> Self {
+        self.config.adaptive_band = adaptive_band;
+        self
+    }
+    pub fn r_hat_threshold(mut self, r_hat_threshold: Option<f64>) -> Self {
+        self.config.r_hat_threshold = r_hat_threshold;
+        self
+    }
+    pub fn warmup_mode(mut self, warmup_mode: WarmupMode) -> Self {
+        self.config.warmup_mode = warmup_mode;
+        self
+    }
+    pub fn deterministic_reduction(mut self, deterministic_reduction: bool) -> Self {
+        self.config.deterministic_reduction = deterministic_reduction;
+        self
+    }
+    pub fn sinkhorn_iterations(mut self, sinkhorn_iterations: Option<usize>) -> Self {
+        self.config.sinkhorn_iterations = sinkhorn_iterations;
+        self
+    }
+    pub fn move_size(mut self, move_size: usize) -> Self {
+        self.config.move_size = move_size;
+        self
+    }
+    pub fn time_budget(mut self, time_budget: Option<Duration>) -> Self {
+        self.config.time_budget = time_budget;
+        self
+    }
+    pub fn restart_outliers(mut self, restart_outliers: Option<f64>) -> Self {
+        self.config.restart_outliers = restart_outliers;
+        self
+    }
+    pub fn control_variate(mut self, control_variate: bool) -> Self {
+        self.config.control_variate = control_variate;
+        self
+    }
+    pub fn build(self) -> Result<Config, ConfigError> {
+        let config = self.config;
+        if config.num_of_chains == 0 {
+            return Err(ConfigError::ZeroChains);
+        }
+        if config.warmup_times == 0 {
+            return Err(ConfigError::ZeroWarmup);
+        }
+        if !(2..=MAX_CYCLE_LEN).contains(&config.move_size) {
+            return Err(ConfigError::MoveSize(config.move_size));
+        }
+        if let Some(sigmas) = config.restart_outliers {
+            if sigmas.is_nan() || sigmas <= 0.0 {
+                return Err(ConfigError::RestartThreshold);
+            }
+        }
+        if let Some(bound) = config.ratio_clamp {
+            if bound.is_nan() || bound < 1.0 {
+                return Err(ConfigError::RatioClamp);
+            }
+        }
+        for (field, value) in [
+            ("weight_sample_intervals", config.weight_sample_intervals),
+            (
+                "estimator_sample_intervals",
+                config.estimator_sample_intervals,
+            ),
+            (
+                "num_of_weight_estimations",
+                config.num_of_weight_estimations,
+            ),
+            (
+                "num_of_estimator_estimations",
+                config.num_of_estimator_estimations,
+            ),
+        ] {
+            if value == 0 {
+                return Err(ConfigError::ZeroSamples(field));
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Summary of the chains after [`MCState::warmup_with_stats`], cheap enough
+/// to decide whether to warm up more before cooling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WarmupStats {
+    /// Metropolis acceptance rate of all chains since the warmup started
+    pub acceptance_rate: f64,
+    /// smallest, average and largest weight of the current matchings
+    pub min_weight: f64,
+    pub mean_weight: f64,
+    pub max_weight: f64,
+    /// accepted moves per chain and matched edge
+    pub turnover: f64,
+    /// whether the turnover reaches `ln(n)`, by which a chain has likely
+    /// touched every edge of its initial matching; a crude indicator only
+    pub mixed: bool,
+    /// chains restarted as outliers at the end of the warmup
+    pub restarted: usize,
+}
+
+/// State of the cooling after a step of [`MCState::cooling_evolve_with`] or
+/// of a [`CoolingRun`].
+#[derive(Debug, Clone, Copy)]
+pub struct CoolingProgress {
+    /// beta the step cooled to
+    pub beta: f64,
+    /// estimate of the permanent including this step
+    pub estimator: f64,
+    /// ratio of the partition functions across the step
+    pub ratio: f64,
+    /// Metropolis acceptance rate of the chains during the step
+    pub acceptance_rate: f64,
+    pub step_index: usize,
+    /// number of betas of the schedule as far as its `size_hint` tells, the
+    /// upper bound if there is one; adaptive cooling may take more steps
+    pub total_steps: usize,
+}
+
+/// Default progress report of [`MCState::cooling_evolve`].
+pub fn log_progress(progress: CoolingProgress) -> ControlFlow<()> {
+    info!(
+        "beta = {:.5}, estimator: {:.5}, ratio: {:.5}, acceptance: {:.3}",
+        progress.beta, progress.estimator, progress.ratio, progress.acceptance_rate
+    );
+    ControlFlow::Continue(())
+}
+
+/// Cooling started by [`MCState::evolve_iter`], yielding the progress after
+/// every step. Stopping early keeps the estimate of the steps taken so far.
+pub struct CoolingRun<'a, T: MetropolisFilter, S: Schedule> {
+    state: &'a mut MCState<T>,
+    recompute: bool,
+    log_estimator: f64,
+    log_variance: f64,
+    /// (delta beta, energies at the lower beta) of every step
+    levels: Vec<(f64, Vec<f64>)>,
+    /// rest of the schedule, pulled one target at a time
+    targets: S,
+    target: Option<f64>,
+    /// adaptive increment, `None` steps straight to the next target
+    increment: Option<f64>,
+    step: usize,
+    total_steps: usize,
+    /// whether the time budget ran out before the last target
+    truncated: bool,
+}
+
+impl<T: MetropolisFilter + 'static + Send + Sync, S: Schedule> Iterator for CoolingRun<'_, T, S> {
+    type Item = CoolingProgress;
+
+    fn next(&mut self) -> Option<CoolingProgress> {
+        let state = &mut *self.state;
+        while let Some(t) = self.target {
+            if let Some(budget) = state.config.time_budget {
+                if state.started.elapsed() >= budget {
+                    warn!(
+                        "time budget of {:?} exhausted at beta = {:.5}",
+                        budget, state.global_state.beta
+                    );
+                    self.truncated = true;
+                    self.target = None;
+                    break;
+                }
+            }
+            let i = self
+                .increment
+                .map_or(t, |x| (state.global_state.beta + x).min(t));
+            let delta = i - state.global_state.beta;
+            let (mut ratio, variance) = state.evolve(i, self.recompute, 0.0);
+            if let Some((low, high)) = state.config.adaptive_band {
+                // without accepted samples or a finite variance the ratio is a
+                // placeholder that says nothing about the step, which can only
+                // be made shorter
+                if state.accepted_samples == 0 && delta > MIN_ADAPTIVE_INCREMENT {
+                    debug!(
+                        "beta = {:.5}, no estimator samples accepted, halving the increment {:.5}",
+                        state.global_state.beta, delta
+                    );
+                    state.adaptive_retries += 1;
+                    self.increment = Some(delta / 2.0);
+                    continue;
+                }
+                if variance.is_finite() {
+                    if ratio < low && delta > MIN_ADAPTIVE_INCREMENT {
+                        debug!(
+                            "beta = {:.5}, ratio {:.5} below {}, halving the increment {:.5}",
+                            state.global_state.beta, ratio, low, delta
+                        );
+                        state.adaptive_retries += 1;
+                        self.increment = Some(delta / 2.0);
+                        continue;
+                    }
+                    if ratio > high {
+                        self.increment = self.increment.map(|x| 2.0 * x);
+                    }
+                }
+            }
+            if i >= t {
+                self.target = self.targets.next();
+            }
+            self.log_variance += variance;
+            if state.config.annealing_direction != AnnealingDirection::Forward {
+                self.levels
+                    .push((delta, std::mem::take(&mut state.level_energies)));
+            }
+            if state.accepted_samples < state.config.estimator_variance_floor {
+                warn!(
+                    "beta = {:.5}, only {} estimator samples accepted, the ratio is unreliable",
+                    state.global_state.beta, state.accepted_samples
+                );
+                state.undersampled_steps += 1;
+            }
+            if let Some(bound) = state.config.ratio_clamp {
+                let (clamped, changed) = clamp_ratio(ratio, bound);
+                if changed {
+                    warn!(
+                        "beta = {:.5}, ratio {:.5} clamped to {:.5}",
+                        state.global_state.beta, ratio, clamped
+                    );
+                    state.clamped_ratios += 1;
+                }
+                ratio = clamped;
+            }
+            let acceptance_rate = state.acceptance_rate();
+            if let Some(metrics) = state.metrics.as_mut() {
+                metrics.send(&StepMetrics {
+                    step: self.step,
+                    beta: state.global_state.beta,
+                    next_beta: i,
+                    ratio,
+                    estimator: (self.log_estimator + ratio.ln()).exp(),
+                    accepted_samples: state.accepted_samples,
+                    acceptance_rate,
+                });
+            }
+            self.log_estimator += ratio.ln();
+            state.global_state.beta = i;
+            state.beta_steps.push(i);
+            let progress = CoolingProgress {
+                beta: i,
+                estimator: self.log_estimator.exp(),
+                ratio,
+                acceptance_rate,
+                step_index: self.step,
+                total_steps: self.total_steps,
+            };
+            self.step += 1;
+            if let Some((path, every)) = state.checkpoint.as_ref() {
+                if self.step.is_multiple_of(every.get()) {
+                    match state
+                        .write_checkpoint(path, Some((self.log_estimator, self.log_variance)))
+                    {
+                        Ok(()) => debug!("beta = {:.5}, checkpoint written", i),
+                        Err(e) => warn!("failed to write checkpoint {}: {}", path.display(), e),
+                    }
+                }
+            }
+            return Some(progress);
+        }
+        None
+    }
+}
+
+impl<T: MetropolisFilter + 'static + Send + Sync, S: Schedule> CoolingRun<'_, T, S> {
+    /// Estimate of the permanent from the steps taken, after the final
+    /// directional pass unless annealing forward only.
+    pub fn finish(self) -> PermanentEstimate {
+        let state = self.state;
+        let direction = state.config.annealing_direction;
+        let (mut log_estimator, mut log_variance) = (self.log_estimator, self.log_variance);
+        if direction != AnnealingDirection::Forward {
+            let (directional, variance) = state.directional_pass(&self.levels, log_estimator);
+            info!(
+                "ln(estimate): forward {:.5}, reverse {:.5}, combined {:.5}",
+                directional.forward, directional.reverse, directional.combined
+            );
+            state.directional = Some(directional);
+            log_estimator = match direction {
+                AnnealingDirection::Reverse => directional.reverse,
+                _ => directional.combined,
+            };
+            log_variance = variance;
+        }
+        PermanentEstimate {
+            value: log_estimator.exp(),
+            log_value: log_estimator,
+            rel_std_error: log_variance.sqrt(),
+            truncated: self.truncated,
+        }
+    }
+}
+
+/// Weight samples per chain behind the R-hat that extends the warmup.
+const R_HAT_SAMPLES: usize = 64;
+
+/// Most times the warmup is extended while waiting for R-hat to drop.
+const MAX_WARMUP_EXTENSIONS: usize = 16;
+
+/// Number of chains whose weight the autocorrelation warmup watches.
+const AUTOTUNE_CHAINS: usize = 4;
+
+/// Transitions between two autocorrelation estimates of the warmup.
+const AUTOTUNE_ROUND: usize = 1024;
+
+/// Integrated autocorrelation times the autocorrelation warmup runs for.
+const WARMUP_CORRELATION_TIMES: f64 = 20.0;
+
+/// Smallest beta increment adaptive cooling halves down to.
+const MIN_ADAPTIVE_INCREMENT: f64 = 1e-6;
+
+/// `ln(n!)`, summed term by term so that it stays finite for large `n`.
+pub fn ln_factorial(n: usize) -> f64 {
+    (2..=n).map(|k| (k as f64).ln()).sum()
+}
+
+/// Clamp `ratio` into `[1/bound, bound]`, reporting whether it was out of range.
+/// A NaN ratio has no place in the range and is passed through unchanged.
+fn clamp_ratio(ratio: f64, bound: f64) -> (f64, bool) {
+    if ratio.is_nan() {
+        return (ratio, false);
+    }
+    let clamped = ratio.clamp(1.0 / bound, bound);
+    (clamped, clamped != ratio)
+}
+
+/// Ratio `sum(y) / sum(x)` of the per-chain estimator sums `y` and importance
+/// totals `x`, with its first-order bias removed.
+///
+/// A ratio of means `R = mean(y) / mean(x)` over `m` chains is biased. Expanding
+/// it around the true means (delta method) gives
+/// `E[R] - r ~ (r * var(x) - cov(x, y)) / (m * mean(x)^2)`, which is estimated
+/// from the sample (co)variance across chains and subtracted. This needs at
+/// least two chains; with fewer the plain ratio is returned.
+fn bias_corrected_ratio(sums: &[StepSum]) -> f64 {
+    let m = sums.len() as f64;
+    let mean_x = sums.iter().map(|x| x.0).sum::<f64>() / m;
+    let mean_y = sums.iter().map(|x| x.1).sum::<f64>() / m;
+    let ratio = mean_y / mean_x;
+    if sums.len() < 2 {
+        return ratio;
+    }
+    let (var_x, cov_xy) = sums.iter().fold((0.0, 0.0), |(var, cov), x| {
+        let dx = x.0 - mean_x;
+        (var + dx * dx, cov + dx * (x.1 - mean_y))
+    });
+    let bias = (ratio * var_x - cov_xy) / (m - 1.0) / (m * mean_x * mean_x);
+    ratio - bias
+}
+
+/// Subtract from the importance total and the estimator sum of every chain
+/// its zero-mean control, `controls[i].0` and `controls[i].1` respectively,
+/// times the coefficient `cov(sum, control) / var(control)` across chains that
+/// minimizes the variance of the corrected sums. The coefficients come from
+/// the same chains, which biases the sums by `O(1 / m)` over `m` chains.
+/// Nothing changes with fewer than two chains, constant controls, or if the
+/// corrected importance total would not be positive.
+fn apply_control_variates(sums: &mut [StepSum], controls: &[(f64, f64)]) {
+    if sums.len() < 2 {
+        return;
+    }
+    let m = sums.len() as f64;
+    let coefficient = |value: &dyn Fn(&StepSum) -> f64, control: &dyn Fn(&(f64, f64)) -> f64| {
+        let mean_value = sums.iter().map(value).sum::<f64>() / m;
+        let mean_control = controls.iter().map(control).sum::<f64>() / m;
+        let (var, cov) = sums
+            .iter()
+            .zip(controls)
+            .fold((0.0, 0.0), |(var, cov), (x, c)| {
+                let dc = control(c) - mean_control;
+                (var + dc * dc, cov + dc * (value(x) - mean_value))
+            });
+        if var > 0.0 {
+            cov / var
+        } else {
+            0.0
+        }
+    };
+    let count = coefficient(&|x| x.0, &|c| c.0);
+    let sum = coefficient(&|x| x.1, &|c| c.1);
+    let total = sums
+        .iter()
+        .zip(controls)
+        .map(|(x, c)| x.0 - count * c.0)
+        .sum::<f64>();
+    if total <= 0.0 {
+        return;
+    }
+    for (x, c) in sums.iter_mut().zip(controls) {
+        x.0 -= count * c.0;
+        x.1 -= sum * c.1;
+    }
+}
+
+/// Variance of `ln(ratio)` for the ratio of the per-chain sums, treating each
+/// chain as an independent sample. By the delta method
+/// `var(R) ~ var(y - R * x) / (m * mean(x)^2)` over `m` chains, and
+/// `var(ln R) ~ var(R) / R^2`. Infinite with fewer than two chains.
+fn log_ratio_variance(sums: &[StepSum], ratio: f64) -> f64 {
+    if sums.len() < 2 {
+        return f64::INFINITY;
+    }
+    let m = sums.len() as f64;
+    let mean_x = sums.iter().map(|x| x.0).sum::<f64>() / m;
+    let residuals = sums.iter().map(|x| x.1 - ratio * x.0);
+    let mean_residual = residuals.clone().sum::<f64>() / m;
+    let var_residual = residuals
+        .map(|r| (r - mean_residual) * (r - mean_residual))
+        .sum::<f64>()
+        / (m - 1.0);
+    var_residual / (m * mean_x * mean_x) / (ratio * ratio)
+}
+
+/// `ln(mean(exp(x)))` without overflowing, `-inf` for no samples.
+fn log_mean_exp(x: impl Iterator<Item = f64> + Clone) -> f64 {
+    let max = x.clone().fold(f64::NEG_INFINITY, f64::max);
+    if max == f64::NEG_INFINITY {
+        return max;
+    }
+    let (sum, count) = x.fold((0.0, 0), |(sum, count), y| {
+        (sum + (y - max).exp(), count + 1)
+    });
+    max + (sum / count as f64).ln()
+}
+
+/// Variance of `ln(mean(exp(x)))` by the delta method, treating the samples
+/// as independent.
+fn log_mean_exp_variance(x: &[f64]) -> f64 {
+    if x.len() < 2 {
+        return f64::INFINITY;
+    }
+    let n = x.len() as f64;
+    let log_mean = log_mean_exp(x.iter().copied());
+    let var = x
+        .iter()
+        .map(|y| (y - log_mean).exp_m1().powi(2))
+        .sum::<f64>()
+        / (n - 1.0);
+    var / n
+}
+
+/// Bennett's acceptance ratio estimate of `ln(Z(b + delta) / Z(b))` from
+/// energies sampled at `b` (`forward`) and at `b + delta` (`reverse`).
+///
+/// With reduced works `W_F = delta * E` and `W_R = -delta * E`, the free
+/// energy difference `F = -ln(Z(b + delta) / Z(b))` solves
+/// `sum_F f(M + W_F - F) = sum_R f(-M + W_R + F)` for the Fermi function
+/// `f(x) = 1 / (1 + exp(x))` and `M = ln(n_F / n_R)`. The left side grows and
+/// the right side shrinks with `F`, so the root is found by bisection.
+fn bar_log_ratio(forward: &[f64], reverse: &[f64], delta: f64) -> f64 {
+    let fermi = |x: f64| 1.0 / (1.0 + x.exp());
+    let m = (forward.len() as f64 / reverse.len() as f64).ln();
+    let imbalance = |f: f64| {
+        forward
+            .iter()
+            .map(|e| fermi(m + delta * e - f))
+            .sum::<f64>()
+            - reverse
+                .iter()
+                .map(|e| fermi(-m - delta * e + f))
+                .sum::<f64>()
+    };
+    let one_sided = [
+        -log_mean_exp(forward.iter().map(|e| -delta * e)),
+        log_mean_exp(reverse.iter().map(|e| delta * e)),
+    ];
+    let mut lo = one_sided[0].min(one_sided[1]) - 1.0;
+    let mut hi = one_sided[0].max(one_sided[1]) + 1.0;
+    while imbalance(lo) > 0.0 {
+        lo -= hi - lo;
+    }
+    while imbalance(hi) < 0.0 {
+        hi += hi - lo;
+    }
+    for _ in 0..128 {
+        let mid = 0.5 * (lo + hi);
+        if imbalance(mid) < 0.0 {
+            lo = mid;
+        } else {
+            hi = mid;
         }
     }
+    -0.5 * (lo + hi)
+}
+
+/// Reverse and combined log ratio of one cooling step, with the variances of
+/// both one-sided estimates. The forward ratio comes from the step itself.
+struct DirectionalStep {
+    reverse: f64,
+    combined: f64,
+    forward_variance: f64,
+    reverse_variance: f64,
+}
+
+/// Log ratios of every step from `levels` of (delta beta, energies sampled at
+/// the lower beta), where `last` holds the energies sampled at the final beta.
+/// Steps lacking samples on either side are skipped (ratio one).
+fn directional_steps(levels: &[(f64, Vec<f64>)], last: &[f64]) -> Vec<DirectionalStep> {
+    let upper = levels
+        .iter()
+        .skip(1)
+        .map(|x| x.1.as_slice())
+        .chain(std::iter::once(last));
+    levels
+        .iter()
+        .zip(upper)
+        .filter(|((_, lower), upper)| !lower.is_empty() && !upper.is_empty())
+        .map(|((delta, lower), upper)| {
+            let forward: Vec<f64> = lower.iter().map(|e| -delta * e).collect();
+            let reverse: Vec<f64> = upper.iter().map(|e| delta * e).collect();
+            DirectionalStep {
+                reverse: -log_mean_exp(reverse.iter().copied()),
+                combined: bar_log_ratio(lower, upper, *delta),
+                forward_variance: log_mean_exp_variance(&forward),
+                reverse_variance: log_mean_exp_variance(&reverse),
+            }
+        })
+        .collect()
+}
+
+/// Point estimate of the permanent with its Monte Carlo error.
+///
+/// The relative standard error is the standard error of `log_value`, summed
+/// in quadrature over the cooling steps; each step treats the chains as
+/// independent samples, so it shrinks like `1 / sqrt(num_of_chains)`. It is
+/// infinite when a run has fewer than two chains or a step accepted nothing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PermanentEstimate {
+    pub value: f64,
+    pub log_value: f64,
+    pub rel_std_error: f64,
+    /// the time budget stopped the cooling early, so the estimate only covers
+    /// the steps taken
+    pub truncated: bool,
+}
+
+/// Serializable view of the sampler state, used to inspect its configuration.
+#[derive(Serialize, Debug)]
+pub struct StateSnapshot {
+    pub beta: f64,
+    pub weight: Vec<Vec<f64>>,
+    pub matchings: Vec<Vec<(usize, usize)>>,
+}
+
+/// Essential sampler state, written by [`MCState::save`] and during cooling.
+#[derive(Serialize, Deserialize)]
+struct Checkpoint {
+    beta: f64,
+    /// log estimate and its variance accumulated up to `beta`, if taken
+    /// during a cooling; an infinite variance, which JSON cannot hold, is
+    /// stored as `None`
+    estimate: Option<(f64, Option<f64>)>,
+    weight: Vec<Vec<f64>>,
+    chains: Vec<ChainCheckpoint>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChainCheckpoint {
+    matching: Vec<(usize, usize)>,
+    weight: f64,
+    active_count: usize,
+    rng: ChainRng,
 }
 
-struct AddPair(f64, f64);
-impl Sum for AddPair {
+/// Importance total, weighted estimator sum and number of accepted samples.
+#[derive(Clone, Copy)]
+struct StepSum(f64, f64, usize);
+impl Sum for StepSum {
     fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
-        iter.reduce(|x, y| AddPair(x.0 + y.0, x.1 + y.1))
-            .unwrap_or(AddPair(0.0, 0.0))
+        let (mut count, mut sum, mut accepted) =
+            (CompensatedSum::default(), CompensatedSum::default(), 0);
+        for x in iter {
+            count.add(x.0);
+            sum.add(x.1);
+            accepted += x.2;
+        }
+        StepSum(count.value(), sum.value(), accepted)
+    }
+}
+
+impl StepSum {
+    /// Sum `sums` in order, or in whatever order the parallel reduction picks
+    /// unless `deterministic`.
+    fn reduce(sums: &[StepSum], deterministic: bool) -> Self {
+        if deterministic {
+            return sums.iter().copied().sum();
+        }
+        par::reduce(sums, StepSum(0.0, 0.0, 0), |x, y| {
+            StepSum(x.0 + y.0, x.1 + y.1, x.2 + y.2)
+        })
     }
 }
 
 impl<T: MetropolisFilter + 'static + Send + Sync> MCState<T> {
     pub fn new(graph: graph::Graph, config: Config) -> Self {
         let global_state = State::from(&graph);
+        Self::with_state(graph, global_state, config)
+    }
+    /// Build the chains on top of a prepared `global_state`, e.g. one with
+    /// custom edge energies.
+    pub fn with_state(graph: graph::Graph, global_state: State, config: Config) -> Self {
+        let size = graph.size;
+        Self::build(graph, global_state, config, |_, rng| {
+            Match::random(size, rng)
+        })
+    }
+    /// Build the chains starting from the given perfect matchings of `graph`
+    /// instead of random permutations, e.g. ones found by
+    /// [`graph::Graph::find_match`]; chain `i` starts from
+    /// `matchings[i % matchings.len()]`.
+    pub fn with_initial_matchings(
+        graph: graph::Graph,
+        config: Config,
+        matchings: Vec<Match>,
+    ) -> anyhow::Result<Self> {
+        if matchings.is_empty() {
+            anyhow::bail!("no initial matchings given");
+        }
+        for (index, matching) in matchings.iter().enumerate() {
+            if let Err(e) = matching.validate(&graph) {
+                anyhow::bail!("initial matching {}: {}", index, e);
+            }
+        }
+        let global_state = State::from(&graph);
+        Ok(Self::build(graph, global_state, config, |index, _| {
+            matchings[index % matchings.len()].clone()
+        }))
+    }
+    fn build(
+        graph: graph::Graph,
+        mut global_state: State,
+        config: Config,
+        initial: impl Fn(usize, &mut ChainRng) -> Match,
+    ) -> Self {
+        global_state.acceptance_threshold = config.acceptance_threshold;
+        if let Some(iterations) = config.sinkhorn_iterations {
+            global_state.sinkhorn_init(&graph, iterations);
+        }
         let size = graph.size;
         let chains = (0..config.num_of_chains)
-            .map(|_| {
-                let matching = Match::random(graph.size);
-                let attr = T::initial_attr(&matching, &global_state);
-                let weight = global_state.weight_of_match(&matching);
-                let active_count = global_state.active_count_of_match(&matching);
-                AugmentedMatch {
-                    matching,
-                    attr,
-                    weight,
-                    active_count,
-                }
+            .map(|index| {
+                let mut rng = chain_rng(config.seed, index as u64);
+                let matching = initial(index, &mut rng);
+                let mut chain = AugmentedMatch::new(matching, &global_state, config.move_size, rng);
+                chain.scan = config.deterministic_proposals.then(ScanKernel::default);
+                chain
             })
             .collect();
         MCState {
@@ -118,98 +1039,2026 @@ impl<T: MetropolisFilter + 'static + Send + Sync> MCState<T> {
             global_state,
             chains,
             size,
+            weight_traces: vec![Vec::new(); config.num_of_chains],
+            clamped_ratios: 0,
+            accepted_samples: 0,
+            ratios_above_one: 0,
+            undersampled_steps: 0,
+            beta_steps: Vec::new(),
+            adaptive_retries: 0,
+            metrics: None,
+            level_energies: Vec::new(),
+            directional: None,
+            correlation_time: None,
+            checkpoint: None,
+            resumed_estimate: None,
+            started: Instant::now(),
+            restarted_chains: 0,
         }
     }
-    pub fn warmup(&mut self) {
-        self.chains.par_iter_mut().for_each(|x| {
-            x.transit_n_times(&self.global_state, self.config.warmup_times);
-        });
+    /// Rebuild a state saved by [`MCState::save`] or a cooling checkpoint.
+    pub fn resume<P: AsRef<Path>>(
+        graph: graph::Graph,
+        path: P,
+        config: Config,
+    ) -> anyhow::Result<Self> {
+        let mut state = Self::new(graph, config);
+        state.restore(path)?;
+        Ok(state)
     }
-    fn evolve(&mut self, next_beta: f64, recompute: bool, penalty: f64) -> f64 {
-        let matrix = AtomicMatrix::new(self.size);
-        let diff = self.global_state.beta - next_beta;
-        let global_sum = self
+    /// Write the weight matrix, beta and chains to `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        self.write_checkpoint(path.as_ref(), None)
+    }
+    /// Checkpoint to `path` after every `every` cooling steps. A state
+    /// restored from it continues the cooling where the checkpoint was taken.
+    pub fn set_checkpoint(&mut self, path: PathBuf, every: NonZeroUsize) {
+        self.checkpoint = Some((path, every));
+    }
+    fn write_checkpoint(&self, path: &Path, estimate: Option<(f64, f64)>) -> anyhow::Result<()> {
+        let checkpoint = Checkpoint {
+            beta: self.global_state.beta,
+            estimate: estimate.map(|(x, variance)| (x, variance.is_finite().then_some(variance))),
+            weight: self.global_state.weight.to_rows(),
+            chains: self
+                .chains
+                .iter()
+                .map(|x| ChainCheckpoint {
+                    matching: x.matching.edges.to_vec(),
+                    weight: x.weight,
+                    active_count: x.active_count,
+                    rng: x.rng.clone(),
+                })
+                .collect(),
+        };
+        // written aside first so that a crash never leaves a torn checkpoint
+        let partial = path.with_extension("partial");
+        let writer = std::io::BufWriter::new(std::fs::File::create(&partial)?);
+        simd_json::to_writer(writer, &checkpoint)?;
+        std::fs::rename(partial, path).map_err(Into::into)
+    }
+    /// Replace the weight matrix, beta and chains with those saved at `path`.
+    pub fn restore<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<()> {
+        let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        let checkpoint: Checkpoint = simd_json::from_reader(reader)?;
+        if checkpoint.chains.len() != self.chains.len() {
+            anyhow::bail!(
+                "checkpoint has {} chains, expected {}",
+                checkpoint.chains.len(),
+                self.chains.len()
+            );
+        }
+        if checkpoint.weight.len() != self.size
+            || checkpoint.weight.iter().any(|x| x.len() != self.size)
+        {
+            anyhow::bail!("checkpoint weights are not {0}x{0}", self.size);
+        }
+        if checkpoint.estimate.is_some()
+            && self.config.annealing_direction != AnnealingDirection::Forward
+        {
+            anyhow::bail!("a cooling can only be resumed when annealing forward");
+        }
+        for chain in checkpoint.chains.iter() {
+            let mut seen = vec![(false, false); self.size];
+            for &(u, v) in chain
+                .matching
+                .iter()
+                .filter(|(u, v)| *u < self.size && *v < self.size)
+            {
+                seen[u].0 = true;
+                seen[v].1 = true;
+            }
+            if chain.matching.len() != self.size || seen.iter().any(|x| *x != (true, true)) {
+                anyhow::bail!(
+                    "checkpoint matching is not perfect on {} vertices",
+                    self.size
+                );
+            }
+        }
+        for (i, row) in checkpoint.weight.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                self.global_state.weight.set(i, j, *value);
+            }
+        }
+        self.global_state.beta = checkpoint.beta;
+        for (chain, saved) in self.chains.iter_mut().zip(checkpoint.chains) {
+            chain.matching = Match {
+                edges: saved.matching.into_boxed_slice(),
+            };
+            chain.attr = T::initial_attr(&chain.matching, &self.global_state);
+            chain.weight = saved.weight;
+            chain.active_count = saved.active_count;
+            chain.energy = self.global_state.energy_of_match(&chain.matching);
+            chain.rng = saved.rng;
+        }
+        self.resumed_estimate = checkpoint
+            .estimate
+            .map(|(x, variance)| (x, variance.unwrap_or(f64::INFINITY)));
+        Ok(())
+    }
+    pub fn snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            beta: self.global_state.beta,
+            weight: self.global_state.weight.to_rows(),
+            matchings: self
+                .chains
+                .iter()
+                .map(|x| x.matching.edges.to_vec())
+                .collect(),
+        }
+    }
+    /// Replace the matchings of the chains, recomputing what the chains cache
+    /// about them.
+    pub fn set_matchings(&mut self, matchings: Vec<Match>) -> anyhow::Result<()> {
+        if matchings.len() != self.chains.len() {
+            anyhow::bail!(
+                "got {} matchings for {} chains",
+                matchings.len(),
+                self.chains.len()
+            );
+        }
+        for (chain, matching) in self.chains.iter_mut().zip(matchings) {
+            chain.attr = T::initial_attr(&matching, &self.global_state);
+            chain.weight = self.global_state.weight_of_match(&matching);
+            chain.active_count = self.global_state.active_count_of_match(&matching);
+            chain.energy = self.global_state.energy_of_match(&matching);
+            chain.matching = matching;
+        }
+        Ok(())
+    }
+    /// Keep a uniform subsample of at most `capacity` matchings out of the
+    /// `num_of_weight_estimations` matchings each chain produces at the current
+    /// beta, spaced `weight_sample_intervals` transitions apart.
+    pub fn sample_reservoir(&mut self, capacity: usize) -> Vec<Vec<(usize, usize)>> {
+        let config = self.config;
+        let global_state = &self.global_state;
+        let pool: Vec<_> = self
             .chains
             .par_iter_mut()
-            .map(|x| {
-                if recompute {
-                    x.weight = self.global_state.weight_of_match(&x.matching);
-                    x.attr = T::initial_attr(&x.matching, &self.global_state);
-                }
-                for _ in 0..self.config.num_of_weight_estimations {
-                    x.transit_n_times(&self.global_state, self.config.weight_sample_intervals);
-                    let sample = x.choose_weighted_edge(&self.global_state);
-                    matrix.inc(sample.0, sample.1);
-                }
-                let mut local_sample_count = 0.0;
-                let mut local_sum = 0.0;
-                for _ in 0..self.config.num_of_estimator_estimations {
-                    if let Some(sample) = x.rejection_sample(
-                        &self.global_state,
-                        self.config.estimator_sample_intervals,
-                    ) {
-                        let importance = (x.active_count as f64 * penalty).exp();
-                        local_sample_count += importance;
-                        local_sum += (diff * sample as f64).exp() * importance as f64;
-                    }
+            .flat_map(|x| {
+                let mut reservoir = Reservoir::new(capacity);
+                for _ in 0..config.num_of_weight_estimations {
+                    x.transit_n_times(global_state, config.weight_sample_intervals);
+                    reservoir.push(x.matching.edges.to_vec(), &mut x.rng);
                 }
-                AddPair(local_sample_count, local_sum)
+                reservoir.into_items()
             })
-            .sum::<AddPair>();
-        self.global_state.weight = matrix.finish(&self.global_state);
-        if global_sum.1 >= global_sum.0 {
-            1.0
-        } else {
-            global_sum.1 / global_sum.0
+            .collect();
+        // every chain saw the same number of matchings, so a uniform pick from
+        // the union of their reservoirs is uniform over all of them
+        let mut reservoir = Reservoir::new(capacity);
+        if let Some(chain) = self.chains.first_mut() {
+            for matching in pool {
+                reservoir.push(matching, &mut chain.rng);
+            }
         }
+        reservoir.into_items()
     }
-    pub fn cooling_evolve(&mut self, mut sequence: CoolingSchedule, recompute: bool) -> f64 {
-        let factorial = (1..=self.size).product::<usize>() as f64;
-        let mut estimator = factorial;
-        sequence.next();
-        for i in sequence {
-            let ratio = self.evolve(i, recompute, 0.0);
-            info!(
-                "beta = {:.5}, estimator: {:.5}, ratio: {:.5}",
-                self.global_state.beta, estimator, ratio
-            );
-            estimator *= ratio;
-            self.global_state.beta = i;
+    /// Record the matching of every accepted estimator sample from now on,
+    /// keeping a uniform subsample of at most `capacity` of them. This only
+    /// observes the samples the estimator draws anyway, with generators of its
+    /// own, so it changes neither the estimate nor a seeded run.
+    pub fn record_samples(&mut self, capacity: usize) {
+        for (index, x) in self.chains.iter_mut().enumerate() {
+            x.samples = Some(SampleRecorder {
+                reservoir: Reservoir::new(capacity),
+                // streams with the top bit set are never used by a chain
+                rng: chain_rng(self.config.seed, (1 << 63) | index as u64),
+            });
         }
-        estimator
     }
-}
-
-#[cfg(test)]
-mod test {
-    use std::{num::NonZeroUsize, path::PathBuf};
-
-    use crate::{cooling_schedule::CoolingConfig, graph::Graph};
-
-    #[test]
-    fn box_example() {
-        let path: PathBuf = env!("PWD").into();
-        let path = path.join("data").join("4-cycles.json");
-        let graph = Graph::load(path).unwrap();
-        println!("{:?}", graph);
-        let config = super::Config::default();
-        let mut state = super::MCState::<crate::filter::Additive>::new(graph, config);
-        state.warmup();
-        println!("warmup done");
-        let size = state.size;
-        let cooling_cfg = CoolingConfig {
-            n: NonZeroUsize::new(size).unwrap(),
-            additive_ratio: NonZeroUsize::new(16).unwrap(),
-            multiplicative_ratio: NonZeroUsize::new(16).unwrap(),
+    /// Stop recording and return the matchings kept since
+    /// [`MCState::record_samples`], a uniform subsample of all recorded ones.
+    pub fn take_samples(&mut self) -> Vec<Vec<(usize, usize)>> {
+        let recorders: Vec<SampleRecorder> = self
+            .chains
+            .iter_mut()
+            .filter_map(|x| x.samples.take())
+            .collect();
+        let Some(first) = recorders.first() else {
+            return Vec::new();
         };
-        let schedule = crate::cooling_schedule::CoolingSchedule::from(cooling_cfg);
-        state.cooling_evolve(schedule, false);
-        for i in 0..size {
-            for j in 0..size {
-                // print state.global_state.weight.get(i, j)
-                print!("{:.2} ", 1.0 / state.global_state.weight.get(i, j));
+        let (capacity, mut rng) = (first.reservoir.capacity(), first.rng.clone());
+        let reservoirs = recorders.into_iter().map(|x| x.reservoir).collect();
+        Reservoir::merge(reservoirs, capacity, &mut rng).into_items()
+    }
+    /// Record the trajectory of `chain` every `thin` transitions from now on.
+    pub fn record_trajectory(&mut self, chain: usize, thin: NonZeroUsize) -> anyhow::Result<()> {
+        match self.chains.get_mut(chain) {
+            Some(x) => {
+                x.trajectory = Some(Trajectory::new(thin));
+                Ok(())
             }
-            println!();
+            None => anyhow::bail!(
+                "chain {} does not exist ({} chains in total)",
+                chain,
+                self.chains.len()
+            ),
+        }
+    }
+    /// Trajectory points recorded so far, if recording is enabled.
+    pub fn trajectory(&self) -> Option<&[TrajectoryPoint]> {
+        self.chains
+            .iter()
+            .find_map(|x| x.trajectory.as_ref())
+            .map(|x| x.points.as_slice())
+    }
+    /// Run `warmup_times` transitions on every chain. With an R-hat
+    /// threshold, keep repeating that until the R-hat of the chain weights
+    /// drops below it.
+    ///
+    /// The autocorrelation warmup mode runs instead until the chains have
+    /// advanced `WARMUP_CORRELATION_TIMES` correlation times, but at most
+    /// `MAX_WARMUP_EXTENSIONS` times `warmup_times` transitions.
+    ///
+    /// With `restart_outliers`, the chains stuck far from the others are then
+    /// restarted, before any R-hat extension.
+    pub fn warmup(&mut self) {
+        match self.config.warmup_mode {
+            WarmupMode::Fixed => self.advance(self.config.warmup_times),
+            WarmupMode::Autocorrelation => self.autocorrelation_warmup(),
+        }
+        self.restarted_chains = 0;
+        if let Some(sigmas) = self.config.restart_outliers {
+            self.restarted_chains = self.restart_outliers(sigmas);
+            info!(
+                "restarted {} of {} chains more than {} standard deviations from the median weight",
+                self.restarted_chains,
+                self.chains.len(),
+                sigmas
+            );
+        }
+        let Some(threshold) = self.config.r_hat_threshold else {
+            return;
+        };
+        for extension in 0..=MAX_WARMUP_EXTENSIONS {
+            self.record_weight_traces(R_HAT_SAMPLES);
+            let r_hat = self.r_hat();
+            if r_hat < threshold {
+                info!("R-hat {:.4} after {} warmup extensions", r_hat, extension);
+                return;
+            }
+            debug!("R-hat {:.4} after {} warmup extensions", r_hat, extension);
+            if extension < MAX_WARMUP_EXTENSIONS {
+                self.advance(self.config.warmup_times);
+            }
+        }
+        warn!(
+            "R-hat {:.4} is still above {} after {} warmup extensions",
+            self.r_hat(),
+            threshold,
+            MAX_WARMUP_EXTENSIONS
+        );
+    }
+    /// Reinitialize every chain whose weight lies more than `sigmas` standard
+    /// deviations from the median chain weight with a random matching, and
+    /// run `warmup_times` transitions on it alone. Returns the number of
+    /// chains restarted.
+    pub fn restart_outliers(&mut self, sigmas: f64) -> usize {
+        let mut weights: Vec<f64> = self.chains.iter().map(|x| x.weight).collect();
+        if weights.is_empty() {
+            return 0;
+        }
+        let count = weights.len() as f64;
+        let mean = weights.iter().sum::<f64>() / count;
+        let deviation = (weights.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / count).sqrt();
+        weights.sort_unstable_by(f64::total_cmp);
+        let median = weights[weights.len() / 2];
+        let (size, warmup_times) = (self.size, self.config.warmup_times);
+        let global_state = &self.global_state;
+        self.chains
+            .par_iter_mut()
+            .filter(|x| (x.weight - median).abs() > sigmas * deviation)
+            .map(|x| {
+                let matching = Match::random(size, &mut x.rng);
+                x.attr = T::initial_attr(&matching, global_state);
+                x.weight = global_state.weight_of_match(&matching);
+                x.active_count = global_state.active_count_of_match(&matching);
+                x.energy = global_state.energy_of_match(&matching);
+                x.matching = matching;
+                x.transit_n_times(global_state, warmup_times);
+            })
+            .count()
+    }
+    /// Like [`MCState::warmup`], but report on the chains afterwards.
+    pub fn warmup_with_stats(&mut self) -> WarmupStats {
+        for x in self.chains.iter_mut() {
+            x.reset_acceptance();
+        }
+        self.warmup();
+        self.warmup_stats()
+    }
+    /// Report on the chains as they are now, counting the moves since the
+    /// last [`MCState::warmup_with_stats`] or cooling step; call it again
+    /// after [`MCState::advance`] to see whether warming up more helped.
+    pub fn warmup_stats(&self) -> WarmupStats {
+        let weights = || self.chains.iter().map(|x| x.weight);
+        let chains = self.chains.len().max(1) as f64;
+        let accepted = self.chains.iter().map(|x| x.accepted).sum::<usize>();
+        let turnover = accepted as f64 / chains / self.size.max(1) as f64;
+        WarmupStats {
+            acceptance_rate: self.acceptance_rate(),
+            min_weight: weights().fold(f64::INFINITY, f64::min),
+            mean_weight: weights().sum::<f64>() / chains,
+            max_weight: weights().fold(f64::NEG_INFINITY, f64::max),
+            turnover,
+            mixed: turnover >= (self.size as f64).ln(),
+            restarted: self.restarted_chains,
+        }
+    }
+    fn autocorrelation_warmup(&mut self) {
+        let watched = self.chains.len().min(AUTOTUNE_CHAINS);
+        let limit = MAX_WARMUP_EXTENSIONS * self.config.warmup_times;
+        let mut traces = vec![Vec::new(); watched];
+        let mut advanced = 0;
+        let tau = loop {
+            let global_state = &self.global_state;
+            let rounds = self
+                .chains
+                .par_iter_mut()
+                .take(watched)
+                .map(|x| {
+                    (0..AUTOTUNE_ROUND)
+                        .map(|_| {
+                            x.transit_n_times(global_state, 1);
+                            x.weight
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            self.chains
+                .par_iter_mut()
+                .skip(watched)
+                .for_each(|x| x.transit_n_times(global_state, AUTOTUNE_ROUND));
+            for (trace, round) in traces.iter_mut().zip(rounds) {
+                trace.extend(round);
+            }
+            advanced += AUTOTUNE_ROUND;
+            // the first half of the traces still carries the initial state
+            let tau = traces
+                .iter()
+                .map(|x| diagnostics::integrated_autocorrelation_time(&x[x.len() / 2..]))
+                .sum::<f64>()
+                / watched as f64;
+            if advanced as f64 >= WARMUP_CORRELATION_TIMES * tau || advanced >= limit {
+                break tau;
+            }
+        };
+        if (advanced as f64) < WARMUP_CORRELATION_TIMES * tau {
+            warn!(
+                "warmup stopped after {} transitions, short of {} correlation times of {:.1}",
+                advanced, WARMUP_CORRELATION_TIMES, tau
+            );
+        } else {
+            info!(
+                "warmup ran {} transitions, correlation time {:.1}",
+                advanced, tau
+            );
+        }
+        self.correlation_time = Some(tau);
+    }
+    /// Integrated autocorrelation time of the chain weight, in transitions,
+    /// measured by the last autocorrelation warmup. A sample interval of a
+    /// few correlation times gives nearly independent samples.
+    pub fn correlation_time(&self) -> Option<f64> {
+        self.correlation_time
+    }
+    /// Replace the weight traces by `samples` weights of every chain, taken
+    /// `weight_sample_intervals` transitions apart.
+    pub fn record_weight_traces(&mut self, samples: usize) {
+        let interval = self.config.weight_sample_intervals;
+        let global_state = &self.global_state;
+        self.chains
+            .par_iter_mut()
+            .zip(self.weight_traces.par_iter_mut())
+            .for_each(|(x, trace)| {
+                trace.clear();
+                for _ in 0..samples {
+                    x.transit_n_times(global_state, interval);
+                    trace.push(x.weight);
+                }
+            });
+    }
+    /// Gelman–Rubin R-hat of the chain weights recorded by the last cooling
+    /// step or [`MCState::record_weight_traces`].
+    pub fn r_hat(&self) -> f64 {
+        diagnostics::potential_scale_reduction(&self.weight_traces)
+    }
+    /// Run `n` transitions on every chain at the current beta.
+    pub fn advance(&mut self, n: usize) {
+        self.chains.par_iter_mut().for_each(|x| {
+            x.transit_n_times(&self.global_state, n);
+        });
+    }
+    /// Warm up, then record `steps` transitions of the first chain and report
+    /// the autocorrelation of its weight and active count up to `max_lag`.
+    pub fn profile_mixing(&mut self, steps: usize, max_lag: usize) -> MixingProfile {
+        self.warmup();
+        for x in self.chains.iter_mut() {
+            x.trajectory = None;
+        }
+        self.chains[0].trajectory = Some(Trajectory::new(NonZeroUsize::MIN));
+        self.chains[0].transit_n_times(&self.global_state, steps);
+        let trajectory = self.chains[0].trajectory.take().unwrap();
+        MixingProfile::from_trajectory(&trajectory.points, max_lag)
+    }
+    /// Energies of estimator samples drawn at the current beta.
+    fn sample_energies(&mut self) -> Vec<f64> {
+        let config = self.config;
+        let global_state = &self.global_state;
+        self.chains
+            .par_iter_mut()
+            .map(|x| {
+                (0..config.num_of_estimator_estimations)
+                    .filter_map(|_| {
+                        x.rejection_sample(global_state, config.estimator_sample_intervals)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+            .concat()
+    }
+    /// One cooling step to `next_beta`, returning the estimated ratio and the
+    /// variance of its logarithm.
+    fn evolve(&mut self, next_beta: f64, recompute: bool, penalty: f64) -> (f64, f64) {
+        let record = self.config.annealing_direction != AnnealingDirection::Forward;
+        let control_variate = self.config.control_variate;
+        let diff = self.global_state.beta - next_beta;
+        let counts = par::fold_reduce(
+            self.chains
+                .par_iter_mut()
+                .zip(self.weight_traces.par_iter_mut()),
+            || CountMatrix::new(&self.global_state.weight),
+            |mut counts, (x, trace)| {
+                trace.clear();
+                x.reset_acceptance();
+                if recompute {
+                    x.weight = self.global_state.weight_of_match(&x.matching);
+                    x.attr = T::initial_attr(&x.matching, &self.global_state);
+                }
+                for _ in 0..self.config.num_of_weight_estimations {
+                    x.transit_n_times(&self.global_state, self.config.weight_sample_intervals);
+                    let sample = x.choose_weighted_edge(&self.global_state);
+                    counts.inc(self.global_state.weight.cell(sample.0, sample.1));
+                    trace.push(x.weight);
+                }
+                counts
+            },
+            CountMatrix::merge,
+        );
+        let sums = self
+            .chains
+            .par_iter_mut()
+            .map(|x| {
+                let mut local_sample_count = CompensatedSum::default();
+                let mut local_sum = CompensatedSum::default();
+                let mut local_accepted = 0;
+                let mut energies = Vec::new();
+                let (mut count_control, mut sum_control) =
+                    (CompensatedSum::default(), CompensatedSum::default());
+                // the coin of every attempt less its probability has mean zero
+                let mut track = |x: &AugmentedMatch<T>, probability: f64, accepted: bool| {
+                    if control_variate {
+                        let importance = (x.active_count as f64 * penalty).exp();
+                        let coin = importance * (f64::from(u8::from(accepted)) - probability);
+                        count_control.add(coin);
+                        sum_control.add((diff * x.energy).exp() * coin);
+                    }
+                };
+                for _ in 0..self.config.num_of_estimator_estimations {
+                    if let Some(sample) = x.rejection_sample_with(
+                        &self.global_state,
+                        self.config.estimator_sample_intervals,
+                        &mut track,
+                    ) {
+                        let importance = (x.active_count as f64 * penalty).exp();
+                        local_sample_count.add(importance);
+                        local_sum.add((diff * sample).exp() * importance);
+                        local_accepted += 1;
+                        if record {
+                            energies.push(sample);
+                        }
+                    }
+                }
+                (
+                    StepSum(
+                        local_sample_count.value(),
+                        local_sum.value(),
+                        local_accepted,
+                    ),
+                    (count_control.value(), sum_control.value()),
+                    energies,
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut controls = Vec::with_capacity(sums.len());
+        let mut energies = Vec::with_capacity(sums.len());
+        let mut sums = sums
+            .into_iter()
+            .map(|(sum, control, chain_energies)| {
+                controls.push(control);
+                energies.push(chain_energies);
+                sum
+            })
+            .collect::<Vec<_>>();
+        if control_variate {
+            apply_control_variates(&mut sums, &controls);
+        }
+        self.level_energies = energies.concat();
+        let deterministic = self.config.deterministic_reduction;
+        let global_sum = StepSum::reduce(&sums, deterministic);
+        self.global_state.weight = counts.finish(&self.global_state, deterministic);
+        self.accepted_samples = global_sum.2;
+        if global_sum.2 == 0 {
+            return (1.0, f64::INFINITY);
+        }
+        // each sample contributes exp(-(next_beta - beta) * energy) <= 1 when
+        // cooling, so a ratio above one can only come from sampling noise
+        if global_sum.1 > global_sum.0 {
+            self.ratios_above_one += 1;
+            debug!(
+                "beta = {:.5}, ratio above one for {} times",
+                self.global_state.beta, self.ratios_above_one
+            );
+            if !self.config.allow_ratio_above_one {
+                return (1.0, log_ratio_variance(&sums, 1.0));
+            }
+        }
+        let ratio = if self.config.bias_correction {
+            bias_corrected_ratio(&sums)
+        } else {
+            global_sum.1 / global_sum.0
+        };
+        (ratio, log_ratio_variance(&sums, ratio))
+    }
+    /// Cool the chains along `sequence`, estimating the permanent as `n!`
+    /// times the product of the ratios of consecutive partition functions.
+    /// The estimator is accumulated in log space starting at `ln(n!)`, so
+    /// `log_value` stays finite where `value` overflows.
+    ///
+    /// With an adaptive band `[low, high]`, a step whose ratio falls below
+    /// `low` is re-run with half the beta increment, and the increment doubles
+    /// again after a ratio above `high`; the betas of `sequence` still all get
+    /// visited.
+    ///
+    /// Unless annealing forward only, the energies sampled at every beta are
+    /// kept and, after sampling once more at the final beta, turned into the
+    /// estimate of the configured direction; see
+    /// [`MCState::directional_estimates`].
+    ///
+    /// With `control_variate`, every rejection sampling attempt contributes
+    /// its acceptance coin less the known acceptance probability `1/weight`,
+    /// a statistic of mean zero, weighted like the sample it would have
+    /// become. Subtracting the best multiple of these controls cuts the
+    /// variance of each chain's sums by a factor `1 - rho^2`, `rho` being
+    /// their correlation with the controls. That removes most of the coin
+    /// noise, so the gain is largest when the acceptance probabilities are
+    /// small and close to nothing when nearly every attempt is accepted.
+    pub fn cooling_evolve(
+        &mut self,
+        sequence: impl Schedule,
+        recompute: bool,
+    ) -> PermanentEstimate {
+        self.cooling_evolve_with(sequence, recompute, &mut log_progress)
+    }
+    /// Like [`MCState::cooling_evolve`], but reports every step to `progress`
+    /// instead of logging it. Cooling stops early, with the estimate so far,
+    /// once `progress` returns `ControlFlow::Break`.
+    pub fn cooling_evolve_with(
+        &mut self,
+        sequence: impl Schedule,
+        recompute: bool,
+        progress: &mut dyn FnMut(CoolingProgress) -> ControlFlow<()>,
+    ) -> PermanentEstimate {
+        let mut run = self.evolve_iter(sequence, recompute);
+        for step in run.by_ref() {
+            if progress(step).is_break() {
+                info!("cooling stopped at beta = {:.5}", step.beta);
+                break;
+            }
+        }
+        run.finish()
+    }
+    /// Cool the chains along `sequence` one step at a time, as in
+    /// [`MCState::cooling_evolve`]: the returned iterator runs a cooling step
+    /// per item, and [`CoolingRun::finish`] turns the steps taken into the
+    /// estimate.
+    pub fn evolve_iter<S: Schedule>(
+        &mut self,
+        mut sequence: S,
+        recompute: bool,
+    ) -> CoolingRun<'_, T, S> {
+        let (log_estimator, log_variance) = self
+            .resumed_estimate
+            .take()
+            .unwrap_or((ln_factorial(self.size), 0.0));
+        let start = self.global_state.beta;
+        // the schedule is only pulled as far as the cooling goes, so it may be
+        // lazy or unbounded
+        let target = sequence.find(|x| *x > start);
+        let (low, high) = sequence.size_hint();
+        let total_steps = high
+            .unwrap_or(low)
+            .saturating_add(usize::from(target.is_some()));
+        CoolingRun {
+            state: self,
+            recompute,
+            log_estimator,
+            log_variance,
+            levels: Vec::new(),
+            targets: sequence,
+            target,
+            increment: None,
+            step: 0,
+            total_steps,
+            truncated: false,
+        }
+    }
+    /// Reverse and combined estimates from the energies of every level, plus a
+    /// final sample at the current (last) beta, with the log variance of the
+    /// configured direction.
+    fn directional_pass(
+        &mut self,
+        levels: &[(f64, Vec<f64>)],
+        forward: f64,
+    ) -> (DirectionalEstimates, f64) {
+        let last = self.sample_energies();
+        let steps = directional_steps(levels, &last);
+        self.level_energies = last;
+        let start = ln_factorial(self.size);
+        let estimates = DirectionalEstimates {
+            forward,
+            reverse: start + steps.iter().map(|x| x.reverse).sum::<f64>(),
+            combined: start + steps.iter().map(|x| x.combined).sum::<f64>(),
+        };
+        let variance = match self.config.annealing_direction {
+            AnnealingDirection::Reverse => steps.iter().map(|x| x.reverse_variance).sum(),
+            // Bennett's estimator asymptotically does not exceed the smaller
+            // one-sided variance
+            _ => steps
+                .iter()
+                .map(|x| x.forward_variance.min(x.reverse_variance))
+                .sum(),
+        };
+        (estimates, variance)
+    }
+    /// Estimates of each annealing direction of the last cooling, if it ran in
+    /// the reverse or both directions.
+    pub fn directional_estimates(&self) -> Option<DirectionalEstimates> {
+        self.directional
+    }
+    /// Like [`MCState::cooling_evolve`], but only returns the natural logarithm
+    /// of the estimator.
+    pub fn log_cooling_evolve(&mut self, sequence: impl Schedule, recompute: bool) -> f64 {
+        self.cooling_evolve(sequence, recompute).log_value
+    }
+    /// Diagnostics of the chains after cooling them to `permanent`, which
+    /// took `warmup_time` and `cooling_time`, with the a priori `bounds` of
+    /// the permanent.
+    pub fn estimate(
+        &self,
+        permanent: PermanentEstimate,
+        bounds: (f64, f64),
+        warmup_time: Duration,
+        cooling_time: Duration,
+    ) -> Estimate {
+        Estimate {
+            value: permanent.value,
+            log_value: permanent.log_value,
+            rel_std_error: permanent.rel_std_error,
+            bounds,
+            effective_sample_size: self.effective_sample_size(),
+            nominal_sample_size: self.config.num_of_chains * self.config.num_of_weight_estimations,
+            acceptance_rate: self.acceptance_rate(),
+            r_hat: self.r_hat(),
+            warmup_time,
+            cooling_time,
+            truncated: permanent.truncated,
+        }
+    }
+    /// Consume the state into a report of `estimate`, moving the final weight
+    /// matrix instead of copying it.
+    pub fn into_report(self, estimate: Estimate) -> EstimatorReport {
+        EstimatorReport {
+            estimate,
+            beta: self.global_state.beta,
+            weight: self.global_state.weight,
+            beta_steps: self.beta_steps,
+            elapsed: estimate.warmup_time + estimate.cooling_time,
+            failure: None,
+            resumed_at: None,
+        }
+    }
+    /// Graph the chains run on.
+    pub fn graph(&self) -> &graph::Graph {
+        &self.graph
+    }
+    /// Betas reached by the cooling steps so far, in order.
+    pub fn beta_steps(&self) -> &[f64] {
+        &self.beta_steps
+    }
+    /// Number of cooling steps whose ratio was clamped.
+    pub fn clamped_ratios(&self) -> usize {
+        self.clamped_ratios
+    }
+    /// Stream the metrics of every following cooling step to `sink`.
+    pub fn set_metrics_sink(&mut self, sink: MetricsSink) {
+        self.metrics = Some(sink);
+    }
+    /// Detach the metrics sink, flushing the messages queued so far.
+    pub fn take_metrics_sink(&mut self) -> Option<MetricsSink> {
+        self.metrics.take()
+    }
+    /// Number of cooling steps whose raw ratio exceeded one.
+    pub fn ratios_above_one(&self) -> usize {
+        self.ratios_above_one
+    }
+    /// Fraction of the Metropolis moves accepted across all chains, during the
+    /// last cooling step or, before cooling, since the chains were built. A
+    /// rate near 0 or 1 means the chains barely move through the matchings.
+    pub fn acceptance_rate(&self) -> f64 {
+        let (accepted, proposed) = self
+            .chains
+            .iter()
+            .fold((0, 0), |(a, p), x| (a + x.accepted, p + x.proposed));
+        accepted as f64 / proposed as f64
+    }
+    /// Number of estimator samples accepted in the last cooling step.
+    pub fn accepted_samples(&self) -> usize {
+        self.accepted_samples
+    }
+    /// Number of cooling steps re-run with a halved beta increment.
+    pub fn adaptive_retries(&self) -> usize {
+        self.adaptive_retries
+    }
+    /// Number of cooling steps that fell below the estimator variance floor.
+    pub fn undersampled_steps(&self) -> usize {
+        self.undersampled_steps
+    }
+    /// Effective sample size of each chain, estimated from the autocorrelation
+    /// of its weight trace during the last cooling step.
+    pub fn chain_effective_sample_sizes(&self) -> Vec<f64> {
+        self.weight_traces
+            .par_iter()
+            .map(|trace| diagnostics::effective_sample_size(trace))
+            .collect()
+    }
+    /// Aggregate effective sample size over all chains.
+    pub fn effective_sample_size(&self) -> f64 {
+        self.chain_effective_sample_sizes().into_iter().sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{num::NonZeroUsize, path::PathBuf};
+
+    use crate::{cooling_schedule::CoolingConfig, graph::Graph};
+
+    /// `chains` chains warmed up for `warmup` steps that take `weights` weight
+    /// and `estimators` estimator samples per cooling step, one step apart.
+    fn small_config(
+        chains: usize,
+        warmup: usize,
+        weights: usize,
+        estimators: usize,
+    ) -> super::Config {
+        super::Config {
+            num_of_chains: chains,
+            warmup_times: warmup,
+            weight_sample_intervals: 1,
+            num_of_weight_estimations: weights,
+            estimator_sample_intervals: 1,
+            num_of_estimator_estimations: estimators,
+            ..super::Config::default()
+        }
+    }
+
+    #[test]
+    fn bias_correction_reduces_ratio_bias() {
+        use rand::SeedableRng;
+        // every "chain" weighs a uniform permutation, an exact sample at beta = 0,
+        // by its Gibbs factors at beta = 1/4 and 1/2: the ratio of their means over
+        // few chains underestimates Z(1/2) / Z(1/4) through the convexity of 1 / x
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("4-cycles.json")).unwrap();
+        let exact = partition_function(&graph, 0.5) / partition_function(&graph, 0.25);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let replicates = 20000;
+        let (mut plain, mut corrected) = (0.0, 0.0);
+        for _ in 0..replicates {
+            let sums: Vec<_> = gibbs_energies(&graph, 0.0, 4, &mut rng)
+                .into_iter()
+                .map(|energy| super::StepSum((-0.25 * energy).exp(), (-0.5 * energy).exp(), 1))
+                .collect();
+            let total = sums.iter().copied().sum::<super::StepSum>();
+            plain += total.1 / total.0;
+            corrected += super::bias_corrected_ratio(&sums);
+        }
+        let plain_bias = (plain / replicates as f64 / exact - 1.0).abs();
+        let corrected_bias = (corrected / replicates as f64 / exact - 1.0).abs();
+        assert!(
+            corrected_bias < 0.5 * plain_bias,
+            "plain {}, corrected {}",
+            plain_bias,
+            corrected_bias
+        );
+        assert_eq!(super::bias_corrected_ratio(&sums_of(&[(2.0, 1.0)])), 0.5);
+    }
+
+    #[test]
+    fn config_builder() {
+        let config = super::Config::builder()
+            .num_of_chains(4)
+            .weight_sample_intervals(3)
+            .estimator_sample_intervals(5)
+            .seed(Some(1))
+            .build()
+            .unwrap();
+        assert_eq!(config.num_of_chains, 4);
+        assert_eq!(config.weight_sample_intervals, 3);
+        assert_eq!(config.estimator_sample_intervals, 5);
+        assert_eq!(config.seed, Some(1));
+        assert_eq!(config.warmup_times, super::Config::default().warmup_times);
+        use super::ConfigError;
+        let builder = super::Config::builder();
+        assert_eq!(
+            builder.num_of_chains(0).build().unwrap_err(),
+            ConfigError::ZeroChains
+        );
+        assert_eq!(
+            builder.warmup_times(0).build().unwrap_err(),
+            ConfigError::ZeroWarmup
+        );
+        let error = builder.num_of_estimator_estimations(0).build().unwrap_err();
+        assert_eq!(
+            error,
+            ConfigError::ZeroSamples("num_of_estimator_estimations")
+        );
+        assert_eq!(
+            error.to_string(),
+            "num_of_estimator_estimations must be positive"
+        );
+        assert_eq!(
+            builder.move_size(1).build().unwrap_err(),
+            ConfigError::MoveSize(1)
+        );
+        assert!(builder.move_size(3).build().is_ok());
+        assert_eq!(
+            builder.restart_outliers(Some(0.0)).build().unwrap_err(),
+            ConfigError::RestartThreshold
+        );
+        assert_eq!(
+            builder.ratio_clamp(Some(0.5)).build().unwrap_err(),
+            ConfigError::RatioClamp
+        );
+        assert_eq!(
+            builder.ratio_clamp(Some(f64::NAN)).build().unwrap_err(),
+            ConfigError::RatioClamp
+        );
+        assert!(builder.ratio_clamp(Some(1.0)).build().is_ok());
+        assert!(
+            builder
+                .control_variate(true)
+                .build()
+                .unwrap()
+                .control_variate
+        );
+    }
+
+    #[test]
+    fn sparse_counts_match_dense() {
+        let n = 256;
+        let graph = Graph {
+            size: n,
+            edges: (0..n).map(|u| vec![u, (u + 1) % n].into()).collect(),
+            weights: None,
+        };
+        let mut sparse = crate::cooling_state::State::from(&graph);
+        let mut dense = crate::cooling_state::State::from(&graph);
+        dense.weight = super::Weights::Dense(dense.weight.to_dense());
+        assert!(sparse.weight.as_dense().is_none());
+        let mut rng = crate::filter::chain_rng(Some(3), 0);
+        for (u, v) in (0..n).flat_map(|u| [(u, u), (u, (u + 1) % n)]) {
+            let value = rand::Rng::gen_range(&mut rng, 0.5..2.0);
+            sparse.weight.set(u, v, value);
+            dense.weight.set(u, v, value);
+        }
+        // no samples on non-edges, so each one is credited a single sample
+        let mut sparse_counts = super::CountMatrix::new(&sparse.weight);
+        let mut dense_counts = super::CountMatrix::new(&dense.weight);
+        for _ in 0..4 * n {
+            let u = rand::Rng::gen_range(&mut rng, 0..n);
+            let v = (u + rand::Rng::gen_range(&mut rng, 0..2)) % n;
+            sparse_counts.inc(sparse.weight.cell(u, v));
+            dense_counts.inc(dense.weight.cell(u, v));
+        }
+        sparse.weight = sparse_counts.finish(&sparse, true);
+        dense.weight = dense_counts.finish(&dense, true);
+        for u in 0..n {
+            for v in 0..n {
+                let (x, y) = (sparse.weight_of_edge(u, v), dense.weight_of_edge(u, v));
+                assert!((x - y).abs() < 1e-9 * y, "({}, {}): {} != {}", u, v, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn chain_counts_are_summed() {
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("complete.json")).unwrap();
+        let state = crate::cooling_state::State::from(&graph);
+        let size = graph.size;
+        let weight = &state.weight;
+        let mut split = [
+            super::CountMatrix::new(weight),
+            super::CountMatrix::new(weight),
+        ];
+        let mut single = super::CountMatrix::new(weight);
+        for (index, (u, v)) in [(0, 1), (1, 0), (0, 1), (2, 2)].into_iter().enumerate() {
+            split[index % 2].inc(weight.cell(u, v));
+            single.inc(weight.cell(u, v));
+        }
+        let [left, right] = split;
+        let split = left.merge(right).finish(&state, true);
+        let single = single.finish(&state, true);
+        for u in 0..size {
+            for v in 0..size {
+                assert_eq!(split.get(u, v), single.get(u, v));
+            }
+        }
+        assert!(split.get(0, 1) < split.get(1, 0));
+    }
+
+    fn sums_of(values: &[(f64, f64)]) -> Vec<super::StepSum> {
+        values.iter().map(|x| super::StepSum(x.0, x.1, 1)).collect()
+    }
+
+    /// Exact `Z(beta)` of an unweighted graph: the permanent with one on the
+    /// edges and `exp(-beta)`, one non-edge worth of energy, elsewhere.
+    fn partition_function(graph: &Graph, beta: f64) -> f64 {
+        let n = graph.size;
+        let mut matrix = vec![(-beta).exp(); n * n];
+        for (u, row) in graph.edges.iter().enumerate() {
+            row.iter().for_each(|&v| matrix[u * n + v] = 1.0);
+        }
+        crate::exact::permanent_ryser(&matrix, n)
+    }
+
+    /// Energies of `count` exact samples at `beta`: uniform permutations
+    /// accepted with probability `exp(-beta * energy)`.
+    fn gibbs_energies(
+        graph: &Graph,
+        beta: f64,
+        count: usize,
+        rng: &mut impl rand::Rng,
+    ) -> Vec<f64> {
+        use rand::seq::SliceRandom;
+        let mut permutation = (0..graph.size).collect::<Vec<_>>();
+        (0..count)
+            .map(|_| loop {
+                permutation.shuffle(rng);
+                let energy = (0..graph.size)
+                    .filter(|&u| !graph.edges[u].contains(&permutation[u]))
+                    .count() as f64;
+                if rng.gen::<f64>() < (-beta * energy).exp() {
+                    break energy;
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn box_example() {
+        let path: PathBuf = env!("PWD").into();
+        let path = path.join("data").join("4-cycles.json");
+        let graph = Graph::load(path).unwrap();
+        println!("{:?}", graph);
+        let config = super::Config::default();
+        let mut state = super::MCState::<crate::filter::Additive>::new(graph, config);
+        state.warmup();
+        println!("warmup done");
+        let size = state.size;
+        let cooling_cfg = CoolingConfig {
+            n: NonZeroUsize::new(size).unwrap(),
+            additive_ratio: NonZeroUsize::new(16).unwrap(),
+            multiplicative_ratio: NonZeroUsize::new(16).unwrap(),
+        };
+        let schedule = crate::cooling_schedule::CoolingSchedule::from(cooling_cfg);
+        state.cooling_evolve(schedule, false);
+        let mut frequencies = state.global_state.weight.to_dense();
+        assert_eq!(frequencies.dimension(), size);
+        frequencies.transform(|x| 1.0 / x);
+        println!("{}", frequencies);
+    }
+
+    #[test]
+    fn effective_sample_size_fast_mixing() {
+        let path: PathBuf = env!("PWD").into();
+        let path = path.join("data").join("complete.json");
+        let graph = Graph::load(path).unwrap();
+        let config = super::Config {
+            num_of_chains: 8,
+            warmup_times: 256,
+            weight_sample_intervals: 64,
+            estimator_sample_intervals: 1,
+            num_of_weight_estimations: 512,
+            num_of_estimator_estimations: 1,
+            ..super::Config::default()
+        };
+        let mut state = super::MCState::<crate::filter::Constant>::new(graph, config);
+        for i in 0..state.size {
+            for j in 0..state.size {
+                state
+                    .global_state
+                    .weight
+                    .set(i, j, 1.0 + ((i + 2 * j) % 5) as f64);
+            }
+        }
+        state.warmup();
+        state.evolve(0.0, true, 0.0);
+        let nominal = (config.num_of_chains * config.num_of_weight_estimations) as f64;
+        let ess = state.effective_sample_size();
+        assert_eq!(
+            state.chain_effective_sample_sizes().len(),
+            config.num_of_chains
+        );
+        assert!(ess > 0.7 * nominal, "ess = {}, nominal = {}", ess, nominal);
+    }
+
+    fn cost_estimate(cost: &crate::graph::CostMatrix, temperature: f64) -> (f64, f64) {
+        let mut gibbs = crate::cooling_state::Matrix::new(cost.size, 0.0);
+        for (i, row) in cost.cost.iter().enumerate() {
+            for (j, c) in row.iter().enumerate() {
+                gibbs.set(i, j, (-c / temperature).exp());
+            }
+        }
+        let config = super::Config {
+            num_of_chains: 64,
+            warmup_times: 256,
+            num_of_weight_estimations: 64,
+            num_of_estimator_estimations: 16,
+            estimator_sample_intervals: 8,
+            seed: Some(17),
+            ..super::Config::default()
+        };
+        let state = crate::cooling_state::State::from_cost(cost, temperature);
+        let mut state = super::MCState::<crate::filter::Constant>::with_state(
+            Graph::complete(cost.size),
+            state,
+            config,
+        );
+        state.warmup();
+        let cooling_cfg = CoolingConfig {
+            n: NonZeroUsize::new(cost.size).unwrap(),
+            additive_ratio: NonZeroUsize::new(4).unwrap(),
+            multiplicative_ratio: NonZeroUsize::new(1).unwrap(),
+        };
+        let scale = crate::estimate::WeightScale::of_cost(cost, temperature);
+        let schedule = scale.schedule(crate::cooling_schedule::CoolingSchedule::from(cooling_cfg));
+        (
+            scale.apply(state.cooling_evolve(schedule, false)).value,
+            crate::exact::ryser_permanent_matrix(&gibbs),
+        )
+    }
+
+    fn cost_fixture() -> crate::graph::CostMatrix {
+        let path: PathBuf = env!("PWD").into();
+        crate::graph::CostMatrix::load(path.join("data").join("cost.json")).unwrap()
+    }
+
+    #[test]
+    fn weighted_graph() {
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("weighted.json")).unwrap();
+        let n = graph.size;
+        let dense: Vec<f64> = (0..n * n)
+            .map(|x| graph.edge_weight(x / n, x % n))
+            .collect();
+        let exact = crate::exact::permanent_ryser(&dense, n);
+        // at beta = 1 the chains target the weights scaled down by max(w), and
+        // exp(-non_edge_energy) on the non-edges, whose terms vanish in an f64
+        let scale = graph.max_weight();
+        let log_scale = n as f64 * scale.ln();
+        let max_energy = dense
+            .iter()
+            .filter(|w| **w > 0.0)
+            .map(|w| -(w / scale).ln())
+            .fold(0.0, f64::max);
+        let non_edge = (-crate::cooling_state::non_edge_energy(n, max_energy)).exp();
+        let target: Vec<f64> = dense
+            .iter()
+            .map(|w| if *w == 0.0 { non_edge } else { w / scale })
+            .collect();
+        let target = crate::exact::permanent_ryser(&target, n) * log_scale.exp();
+        assert!(
+            (target / exact - 1.0).abs() < 1e-15,
+            "{} vs {}",
+            target,
+            exact
+        );
+        let config = super::Config {
+            num_of_chains: 256,
+            warmup_times: 256,
+            num_of_weight_estimations: 64,
+            num_of_estimator_estimations: 16,
+            estimator_sample_intervals: 8,
+            seed: Some(2),
+            ..super::Config::default()
+        };
+        let mut state = super::MCState::<crate::filter::Constant>::new(graph, config);
+        state.warmup();
+        let cooling_cfg = CoolingConfig {
+            n: NonZeroUsize::new(n).unwrap(),
+            additive_ratio: NonZeroUsize::new(4).unwrap(),
+            multiplicative_ratio: NonZeroUsize::new(1).unwrap(),
+        };
+        let schedule = crate::cooling_schedule::CoolingSchedule::from(cooling_cfg)
+            .take_while(|beta| *beta < 1.0)
+            .chain(std::iter::once(1.0));
+        let estimate = state.cooling_evolve(schedule, false);
+        // within three of its own standard errors, about 8% at this budget
+        let error = estimate.log_value + log_scale - exact.ln();
+        assert!(estimate.rel_std_error < 0.03, "{:?}", estimate);
+        assert!(
+            error.abs() < 3.0 * estimate.rel_std_error,
+            "{:?} vs {}",
+            estimate,
+            exact
+        );
+    }
+
+    #[test]
+    fn cost_matrix_high_temperature() {
+        let (estimate, exact) = cost_estimate(&cost_fixture(), 1e6);
+        assert!((exact - 24.0).abs() < 1e-3);
+        assert!(
+            (estimate - 24.0).abs() < 0.01 * 24.0,
+            "estimate = {}",
+            estimate
+        );
+    }
+
+    #[test]
+    fn cost_matrix_unit_temperature() {
+        let (estimate, exact) = cost_estimate(&cost_fixture(), 1.0);
+        assert!(
+            (estimate - exact).abs() < 0.1 * exact,
+            "{} vs {}",
+            estimate,
+            exact
+        );
+    }
+
+    #[test]
+    fn cost_matrix_negative_costs() {
+        // every assignment weighs e^4, so ratios of the unshifted energies
+        // would all exceed one
+        let cost = crate::graph::CostMatrix {
+            size: 2,
+            cost: vec![vec![-2.0; 2].into(); 2].into(),
+        };
+        let (estimate, exact) = cost_estimate(&cost, 1.0);
+        assert!((exact - 2.0 * 4f64.exp()).abs() < 1e-9 * exact);
+        assert!(
+            (estimate - exact).abs() < 1e-9 * exact,
+            "{} vs {}",
+            estimate,
+            exact
+        );
+    }
+
+    #[test]
+    fn reservoir_of_matchings() {
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("complete.json")).unwrap();
+        let config = super::Config {
+            num_of_chains: 4,
+            num_of_weight_estimations: 100,
+            ..super::Config::default()
+        };
+        let mut state = super::MCState::<crate::filter::Constant>::new(graph, config);
+        let samples = state.sample_reservoir(10);
+        assert_eq!(samples.len(), 10);
+        assert!(samples.iter().all(|x| x.len() == 5));
+    }
+
+    #[test]
+    fn initial_snapshot() {
+        let path: PathBuf = env!("PWD").into();
+        let path = path.join("data").join("cycle.json");
+        let graph = Graph::load(path).unwrap();
+        let config = super::Config {
+            num_of_chains: 3,
+            ..super::Config::default()
+        };
+        let state = super::MCState::<crate::filter::Additive>::new(graph, config);
+        let snapshot = state.snapshot();
+        assert_eq!(snapshot.beta, 0.0);
+        assert_eq!(snapshot.weight.len(), 16);
+        assert!(snapshot.weight.iter().flatten().all(|x| *x == 16.0));
+        assert_eq!(snapshot.matchings.len(), config.num_of_chains);
+        assert!(snapshot.matchings.iter().all(|x| x.len() == 16));
+        assert!(simd_json::to_string(&snapshot).is_ok());
+    }
+
+    #[test]
+    fn undersampled_steps_are_reported() {
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("complete.json")).unwrap();
+        let config = super::Config {
+            num_of_chains: 2,
+            warmup_times: 16,
+            num_of_weight_estimations: 8,
+            num_of_estimator_estimations: 1,
+            estimator_sample_intervals: 1,
+            estimator_variance_floor: 10,
+            ..super::Config::default()
+        };
+        let mut state = super::MCState::<crate::filter::Constant>::new(graph.clone(), config);
+        state.cooling_evolve([0.0, 0.1, 0.2, 0.3].into_iter(), false);
+        assert!(state.accepted_samples() <= 2);
+        assert_eq!(state.undersampled_steps(), 3);
+        // a floor the accepted samples reach flags nothing
+        let config = super::Config {
+            num_of_estimator_estimations: 8,
+            estimator_variance_floor: 4,
+            seed: Some(1),
+            ..config
+        };
+        let mut state = super::MCState::<crate::filter::Constant>::new(graph, config);
+        state.cooling_evolve([0.0, 0.1, 0.2, 0.3].into_iter(), false);
+        assert!(state.accepted_samples() >= 4);
+        assert_eq!(state.undersampled_steps(), 0);
+    }
+
+    #[test]
+    fn metrics_socket_receives_every_step() {
+        use std::io::{BufRead, BufReader};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("complete.json")).unwrap();
+        let config = super::Config {
+            num_of_chains: 2,
+            num_of_weight_estimations: 8,
+            num_of_estimator_estimations: 2,
+            ..super::Config::default()
+        };
+        let mut state = super::MCState::<crate::filter::Constant>::new(graph, config);
+        state.set_metrics_sink(crate::metrics::MetricsSink::connect(&addr).unwrap());
+        let (stream, _) = listener.accept().unwrap();
+        state.cooling_evolve([0.0, 0.1, 0.2, 0.3].into_iter(), false);
+        let sink = state.take_metrics_sink().unwrap();
+        assert_eq!(sink.dropped(), 0);
+        drop(sink);
+        let lines: Vec<String> = BufReader::new(stream).lines().map(Result::unwrap).collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains("\"step\":0"));
+    }
+
+    #[test]
+    fn ratio_above_one() {
+        let path: PathBuf = env!("PWD").into();
+        let config = super::Config {
+            num_of_chains: 4,
+            warmup_times: 256,
+            num_of_weight_estimations: 8,
+            num_of_estimator_estimations: 4,
+            estimator_sample_intervals: 4,
+            ..super::Config::default()
+        };
+        for allow_ratio_above_one in [false, true] {
+            let graph = Graph::load(path.join("data").join("cycle.json")).unwrap();
+            let config = super::Config {
+                allow_ratio_above_one,
+                ..config
+            };
+            let mut state = super::MCState::<crate::filter::Constant>::new(graph, config);
+            state.global_state.beta = 1.0;
+            state.warmup();
+            // heating instead of cooling makes every sample contribute above one
+            let (ratio, _) = state.evolve(0.0, false, 0.0);
+            assert_eq!(state.ratios_above_one(), 1);
+            if allow_ratio_above_one {
+                assert!(ratio > 1.0);
+            } else {
+                assert_eq!(ratio, 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn extreme_ratio_is_clamped() {
+        assert_eq!(super::clamp_ratio(1e6, 4.0), (4.0, true));
+        assert_eq!(super::clamp_ratio(1e-6, 4.0), (0.25, true));
+        assert_eq!(super::clamp_ratio(1.5, 4.0), (1.5, false));
+        let (nan, changed) = super::clamp_ratio(f64::NAN, 4.0);
+        assert!(nan.is_nan() && !changed);
+    }
+
+    #[test]
+    fn control_variates_cancel_correlated_noise() {
+        // the sums are 4 plus twice the controls, the counts 1 plus the controls
+        let mut sums = sums_of(&[(0.5, 3.0), (1.5, 5.0), (1.0, 4.0)]);
+        let controls = [(-0.5, -0.5), (0.5, 0.5), (0.0, 0.0)];
+        super::apply_control_variates(&mut sums, &controls);
+        for x in &sums {
+            assert!((x.0 - 1.0).abs() < 1e-12 && (x.1 - 4.0).abs() < 1e-12);
+        }
+        let mut single = sums_of(&[(0.5, 3.0)]);
+        super::apply_control_variates(&mut single, &controls[..1]);
+        assert_eq!((single[0].0, single[0].1), (0.5, 3.0));
+    }
+
+    #[test]
+    fn control_variate_reduces_error() {
+        let run = |control_variate| {
+            let config = super::Config {
+                seed: Some(3),
+                control_variate,
+                ..small_config(16, 64, 8, 8)
+            };
+            let path: PathBuf = env!("PWD").into();
+            let graph = Graph::load(path.join("data").join("4-cycles.json")).unwrap();
+            let mut state = super::MCState::<crate::filter::Additive>::new(graph, config);
+            state.warmup();
+            state.cooling_evolve([0.0, 0.5, 1.0].into_iter(), false)
+        };
+        let (plain, corrected) = (run(false), run(true));
+        assert!(corrected.value.is_finite());
+        assert!(
+            corrected.rel_std_error < plain.rel_std_error,
+            "{:?} vs {:?}",
+            corrected,
+            plain
+        );
+    }
+
+    #[test]
+    fn log_ratio_variance_across_chains() {
+        // residuals y - 2x are -1 and 1: var 2 / (2 chains * 1) / 2^2
+        let sums = sums_of(&[(1.0, 1.0), (1.0, 3.0)]);
+        assert_eq!(super::log_ratio_variance(&sums, 2.0), 0.25);
+        assert!(super::log_ratio_variance(&sums[..1], 2.0).is_infinite());
+    }
+
+    #[test]
+    fn estimate_carries_error() {
+        let run = |num_of_chains| {
+            let config = super::Config {
+                seed: Some(3),
+                ..small_config(num_of_chains, 64, 8, 8)
+            };
+            let path: PathBuf = env!("PWD").into();
+            let graph = Graph::load(path.join("data").join("box.json")).unwrap();
+            let mut state = super::MCState::<crate::filter::Constant>::new(graph, config);
+            state.warmup();
+            state.cooling_evolve([0.0, 0.5, 1.0].into_iter(), false)
+        };
+        let estimate = run(16);
+        assert_eq!(estimate.value, estimate.log_value.exp());
+        assert!(estimate.rel_std_error > 0.0 && estimate.rel_std_error.is_finite());
+        assert!(run(1).rel_std_error.is_infinite());
+    }
+
+    #[test]
+    fn odd_rotations_reach_every_parity() {
+        // rotations of 3 edges alone never change the parity of a matching
+        // and estimate box.json at about a third of its permanent
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("box.json")).unwrap();
+        let exact = crate::exact::ryser_permanent(&graph);
+        for seed in 0..4 {
+            let config = super::Config {
+                num_of_chains: 64,
+                warmup_times: 256,
+                num_of_estimator_estimations: 16,
+                move_size: 3,
+                seed: Some(seed),
+                ..super::Config::quick()
+            };
+            let mut state = super::MCState::<crate::filter::Constant>::new(graph.clone(), config);
+            state.warmup();
+            let betas = [
+                0.0, 0.5, 1.0, 1.5, 2.0, 3.0, 4.0, 6.0, 8.0, 12.0, 16.0, 24.0, 32.0,
+            ];
+            let estimate = state.cooling_evolve(betas.into_iter(), false);
+            assert!(
+                (estimate.log_value - exact.ln()).abs() < 3.0 * estimate.rel_std_error,
+                "{:?} vs {}",
+                estimate,
+                exact
+            );
+        }
+    }
+
+    #[test]
+    fn checkpoint_resumes_cooling() {
+        let config = super::Config {
+            seed: Some(13),
+            deterministic_reduction: true,
+            ..small_config(4, 32, 8, 4)
+        };
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("box.json")).unwrap();
+        let schedule = || crate::cooling_schedule::LinearSchedule {
+            start: 0.0,
+            end: 2.0,
+            steps: 5,
+        };
+        let new = || super::MCState::<crate::filter::Constant>::new(graph.clone(), config);
+        let mut straight = new();
+        straight.warmup();
+        let expected = straight.cooling_evolve(schedule(), false);
+
+        let file = std::env::temp_dir().join(format!("checkpoint-{}.json", std::process::id()));
+        let mut interrupted = new();
+        interrupted.warmup();
+        interrupted.set_checkpoint(file.clone(), NonZeroUsize::new(2).unwrap());
+        interrupted.cooling_evolve_with(schedule(), false, &mut |progress| {
+            if progress.step_index == 2 {
+                std::ops::ControlFlow::Break(())
+            } else {
+                std::ops::ControlFlow::Continue(())
+            }
+        });
+        // stopped after the third step, the checkpoint is of the second
+        assert_eq!(interrupted.global_state.beta, 1.5);
+        let mut resumed =
+            super::MCState::<crate::filter::Constant>::resume(graph.clone(), &file, config)
+                .unwrap();
+        assert_eq!(resumed.global_state.beta, 1.0);
+        let estimate = resumed.cooling_evolve(schedule(), false);
+        assert_eq!(estimate, expected);
+        assert_eq!(resumed.snapshot().matchings, straight.snapshot().matchings);
+
+        let other = super::Config {
+            num_of_chains: 2,
+            ..config
+        };
+        assert!(super::MCState::<crate::filter::Constant>::resume(graph, &file, other).is_err());
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn bennett_acceptance_ratio() {
+        use rand::SeedableRng;
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("box.json")).unwrap();
+        let exact = (partition_function(&graph, 1.0) / partition_function(&graph, 0.0)).ln();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+        let forward = gibbs_energies(&graph, 0.0, 4096, &mut rng);
+        let reverse = gibbs_energies(&graph, 1.0, 4096, &mut rng);
+        let bar = super::bar_log_ratio(&forward, &reverse, 1.0);
+        let one_sided = super::log_mean_exp(forward.iter().map(|e| -e));
+        assert!((bar - exact).abs() < 0.01, "{} vs {}", bar, exact);
+        assert!((bar - exact).abs() < (one_sided - exact).abs());
+    }
+
+    #[test]
+    fn annealing_directions_bracket_exact() {
+        use rand::SeedableRng;
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("box.json")).unwrap();
+        let exact = (partition_function(&graph, 2.0) / partition_function(&graph, 0.0)).ln();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+        let mut sample = |beta: f64| gibbs_energies(&graph, beta, 32, &mut rng);
+        // one-sided estimates are only biased on average, so average runs
+        let runs = 256;
+        let (mut forward, mut reverse, mut combined) = (0.0, 0.0, 0.0);
+        for _ in 0..runs {
+            let levels = [(1.0, sample(0.0)), (1.0, sample(1.0))];
+            let steps = super::directional_steps(&levels, &sample(2.0));
+            forward += levels
+                .iter()
+                .map(|(delta, x)| super::log_mean_exp(x.iter().map(|e| -delta * e)))
+                .sum::<f64>()
+                / runs as f64;
+            reverse += steps.iter().map(|x| x.reverse).sum::<f64>() / runs as f64;
+            combined += steps.iter().map(|x| x.combined).sum::<f64>() / runs as f64;
+        }
+        assert!(forward < exact && exact < reverse);
+        assert!((combined - exact).abs() < (forward - exact).abs());
+        assert!((combined - exact).abs() < (reverse - exact).abs());
+    }
+
+    #[test]
+    fn both_directions_on_chains() {
+        let config = super::Config {
+            seed: Some(1),
+            annealing_direction: super::AnnealingDirection::Both,
+            ..small_config(8, 64, 8, 8)
+        };
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("box.json")).unwrap();
+        let mut state = super::MCState::<crate::filter::Constant>::new(graph, config);
+        state.warmup();
+        let estimate = state.cooling_evolve([0.0, 0.5, 1.0, 2.0].into_iter(), false);
+        let directional = state.directional_estimates().unwrap();
+        assert_eq!(estimate.log_value, directional.combined);
+        assert!(directional.forward.is_finite() && directional.reverse.is_finite());
+        assert!(estimate.rel_std_error.is_finite());
+    }
+
+    #[test]
+    fn adaptive_cooling_splits_steep_steps() {
+        let config = |adaptive_band| super::Config {
+            seed: Some(2),
+            adaptive_band,
+            ..small_config(16, 64, 8, 8)
+        };
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("box.json")).unwrap();
+        let mut fixed = super::MCState::<crate::filter::Constant>::new(graph.clone(), config(None));
+        fixed.warmup();
+        fixed.cooling_evolve([0.0, 8.0].into_iter(), false);
+        assert_eq!(fixed.adaptive_retries(), 0);
+        let band = Some((0.3, 0.7));
+        let mut adaptive = super::MCState::<crate::filter::Constant>::new(graph, config(band));
+        adaptive.warmup();
+        let estimate = adaptive.cooling_evolve([0.0, 8.0].into_iter(), false);
+        assert!(adaptive.adaptive_retries() > 0);
+        assert_eq!(adaptive.global_state.beta, 8.0);
+        assert!(estimate.value.is_finite());
+    }
+
+    #[test]
+    fn adaptive_cooling_halves_unsampled_steps() {
+        let config = super::Config {
+            num_of_estimator_estimations: 0,
+            seed: Some(2),
+            adaptive_band: Some((0.3, 0.7)),
+            ..super::Config::quick()
+        };
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("box.json")).unwrap();
+        let mut state = super::MCState::<crate::filter::Constant>::new(graph, config);
+        state.warmup();
+        state.cooling_evolve([0.0, 1e-5].into_iter(), false);
+        // 1e-5 halves four times to 6.25e-7, below the smallest increment,
+        // which the placeholder ratio of one must not double again
+        assert_eq!(state.adaptive_retries(), 4);
+        assert_eq!(state.beta_steps().len(), 16);
+        assert_eq!(state.global_state.beta, 1e-5);
+    }
+
+    #[test]
+    fn warmup_stats() {
+        let config = super::Config {
+            num_of_chains: 4,
+            warmup_times: 256,
+            seed: Some(2),
+            ..super::Config::default()
+        };
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("4-cycles.json")).unwrap();
+        let mut state = super::MCState::<crate::filter::Additive>::new(graph.clone(), config);
+        let stats = state.warmup_with_stats();
+        assert!(stats.acceptance_rate > 0.0 && stats.acceptance_rate <= 1.0);
+        assert!(stats.min_weight <= stats.mean_weight && stats.mean_weight <= stats.max_weight);
+        assert!(stats.mixed, "{:?}", stats);
+        state.advance(256);
+        assert!(state.warmup_stats().turnover > stats.turnover);
+        let config = super::Config {
+            warmup_times: 0,
+            ..config
+        };
+        let mut state = super::MCState::<crate::filter::Additive>::new(graph, config);
+        let stats = state.warmup_with_stats();
+        assert_eq!(stats.turnover, 0.0);
+        assert!(!stats.mixed);
+    }
+
+    #[test]
+    fn restart_outliers() {
+        let config = super::Config {
+            num_of_chains: 16,
+            warmup_times: 64,
+            seed: Some(3),
+            restart_outliers: Some(3.0),
+            ..super::Config::default()
+        };
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("4-cycles.json")).unwrap();
+        let mut state = super::MCState::<crate::filter::Additive>::new(graph, config);
+        let stats = state.warmup_with_stats();
+        assert_eq!(stats.restarted, state.restarted_chains);
+        // a chain far out dominates the deviation, but not beyond 3 sigmas
+        // with 16 chains
+        state.chains[5].weight = 1e6;
+        assert_eq!(state.restart_outliers(3.0), 1);
+        let chain = &state.chains[5];
+        let weight = state.global_state.weight_of_match(&chain.matching);
+        assert!((chain.weight - weight).abs() < 1e-9 * weight.abs().max(1.0));
+        assert_eq!(state.restart_outliers(1e3), 0);
+    }
+
+    #[test]
+    fn progress_callback_stops_cooling() {
+        let config = super::Config {
+            seed: Some(5),
+            ..small_config(2, 16, 4, 2)
+        };
+        let mut state = super::MCState::<crate::filter::Constant>::new(Graph::complete(4), config);
+        let mut seen = Vec::new();
+        let estimate = state.cooling_evolve_with(
+            crate::cooling_schedule::LinearSchedule {
+                start: 0.0,
+                end: 1.0,
+                steps: 5,
+            },
+            false,
+            &mut |progress| {
+                seen.push(progress);
+                if progress.step_index == 1 {
+                    std::ops::ControlFlow::Break(())
+                } else {
+                    std::ops::ControlFlow::Continue(())
+                }
+            },
+        );
+        assert_eq!(seen.len(), 2);
+        assert!(seen.iter().all(|x| x.total_steps == 4));
+        assert_eq!(seen[1].beta, 0.5);
+        assert_eq!(state.global_state.beta, 0.5);
+        assert!((estimate.value - seen[1].estimator).abs() <= 1e-12 * estimate.value);
+        assert!(!estimate.truncated);
+    }
+
+    #[test]
+    fn recorded_samples_leave_run_alone() {
+        let config = super::Config {
+            seed: Some(9),
+            ..small_config(4, 16, 4, 8)
+        };
+        let graph = Graph::complete(4);
+        let run = |capacity: Option<usize>| {
+            let mut state = super::MCState::<crate::filter::Constant>::new(graph.clone(), config);
+            state.warmup();
+            if let Some(capacity) = capacity {
+                state.record_samples(capacity);
+            }
+            let estimate = state.cooling_evolve([0.5, 1.0].into_iter(), false);
+            (estimate, state.take_samples())
+        };
+        let (plain, none) = run(None);
+        let (recorded, samples) = run(Some(5));
+        assert_eq!(plain, recorded);
+        assert!(none.is_empty());
+        assert_eq!(samples.len(), 5);
+        for edges in samples {
+            let matching = crate::graph::Match {
+                edges: edges.into(),
+            };
+            assert!(matching.is_valid_in(&graph));
+        }
+    }
+
+    #[test]
+    fn time_budget_truncates_cooling() {
+        let config = super::Config {
+            seed: Some(5),
+            time_budget: Some(std::time::Duration::ZERO),
+            ..small_config(2, 16, 4, 2)
+        };
+        let mut state = super::MCState::<crate::filter::Constant>::new(Graph::complete(4), config);
+        state.warmup();
+        let estimate = state.cooling_evolve([0.5, 1.0].into_iter(), false);
+        assert!(estimate.truncated);
+        assert_eq!(state.global_state.beta, 0.0);
+        assert_eq!(estimate.log_value, super::ln_factorial(4));
+    }
+
+    #[test]
+    fn acceptance_rate_across_chains() {
+        let config = super::Config {
+            seed: Some(3),
+            ..small_config(4, 32, 8, 4)
+        };
+        let mut state = super::MCState::<crate::filter::Constant>::new(Graph::complete(4), config);
+        assert!(state.acceptance_rate().is_nan());
+        // uniform weights at beta = 0 accept every move
+        state.warmup();
+        assert_eq!(state.acceptance_rate(), 1.0);
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("box.json")).unwrap();
+        let mut state = super::MCState::<crate::filter::Constant>::new(graph, config);
+        state.warmup();
+        let mut rates = Vec::new();
+        state.cooling_evolve_with([0.0, 4.0, 8.0].into_iter(), false, &mut |progress| {
+            rates.push(progress.acceptance_rate);
+            std::ops::ControlFlow::Continue(())
+        });
+        // the second step samples at beta = 4 with learned weights
+        assert_eq!(rates[0], 1.0);
+        assert_eq!(rates[1], state.acceptance_rate());
+        assert!(rates[1] > 0.0 && rates[1] < 1.0);
+    }
+
+    #[test]
+    fn warmup_extends_until_r_hat_drops() {
+        let config = super::Config {
+            num_of_chains: 8,
+            warmup_times: 1,
+            weight_sample_intervals: 32,
+            r_hat_threshold: Some(1.1),
+            seed: Some(4),
+            ..super::Config::default()
+        };
+        let graph = Graph::complete(6);
+        let mut global_state = crate::cooling_state::State::from(&graph);
+        for i in 0..graph.size {
+            for j in 0..graph.size {
+                global_state
+                    .weight
+                    .set(i, j, 1.0 + ((i + 2 * j) % 5) as f64);
+            }
+        }
+        let mut state =
+            super::MCState::<crate::filter::Constant>::with_state(graph, global_state, config);
+        assert!(state.r_hat().is_nan());
+        state.warmup();
+        assert!(state.r_hat() < 1.1, "r_hat = {}", state.r_hat());
+        assert!(state
+            .weight_traces
+            .iter()
+            .all(|x| x.len() == super::R_HAT_SAMPLES));
+    }
+
+    #[test]
+    fn autocorrelation_warmup() {
+        let config = super::Config {
+            num_of_chains: 6,
+            warmup_times: 1024,
+            warmup_mode: super::WarmupMode::Autocorrelation,
+            seed: Some(6),
+            ..super::Config::default()
+        };
+        let graph = Graph::complete(6);
+        let mut global_state = crate::cooling_state::State::from(&graph);
+        for i in 0..graph.size {
+            for j in 0..graph.size {
+                global_state
+                    .weight
+                    .set(i, j, 1.0 + ((i + 2 * j) % 5) as f64);
+            }
+        }
+        let mut state =
+            super::MCState::<crate::filter::Constant>::with_state(graph, global_state, config);
+        assert_eq!(state.correlation_time(), None);
+        state.warmup();
+        let tau = state.correlation_time().unwrap();
+        assert!((1.0..64.0).contains(&tau), "tau = {}", tau);
+        // uniform weights leave the weight constant, one round is enough
+        let mut uniform =
+            super::MCState::<crate::filter::Constant>::new(Graph::complete(6), config);
+        uniform.warmup();
+        assert_eq!(uniform.correlation_time(), Some(1.0));
+    }
+
+    #[test]
+    fn report_takes_weight_matrix() {
+        let config = small_config(2, 16, 4, 2);
+        let mut state = super::MCState::<crate::filter::Constant>::new(Graph::complete(4), config);
+        let value = state.cooling_evolve([0.0, 1.0].into_iter(), false).value;
+        let weight = state.global_state.weight.to_dense();
+        let data = state
+            .global_state
+            .weight
+            .as_dense()
+            .unwrap()
+            .rows()
+            .next()
+            .unwrap()
+            .as_ptr();
+        let estimate = crate::estimate::Estimate {
+            value,
+            log_value: value.ln(),
+            rel_std_error: f64::INFINITY,
+            bounds: crate::estimate::Estimate::binary_bounds(4),
+            effective_sample_size: 8.0,
+            nominal_sample_size: 8,
+            acceptance_rate: 0.5,
+            r_hat: f64::NAN,
+            warmup_time: std::time::Duration::ZERO,
+            cooling_time: std::time::Duration::ZERO,
+            truncated: false,
+        };
+        let report = state.into_report(estimate);
+        assert_eq!(report.beta, 1.0);
+        assert_eq!(report.beta_steps, [1.0]);
+        let report_weight = report.weight.as_dense().unwrap();
+        assert_eq!(report_weight.checksum(), weight.checksum());
+        // moved, not copied
+        assert_eq!(report_weight.rows().next().unwrap().as_ptr(), data);
+    }
+
+    #[test]
+    fn seeded_runs_are_reproducible() {
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("4-cycles.json")).unwrap();
+        let config = super::Config {
+            seed: Some(42),
+            deterministic_reduction: true,
+            ..super::Config::quick()
+        };
+        let run = |config| {
+            let mut state = super::MCState::<crate::filter::Additive>::new(graph.clone(), config);
+            state.warmup();
+            state.log_cooling_evolve([0.0, 0.5, 1.0, 2.0, 4.0].into_iter(), false)
+        };
+        assert_eq!(run(config).to_bits(), run(config).to_bits());
+        let other = super::Config {
+            seed: Some(43),
+            ..config
+        };
+        assert_ne!(run(config).to_bits(), run(other).to_bits());
+    }
+
+    #[test]
+    fn cooling_run_matches_cooling_evolve() {
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("4-cycles.json")).unwrap();
+        let config = super::Config {
+            seed: Some(21),
+            deterministic_reduction: true,
+            ..super::Config::quick()
+        };
+        let betas = [0.5, 1.0, 2.0, 4.0];
+        let mut state = super::MCState::<crate::filter::Additive>::new(graph.clone(), config);
+        state.warmup();
+        let expected = state.cooling_evolve(betas.into_iter(), false);
+        let mut state = super::MCState::<crate::filter::Additive>::new(graph.clone(), config);
+        state.warmup();
+        let mut run = state.evolve_iter(betas.into_iter(), false);
+        let steps: Vec<_> = run.by_ref().collect();
+        assert_eq!(steps.iter().map(|x| x.beta).collect::<Vec<_>>(), betas);
+        assert_eq!(steps.last().unwrap().estimator, expected.value);
+        assert_eq!(
+            run.finish().log_value.to_bits(),
+            expected.log_value.to_bits()
+        );
+        // stopping early keeps the estimate of the steps taken
+        let mut state = super::MCState::<crate::filter::Additive>::new(graph.clone(), config);
+        state.warmup();
+        let mut run = state.evolve_iter(betas.into_iter(), false);
+        let first = run.next().unwrap();
+        assert_eq!(run.finish().value, first.estimator);
+        assert_eq!(state.beta_steps(), [0.5]);
+        // an unbounded schedule is only pulled as far as the cooling goes
+        let mut state = super::MCState::<crate::filter::Additive>::new(graph, config);
+        state.warmup();
+        let mut run = state.evolve_iter((1..).map(|k| k as f64 / 4.0), false);
+        let steps: Vec<_> = run.by_ref().take(3).collect();
+        assert!(steps.iter().all(|x| x.total_steps == usize::MAX));
+        assert!(run.finish().value.is_finite());
+        assert_eq!(state.beta_steps(), [0.25, 0.5, 0.75]);
+    }
+
+    #[test]
+    fn initial_matchings() {
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("box.json")).unwrap();
+        let config = super::Config {
+            num_of_chains: 3,
+            ..super::Config::default()
+        };
+        let matching = graph.find_match();
+        let state = super::MCState::<crate::filter::Additive>::with_initial_matchings(
+            graph.clone(),
+            config,
+            vec![matching.clone()],
+        )
+        .unwrap();
+        for chain in state.chains.iter() {
+            assert_eq!(chain.matching.edges, matching.edges);
+            assert_eq!(chain.energy, 0.0);
+        }
+        let short = crate::graph::Match {
+            edges: matching.edges[1..].into(),
+        };
+        let error = super::MCState::<crate::filter::Additive>::with_initial_matchings(
+            graph.clone(),
+            config,
+            vec![matching, short],
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "initial matching 1: 6 edges, the graph has 7 vertices per side"
+        );
+        assert!(
+            super::MCState::<crate::filter::Additive>::with_initial_matchings(
+                graph,
+                config,
+                Vec::new()
+            )
+            .is_err()
+        );
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn reduction_ignores_thread_count() {
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("4-cycles.json")).unwrap();
+        let config = super::Config::builder()
+            .num_of_chains(8)
+            .warmup_times(64)
+            .weight_sample_intervals(4)
+            .num_of_weight_estimations(16)
+            .estimator_sample_intervals(4)
+            .num_of_estimator_estimations(4)
+            .seed(Some(42))
+            .deterministic_reduction(true)
+            .build()
+            .unwrap();
+        let run = |threads| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            pool.install(|| {
+                let mut state =
+                    super::MCState::<crate::filter::Additive>::new(graph.clone(), config);
+                state.warmup();
+                state.log_cooling_evolve([0.0, 0.5, 1.0, 2.0].into_iter(), false)
+            })
+        };
+        assert_eq!(run(1).to_bits(), run(3).to_bits());
+    }
+
+    #[test]
+    fn log_estimator_of_large_complete_graph() {
+        let config = small_config(2, 16, 4, 2);
+        let mut state = super::MCState::<crate::filter::Constant>::new(Graph::complete(30), config);
+        // 30! overflows usize, its logarithm is 74.658...
+        let log_estimate = state.log_cooling_evolve([0.0, 0.5, 1.0, 2.0].into_iter(), false);
+        assert!(
+            (log_estimate - 74.65823634883016).abs() < 1e-9,
+            "{}",
+            log_estimate
+        );
+        assert!((super::ln_factorial(30) - log_estimate).abs() < 1e-12);
+    }
+
+    #[test]
+    fn complete_graph_mixes_fast() {
+        let path: PathBuf = env!("PWD").into();
+        let graph = Graph::load(path.join("data").join("complete.json")).unwrap();
+        let config = super::Config {
+            num_of_chains: 1,
+            warmup_times: 256,
+            ..super::Config::default()
+        };
+        let mut state = super::MCState::<crate::filter::Constant>::new(graph, config);
+        for i in 0..state.size {
+            for j in 0..state.size {
+                state
+                    .global_state
+                    .weight
+                    .set(i, j, 1.0 + ((i + 2 * j) % 5) as f64);
+            }
+        }
+        let profile = state.profile_mixing(20000, 32);
+        assert_eq!(profile.weight_acf.len(), 33);
+        assert_eq!(profile.weight_acf[0], 1.0);
+        assert!(
+            profile.weight_acf[16].abs() < 0.1,
+            "{:?}",
+            profile.weight_acf
+        );
+        assert!(profile.weight_tau < 16.0, "tau = {}", profile.weight_tau);
+        // every matching of the complete graph is perfect
+        assert_eq!(profile.active_count_tau, 1.0);
+    }
+
+    #[test]
+    fn thinned_trajectory() {
+        let path: PathBuf = env!("PWD").into();
+        let path = path.join("data").join("complete.json");
+        let graph = Graph::load(path).unwrap();
+        let config = super::Config {
+            num_of_chains: 4,
+            warmup_times: 1000,
+            ..super::Config::default()
+        };
+        let mut state = super::MCState::<crate::filter::Constant>::new(graph, config);
+        assert!(state.trajectory().is_none());
+        assert!(state
+            .record_trajectory(4, NonZeroUsize::new(7).unwrap())
+            .is_err());
+        state
+            .record_trajectory(2, NonZeroUsize::new(7).unwrap())
+            .unwrap();
+        state.warmup();
+        let trajectory = state.trajectory().unwrap();
+        assert_eq!(trajectory.len(), 1000 / 7);
+        for (i, point) in trajectory.iter().enumerate() {
+            assert_eq!(point.step, 7 * (i + 1));
+            assert_eq!(point.active_count, 5);
         }
     }
 }
@@ -0,0 +1,93 @@
+use serde::Serialize;
+use std::{
+    io::Write,
+    net::TcpStream,
+    sync::mpsc::{sync_channel, SyncSender, TrySendError},
+    thread::JoinHandle,
+};
+use tracing::warn;
+
+/// Metrics of a single cooling step.
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct StepMetrics {
+    pub step: usize,
+    pub beta: f64,
+    pub next_beta: f64,
+    pub ratio: f64,
+    pub estimator: f64,
+    pub accepted_samples: usize,
+    pub acceptance_rate: f64,
+}
+
+/// Streams JSON lines to a TCP (`host:port`) or Unix (`unix:/path`) socket.
+///
+/// Messages are handed to a background writer through a bounded queue; when
+/// the consumer falls behind and the queue is full, new messages are dropped
+/// instead of stalling the sampler.
+pub struct MetricsSink {
+    sender: Option<SyncSender<String>>,
+    writer: Option<JoinHandle<()>>,
+    dropped: usize,
+}
+
+impl MetricsSink {
+    const QUEUE_SIZE: usize = 1024;
+
+    pub fn connect(addr: &str) -> anyhow::Result<Self> {
+        let stream: Box<dyn Write + Send> = match addr.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(path) => Box::new(std::os::unix::net::UnixStream::connect(path)?),
+            #[cfg(not(unix))]
+            Some(_) => anyhow::bail!("unix sockets are not supported on this platform"),
+            None => Box::new(TcpStream::connect(addr)?),
+        };
+        Ok(Self::from_writer(stream))
+    }
+
+    pub fn from_writer(mut stream: Box<dyn Write + Send>) -> Self {
+        let (sender, receiver) = sync_channel::<String>(Self::QUEUE_SIZE);
+        let writer = std::thread::spawn(move || {
+            for line in receiver {
+                if let Err(e) = stream.write_all(line.as_bytes()) {
+                    warn!("metrics consumer disconnected: {}", e);
+                    return;
+                }
+            }
+            let _ = stream.flush();
+        });
+        MetricsSink {
+            sender: Some(sender),
+            writer: Some(writer),
+            dropped: 0,
+        }
+    }
+
+    pub fn send<T: Serialize>(&mut self, metrics: &T) {
+        let Ok(mut line) = simd_json::to_string(metrics) else {
+            return;
+        };
+        line.push('\n');
+        if let Some(sender) = &self.sender {
+            match sender.try_send(line) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                    self.dropped += 1
+                }
+            }
+        }
+    }
+
+    /// Number of messages dropped because the consumer was too slow or gone.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+impl Drop for MetricsSink {
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
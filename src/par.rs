@@ -0,0 +1,111 @@
+//! Parallel iterators over the chains and matrix rows: rayon's with the
+//! `parallel` feature, otherwise sequential stand-ins with the same method
+//! names, so that call sites read the same in both builds.
+
+#[cfg(feature = "parallel")]
+pub use rayon::{
+    iter::{
+        IndexedParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator,
+        ParallelIterator,
+    },
+    slice::{ChunksMut, ParallelSlice, ParallelSliceMut},
+};
+
+#[cfg(not(feature = "parallel"))]
+pub use std::slice::ChunksMut;
+
+#[cfg(not(feature = "parallel"))]
+pub trait IntoParallelRefIterator<'a> {
+    type Iter: Iterator;
+    fn par_iter(&'a self) -> Self::Iter;
+}
+
+#[cfg(not(feature = "parallel"))]
+impl<'a, T: 'a> IntoParallelRefIterator<'a> for [T] {
+    type Iter = std::slice::Iter<'a, T>;
+    fn par_iter(&'a self) -> Self::Iter {
+        self.iter()
+    }
+}
+
+#[cfg(not(feature = "parallel"))]
+pub trait IntoParallelRefMutIterator<'a> {
+    type Iter: Iterator;
+    fn par_iter_mut(&'a mut self) -> Self::Iter;
+}
+
+#[cfg(not(feature = "parallel"))]
+impl<'a, T: 'a> IntoParallelRefMutIterator<'a> for [T] {
+    type Iter = std::slice::IterMut<'a, T>;
+    fn par_iter_mut(&'a mut self) -> Self::Iter {
+        self.iter_mut()
+    }
+}
+
+#[cfg(not(feature = "parallel"))]
+pub trait ParallelSlice<T> {
+    fn par_chunks(&self, size: usize) -> std::slice::Chunks<'_, T>;
+}
+
+#[cfg(not(feature = "parallel"))]
+impl<T> ParallelSlice<T> for [T] {
+    fn par_chunks(&self, size: usize) -> std::slice::Chunks<'_, T> {
+        self.chunks(size)
+    }
+}
+
+#[cfg(not(feature = "parallel"))]
+pub trait ParallelSliceMut<T> {
+    fn par_chunks_mut(&mut self, size: usize) -> ChunksMut<'_, T>;
+}
+
+#[cfg(not(feature = "parallel"))]
+impl<T> ParallelSliceMut<T> for [T] {
+    fn par_chunks_mut(&mut self, size: usize) -> ChunksMut<'_, T> {
+        self.chunks_mut(size)
+    }
+}
+
+/// Fold `items` with the associative `op` in whatever order the threads
+/// pick, or from left to right without the `parallel` feature.
+#[cfg(feature = "parallel")]
+pub fn reduce<T: Copy + Send + Sync>(
+    items: &[T],
+    identity: T,
+    op: impl Fn(T, T) -> T + Send + Sync,
+) -> T {
+    items.par_iter().copied().reduce(|| identity, op)
+}
+
+/// Fold `items` with the associative `op` in whatever order the threads
+/// pick, or from left to right without the `parallel` feature.
+#[cfg(not(feature = "parallel"))]
+pub fn reduce<T: Copy>(items: &[T], identity: T, op: impl Fn(T, T) -> T) -> T {
+    items.iter().copied().fold(identity, op)
+}
+
+/// Fold `items` into one accumulator per worker, each started from `init`,
+/// and `merge` the accumulators, or fold them all into a single accumulator
+/// without the `parallel` feature.
+#[cfg(feature = "parallel")]
+pub fn fold_reduce<I: ParallelIterator, A: Send>(
+    items: I,
+    init: impl Fn() -> A + Send + Sync,
+    fold: impl Fn(A, I::Item) -> A + Send + Sync,
+    merge: impl Fn(A, A) -> A + Send + Sync,
+) -> A {
+    items.fold(&init, fold).reduce(&init, merge)
+}
+
+/// Fold `items` into one accumulator per worker, each started from `init`,
+/// and `merge` the accumulators, or fold them all into a single accumulator
+/// without the `parallel` feature.
+#[cfg(not(feature = "parallel"))]
+pub fn fold_reduce<I: Iterator, A>(
+    items: I,
+    init: impl Fn() -> A,
+    fold: impl Fn(A, I::Item) -> A,
+    _merge: impl Fn(A, A) -> A,
+) -> A {
+    items.fold(init(), fold)
+}
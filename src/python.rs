@@ -0,0 +1,68 @@
+//! Python bindings, built into the `permanent_rs` extension module by maturin
+//! (see `pyproject.toml`).
+
+use numpy::PyReadonlyArray2;
+use pyo3::{exceptions::PyValueError, prelude::*, types::PyDict};
+
+use crate::{exact, graph::Graph, markov_chain::Config, Filter};
+
+/// Estimate the permanent of the square nonnegative `matrix`, returning a dict
+/// with the `estimate`, its logarithm `log_estimate` and `rel_std_error`.
+#[pyfunction]
+#[pyo3(signature = (matrix, chains = 2048, seed = None, filter = "additive"))]
+fn estimate<'py>(
+    py: Python<'py>,
+    matrix: PyReadonlyArray2<'py, f64>,
+    chains: usize,
+    seed: Option<u64>,
+    filter: &str,
+) -> PyResult<Bound<'py, PyDict>> {
+    let filter = filter.parse::<Filter>().map_err(PyValueError::new_err)?;
+    let graph = Graph::from_ndarray(matrix.as_array())
+        .map_err(|e| PyValueError::new_err(format!("{:#}", e)))?;
+    graph
+        .find_perfect_match()
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    // a seeded call gives the same estimate whatever the thread count
+    let config = Config {
+        num_of_chains: chains,
+        seed,
+        deterministic_reduction: seed.is_some(),
+        ..Config::default()
+    };
+    let estimate = py.detach(|| crate::estimate_permanent_with_error(&graph, &config, filter));
+    let result = PyDict::new(py);
+    result.set_item("estimate", estimate.value)?;
+    result.set_item("log_estimate", estimate.log_value)?;
+    result.set_item("rel_std_error", estimate.rel_std_error)?;
+    Ok(result)
+}
+
+/// Exact permanent of the square `matrix` by Ryser's formula, in time
+/// exponential in its size.
+#[pyfunction]
+fn permanent_ryser(py: Python<'_>, matrix: PyReadonlyArray2<'_, f64>) -> PyResult<f64> {
+    let matrix = matrix.as_array();
+    let (n, columns) = matrix.dim();
+    if n != columns {
+        return Err(PyValueError::new_err(format!(
+            "matrix of shape {}x{} is not square",
+            n, columns
+        )));
+    }
+    if n > exact::MAX_RYSER_DIMENSION {
+        return Err(PyValueError::new_err(format!(
+            "Ryser's formula is limited to {0}x{0} matrices",
+            exact::MAX_RYSER_DIMENSION
+        )));
+    }
+    let data: Vec<f64> = matrix.iter().copied().collect();
+    Ok(py.detach(|| exact::permanent_ryser(&data, n)))
+}
+
+#[pymodule]
+fn permanent_rs(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(estimate, module)?)?;
+    module.add_function(wrap_pyfunction!(permanent_ryser, module)?)?;
+    Ok(())
+}
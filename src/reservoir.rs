@@ -0,0 +1,161 @@
+use rand::{seq::SliceRandom, Rng};
+
+/// Fixed-size uniform subsample of a stream (Vitter's algorithm R).
+///
+/// Every item pushed so far is retained with equal probability, so the
+/// reservoir is uniform over the produced (possibly thinned and correlated)
+/// items; it does not make them independent of each other.
+pub struct Reservoir<T> {
+    capacity: usize,
+    seen: usize,
+    items: Vec<T>,
+}
+
+impl<T> Reservoir<T> {
+    pub fn new(capacity: usize) -> Self {
+        Reservoir {
+            capacity,
+            seen: 0,
+            items: Vec::with_capacity(capacity),
+        }
+    }
+    pub fn push<R: Rng + ?Sized>(&mut self, item: T, rng: &mut R) {
+        self.seen += 1;
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+        } else {
+            let index = rng.gen_range(0..self.seen);
+            if index < self.capacity {
+                self.items[index] = item;
+            }
+        }
+    }
+    /// Most items the reservoir keeps.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    /// Number of items pushed so far.
+    pub fn seen(&self) -> usize {
+        self.seen
+    }
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+    pub fn into_items(self) -> Vec<T> {
+        self.items
+    }
+    /// Uniform subsample of at most `capacity` items of the union of the
+    /// streams behind `reservoirs`, each of which must hold at least
+    /// `capacity` items unless it saw fewer.
+    pub fn merge<R: Rng + ?Sized>(
+        reservoirs: Vec<Reservoir<T>>,
+        capacity: usize,
+        rng: &mut R,
+    ) -> Self {
+        let seen = reservoirs.iter().map(|x| x.seen).sum();
+        let mut remaining: Vec<usize> = reservoirs.iter().map(|x| x.seen).collect();
+        let mut pools: Vec<Vec<T>> = reservoirs
+            .into_iter()
+            .map(|x| {
+                let mut items = x.items;
+                items.shuffle(rng);
+                items
+            })
+            .collect();
+        let mut items = Vec::with_capacity(capacity.min(seen));
+        for left in (seen - capacity.min(seen) + 1..=seen).rev() {
+            // drawing without replacement from the union, the next item comes
+            // from each stream in proportion to its items not drawn yet
+            let mut pick = rng.gen_range(0..left);
+            let stream = remaining
+                .iter()
+                .position(|x| match pick.checked_sub(*x) {
+                    Some(rest) => {
+                        pick = rest;
+                        false
+                    }
+                    None => true,
+                })
+                .expect("pick is below the remaining total");
+            remaining[stream] -= 1;
+            items.push(
+                pools[stream]
+                    .pop()
+                    .expect("reservoir is too small to merge"),
+            );
+        }
+        Reservoir {
+            capacity,
+            seen,
+            items,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Reservoir;
+
+    #[test]
+    fn bounded_size() {
+        let mut rng = rand::thread_rng();
+        let mut reservoir = Reservoir::new(16);
+        for i in 0..10 {
+            reservoir.push(i, &mut rng);
+        }
+        assert_eq!(reservoir.items(), (0..10).collect::<Vec<_>>());
+        for i in 10..100_000 {
+            reservoir.push(i, &mut rng);
+            assert!(reservoir.items().len() <= 16);
+        }
+        assert_eq!(reservoir.items().len(), 16);
+        assert_eq!(reservoir.seen(), 100_000);
+    }
+
+    #[test]
+    fn merge_is_uniform() {
+        let mut rng = rand::thread_rng();
+        let mut hits = [0usize; 12];
+        for _ in 0..20_000 {
+            let mut first = Reservoir::new(3);
+            for i in 0..10 {
+                first.push(i, &mut rng);
+            }
+            let mut second = Reservoir::new(3);
+            for i in 10..12 {
+                second.push(i, &mut rng);
+            }
+            let merged = Reservoir::merge(vec![first, second], 3, &mut rng);
+            assert_eq!(merged.seen(), 12);
+            assert_eq!(merged.items().len(), 3);
+            for i in merged.into_items() {
+                hits[i] += 1;
+            }
+        }
+        // each item is kept with probability 3 / 12
+        for count in hits {
+            assert!((4_400..5_600).contains(&count), "{:?}", hits);
+        }
+        let merged = Reservoir::merge(vec![Reservoir::<u8>::new(3)], 3, &mut rng);
+        assert!(merged.items().is_empty());
+    }
+
+    #[test]
+    fn uniform_inclusion() {
+        let mut rng = rand::thread_rng();
+        let mut hits = [0usize; 10];
+        for _ in 0..20_000 {
+            let mut reservoir = Reservoir::new(2);
+            for i in 0..10 {
+                reservoir.push(i, &mut rng);
+            }
+            for i in reservoir.into_items() {
+                hits[i] += 1;
+            }
+        }
+        // each item is kept with probability 2 / 10
+        for count in hits {
+            assert!((3_400..4_600).contains(&count), "{:?}", hits);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use crate::{
+    estimate::estimate_once, exact::ryser_permanent, filter::Constant, graph::Graph,
+    markov_chain::Config,
+};
+use std::num::NonZeroUsize;
+use tracing::info;
+
+/// Graph fixtures bundled into the binary.
+const FIXTURES: &[(&str, &str)] = &[
+    ("2-cycles.json", include_str!("../data/2-cycles.json")),
+    ("4-cycles.json", include_str!("../data/4-cycles.json")),
+    ("box.json", include_str!("../data/box.json")),
+    ("choice.json", include_str!("../data/choice.json")),
+    ("complete.json", include_str!("../data/complete.json")),
+    ("cycle.json", include_str!("../data/cycle.json")),
+    ("reverse.json", include_str!("../data/reverse.json")),
+];
+
+/// Seed of every estimate, which makes the self-test deterministic for a
+/// fixed number of threads.
+const SEED: u64 = 0x5e1f_7e57;
+
+/// Largest fixture that is estimated and compared with its exact permanent;
+/// larger ones only go through loading and matching.
+const MAX_ESTIMATE_SIZE: usize = 8;
+
+/// Largest accepted factor between the estimate and the exact permanent. The
+/// runs are short, so this only catches a broken pipeline.
+const TOLERANCE: f64 = 4.0;
+
+fn load_fixture(json: &str) -> anyhow::Result<Graph> {
+    let graph = Graph::parse(json)?;
+    graph.check_feasible()?;
+    graph.find_perfect_match()?;
+    Ok(graph)
+}
+
+fn check_fixture(name: &str, json: &str) -> anyhow::Result<()> {
+    let graph = load_fixture(json)?;
+    if graph.size > MAX_ESTIMATE_SIZE {
+        info!("{}: perfect matching found, too large to estimate", name);
+        return Ok(());
+    }
+    let exact = ryser_permanent(&graph);
+    let config = Config {
+        num_of_chains: 32,
+        warmup_times: 2048,
+        weight_sample_intervals: 8,
+        num_of_weight_estimations: 32,
+        estimator_sample_intervals: 8,
+        num_of_estimator_estimations: 8,
+        seed: Some(SEED),
+        ..Config::default()
+    };
+    let slow_down = NonZeroUsize::new(8).unwrap();
+    let estimate = estimate_once::<Constant>(graph, config, slow_down, slow_down);
+    info!("{}: exact {}, estimate {:.5}", name, exact, estimate);
+    if !(estimate.is_finite() && (estimate / exact).ln().abs() <= TOLERANCE.ln()) {
+        anyhow::bail!(
+            "estimate {} is off from the exact value {}",
+            estimate,
+            exact
+        );
+    }
+    Ok(())
+}
+
+/// Run the whole pipeline on every bundled fixture, returning the name of
+/// each fixture with why it failed, if it did.
+pub fn run() -> Vec<(&'static str, anyhow::Result<()>)> {
+    FIXTURES
+        .iter()
+        .map(|(name, json)| (*name, check_fixture(name, json)))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn fixtures_have_perfect_matchings() {
+        for (name, json) in super::FIXTURES {
+            assert!(super::load_fixture(json).is_ok(), "{}", name);
+        }
+        assert!(super::load_fixture(r#"{"size": 2, "edges": [[0], [0]]}"#).is_err());
+    }
+
+    #[test]
+    fn seeded_fixture_estimates() {
+        let fixture = |name| super::FIXTURES.iter().find(|x| x.0 == name).unwrap().1;
+        // K_5 is estimated, the 16-cycle is only matched
+        for name in ["complete.json", "cycle.json"] {
+            let result = super::check_fixture(name, fixture(name));
+            assert!(result.is_ok(), "{}: {:?}", name, result);
+        }
+    }
+}